@@ -0,0 +1,86 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use lignum_draw::file_format::{canvas_from_json, canvas_to_json, CanvasData, DocumentMeta, InkSample, LayerData, StrokeData, FORMAT_VERSION};
+use lignum_draw::layer::LayerProps;
+use lignum_draw::lignum_format::{canvas_from_lignum, canvas_to_lignum};
+use lignum_draw::page::PageLayout;
+use lignum_draw::stroke_style::StrokeStyle;
+use lignum_draw::ToolMode;
+
+
+/// A helper to create a document with a few hours worth of smoothed strokes
+fn setup_document() -> CanvasData {
+    let strokes = (0..2_000).map(|s| {
+        let origin = (s as f32 * 37.0) % 595.0;
+        let points: Vec<([f32;2], f32)> = (0..400).map(|i| {
+            // smoothed strokes produce arbitrary floats, no nice round numbers
+            let t = i as f32 * 0.173;
+            let x = origin + t * 3.1 + t.sin() * 4.7;
+            let y = s as f32 * 11.3 + t.cos() * 6.9;
+            ([x, y], 1.5 + (t * 0.37).sin())
+        }).collect();
+        // the raw input is kept next to the smoothed curve, roughly one sample per 10 points
+        let samples = points.iter().step_by(10).enumerate().map(|(i, (pos, width))| InkSample {
+            pos: *pos,
+            width: *width,
+            pressure: width / 2.5,
+            tilt: [(i as f32 * 0.3).sin() * 40.0, 20.0],
+            time: i as f32 * 4.0,
+        }).collect();
+        StrokeData { points, style: StrokeStyle::default(), samples, created: None }
+    }).collect();
+
+    CanvasData {
+        format_version: FORMAT_VERSION,
+        meta: DocumentMeta::default(),
+        layers: vec![LayerData { id: 0, props: LayerProps::named("Layer 1"), strokes }],
+        active_layer: 0,
+        pages: PageLayout::Infinite,
+        images: Vec::new(),
+        texts: Vec::new(),
+        pdf: None,
+        tool_mode: ToolMode::Pen,
+        offset: [0.0, 0.0],
+        zoom: 1.0,
+    }
+}
+
+fn bench_load(c: &mut Criterion) {
+    let mut data = setup_document();
+    let json = canvas_to_json(&data).unwrap();
+    let lignum = canvas_to_lignum(&mut data).unwrap();
+
+    let mut group = c.benchmark_group("load");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(json.len() as u64));
+    group.bench_function("json", |b| {
+        b.iter(|| canvas_from_json(black_box(&json)).unwrap());
+    });
+    group.throughput(Throughput::Bytes(lignum.len() as u64));
+    group.bench_function("lignum", |b| {
+        b.iter(|| canvas_from_lignum(black_box(&lignum)).unwrap());
+    });
+    group.finish();
+}
+
+fn bench_save(c: &mut Criterion) {
+    let mut data = setup_document();
+
+    let mut group = c.benchmark_group("save");
+    group.sample_size(10);
+    group.bench_function("json", |b| {
+        b.iter(|| canvas_to_json(black_box(&data)).unwrap());
+    });
+    group.bench_function("lignum", |b| {
+        b.iter(|| canvas_to_lignum(black_box(&mut data)).unwrap());
+    });
+    group.finish();
+}
+
+
+criterion_group!(
+    name = benches;
+    config = Criterion::default();
+    targets = bench_load, bench_save
+);
+
+criterion_main!(benches);
@@ -1,5 +1,5 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use macroquad::{color::{Color, BLACK}, color_u8, math::{vec2, Vec2, Vec3}, models::Mesh, ui::Vertex};
+use macroquad::{color::{Color, BLACK}, math::{vec2, Vec2, Vec3}, models::Mesh, ui::Vertex};
 
 
 /// A helper to create some dummy data for transform_mesh_absolute
@@ -248,8 +248,8 @@ fn build_stroke_mesh_chunk(
         // draw cap at the final segment end
         let end_left_i  = 2 * (n - 1);
         let end_right_i = 2 * (n - 1) + 1;
-        let end_left  = vertices[end_left_i as usize].position.truncate();
-        let end_right = vertices[end_right_i as usize].position.truncate();
+        let end_left  = vertices[end_left_i].position.truncate();
+        let end_right = vertices[end_right_i].position.truncate();
         draw_cap(&mut vertices, &mut indices, end_left, end_right, c, normal);
     }
 
@@ -0,0 +1,138 @@
+use macroquad::prelude::*;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fs;
+use std::path::Path;
+
+use crate::raster::Image;
+
+// Pictures placed on the canvas. The png file is embedded in the document
+// (base64 in json), so documents stay self-contained. Images are drawn
+// below all layers, with the same offset and zoom as strokes.
+
+pub type ImageId = u32;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+/// Screen pixels around a corner that grab the resize handle.
+pub const HANDLE_SIZE: f32 = 10.0;
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct CanvasImage {
+    pub id: ImageId,
+    pub rect: [f32;4], // x, y, width, height in world coords
+    #[serde(serialize_with = "to_base64", deserialize_with = "from_base64")]
+    pub png: Vec<u8>,
+}
+
+impl CanvasImage {
+    pub fn rect(&self) -> Rect {
+        Rect::new(self.rect[0], self.rect[1], self.rect[2], self.rect[3])
+    }
+
+    pub fn set_rect(&mut self, r: Rect) {
+        self.rect = [r.x, r.y, r.w, r.h];
+    }
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn base64_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Decodes standard base64, whitespace is ignored.
+pub fn base64_decode(text: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    let (mut acc, mut bits) = (0u32, 0);
+    for c in text.bytes().filter(|c| !c.is_ascii_whitespace()) {
+        if c == b'=' {
+            break;
+        }
+        let v = BASE64.iter().position(|b| *b == c).ok_or_else(|| format!("invalid base64 character '{}'", c as char))?;
+        acc = acc << 6 | v as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Ok(out)
+}
+
+pub fn to_base64<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&base64_encode(bytes))
+}
+
+pub fn from_base64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    base64_decode(&text).map_err(serde::de::Error::custom)
+}
+
+/// Decodes a png into straight rgba8, whatever its color type.
+pub fn decode_png(bytes: &[u8]) -> Result<Image, String> {
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(|e| e.to_string())?;
+    let data = &buf[..info.buffer_size()];
+    let pixels = match info.color_type {
+        png::ColorType::Rgba => data.to_vec(),
+        png::ColorType::Rgb => data.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+        png::ColorType::GrayscaleAlpha => data.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        png::ColorType::Grayscale => data.iter().flat_map(|g| [*g, *g, *g, 255]).collect(),
+        png::ColorType::Indexed => return Err("unexpanded palette image".to_string()),
+    };
+    Ok(Image { width: info.width, height: info.height, pixels })
+}
+
+/// Reads an image file to embed, only png is supported.
+pub fn load_png(path: &Path) -> Result<(Vec<u8>, Image), String> {
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
+    if !bytes.starts_with(PNG_SIGNATURE) {
+        return Err("only png images can be embedded".to_string());
+    }
+    let image = decode_png(&bytes)?;
+    Ok((bytes, image))
+}
+
+/// World rectangle for a new image centered in `view`: one world unit per
+/// pixel, shrunk to fit into most of the view.
+pub fn placement(width: u32, height: u32, view: Rect) -> Rect {
+    let (w, h) = (width.max(1) as f32, height.max(1) as f32);
+    let scale = (view.w * 0.8 / w).min(view.h * 0.8 / h).min(1.0);
+    let (w, h) = (w * scale, h * scale);
+    let center = view.center();
+    Rect::new(center.x - w * 0.5, center.y - h * 0.5, w, h)
+}
+
+/// Topmost image under a world position.
+pub fn image_at(images: &[CanvasImage], pos: Vec2) -> Option<usize> {
+    images.iter().rposition(|img| img.rect().contains(pos))
+}
+
+/// Whether `pos` grabs the bottom right resize handle of `rect`.
+pub fn on_handle(rect: Rect, pos: Vec2, zoom: f32) -> bool {
+    let corner = vec2(rect.x + rect.w, rect.y + rect.h);
+    let reach = HANDLE_SIZE / zoom;
+    (pos - corner).abs().max_element() <= reach
+}
+
+/// `start` resized by dragging its bottom right corner to `pos`, keeping
+/// the aspect ratio.
+pub fn resized(start: Rect, pos: Vec2, min_size: f32) -> Rect {
+    let scale = ((pos.x - start.x) / start.w).max((pos.y - start.y) / start.h);
+    let scale = scale.max(min_size / start.w.min(start.h).max(f32::EPSILON));
+    Rect::new(start.x, start.y, start.w * scale, start.h * scale)
+}
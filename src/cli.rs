@@ -0,0 +1,158 @@
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: lignum_draw [OPTIONS] [DOCUMENT]
+
+Opens DOCUMENT (.lignum or .json, or imports .rnote, .xopp and .inkml), or
+starts with an empty canvas.
+A DOCUMENT that doesn't exist yet is created on the first save.
+With --export, DOCUMENT is converted without opening a window.
+
+Options:
+  --device <PATH>    evdev node of the stylus, overrides the config file
+  --no-stylus        don't read a stylus, draw with the left mouse button
+  --config <PATH>    read settings from PATH instead of the default config file
+  --export <FILE>    write DOCUMENT to FILE and exit, the format follows the
+                     extension (.pdf, .svg, .png, .xopp, .inkml)
+  --annotated        with --export to a .pdf, write the imported pdf with
+                     the ink drawn over its pages
+  --dpi <N>          resolution of .png exports, default 96
+  --region <X,Y,W,H> canvas area of .png exports, default all pages with ink
+  -h, --help         print this help";
+
+#[derive(Default)]
+pub struct Args {
+    pub document: Option<PathBuf>,
+    pub device: Option<String>,
+    pub no_stylus: bool,
+    pub config: Option<PathBuf>,
+    pub export: Option<PathBuf>,
+    pub annotated: bool,
+    pub dpi: Option<f32>,
+    pub region: Option<[f32; 4]>,
+    pub help: bool,
+}
+
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args::default();
+    let mut args = args.into_iter();
+    let mut only_positional = false;
+
+    while let Some(arg) = args.next() {
+        if only_positional || !arg.starts_with('-') || arg == "-" {
+            if parsed.document.is_some() {
+                return Err(format!("unexpected argument '{}', only one document can be opened", arg));
+            }
+            parsed.document = Some(PathBuf::from(arg));
+            continue;
+        }
+
+        // accept both `--flag value` and `--flag=value`
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+            None => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline_value.clone().or_else(|| args.next()).ok_or_else(|| format!("{} needs a value", flag))
+        };
+
+        let is_switch = matches!(flag.as_str(), "--" | "-h" | "--help" | "--no-stylus" | "--annotated");
+        if is_switch && inline_value.is_some() {
+            return Err(format!("{} doesn't take a value", flag));
+        }
+
+        match flag.as_str() {
+            "--" => only_positional = true,
+            "-h" | "--help" => parsed.help = true,
+            "--no-stylus" => parsed.no_stylus = true,
+            "--annotated" => parsed.annotated = true,
+            "--device" => parsed.device = Some(value()?),
+            "--config" => parsed.config = Some(PathBuf::from(value()?)),
+            "--export" => parsed.export = Some(PathBuf::from(value()?)),
+            "--dpi" => parsed.dpi = Some(parse_dpi(&value()?)?),
+            "--region" => parsed.region = Some(parse_region(&value()?)?),
+            _ => return Err(format!("unknown option '{}'", arg)),
+        }
+    }
+
+    if parsed.no_stylus && parsed.device.is_some() {
+        return Err("--device and --no-stylus can't be combined".to_string());
+    }
+    if parsed.export.is_some() && parsed.document.is_none() {
+        return Err("--export needs a DOCUMENT to convert".to_string());
+    }
+    if (parsed.dpi.is_some() || parsed.region.is_some()) && parsed.export.is_none() {
+        return Err("--dpi and --region only apply to --export".to_string());
+    }
+    if parsed.annotated && parsed.export.is_none() {
+        return Err("--annotated only applies to --export".to_string());
+    }
+    Ok(parsed)
+}
+
+fn parse_dpi(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(dpi) if dpi.is_finite() && dpi > 0.0 => Ok(dpi),
+        _ => Err(format!("--dpi expects a positive number, got '{}'", value)),
+    }
+}
+
+fn parse_region(value: &str) -> Result<[f32; 4], String> {
+    let invalid = || format!("--region expects X,Y,W,H with a positive width and height, got '{}'", value);
+    let parts: Vec<f32> = value.split(',')
+        .map(|v| v.trim().parse::<f32>().ok().filter(|v| v.is_finite()))
+        .collect::<Option<_>>()
+        .ok_or_else(invalid)?;
+    match parts[..] {
+        [x, y, w, h] if w > 0.0 && h > 0.0 => Ok([x, y, w, h]),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Result<Args, String> {
+        parse(list.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn parses_options() {
+        let parsed = args(&["notes.lignum", "--export=out.png", "--dpi", "150", "--region", "0, 10,200,300.5"]).unwrap();
+        assert_eq!(parsed.document, Some(PathBuf::from("notes.lignum")));
+        assert_eq!(parsed.export, Some(PathBuf::from("out.png")));
+        assert_eq!(parsed.dpi, Some(150.0));
+        assert_eq!(parsed.region, Some([0.0, 10.0, 200.0, 300.5]));
+        assert!(!parsed.help && !parsed.no_stylus && !parsed.annotated);
+
+        let parsed = args(&["--device", "/dev/input/event5", "--config=my.toml", "--", "-odd name.lignum"]).unwrap();
+        assert_eq!(parsed.device.as_deref(), Some("/dev/input/event5"));
+        assert_eq!(parsed.config, Some(PathBuf::from("my.toml")));
+        assert_eq!(parsed.document, Some(PathBuf::from("-odd name.lignum")));
+
+        assert!(args(&["-h"]).unwrap().help);
+        assert!(args(&["--no-stylus", "-"]).unwrap().no_stylus);
+        assert!(args(&["in.lignum", "--export", "out.pdf", "--annotated"]).unwrap().annotated);
+        assert!(args(&[]).unwrap().document.is_none());
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        let error = |list: &[&str]| args(list).err().unwrap_or_else(|| panic!("{:?} was accepted", list));
+        assert!(error(&["a.lignum", "b.lignum"]).contains("only one document"));
+        assert!(error(&["--frobnicate"]).contains("unknown option '--frobnicate'"));
+        assert!(error(&["--device"]).contains("--device needs a value"));
+        assert!(error(&["--help=yes"]).contains("doesn't take a value"));
+        assert!(error(&["--device", "x", "--no-stylus"]).contains("can't be combined"));
+        assert!(error(&["--export", "out.pdf"]).contains("needs a DOCUMENT"));
+        assert!(error(&["a.lignum", "--dpi", "96"]).contains("only apply to --export"));
+        assert!(error(&["a.lignum", "--annotated"]).contains("only applies to --export"));
+        for dpi in ["0", "-5", "NaN", "inf", "lots"] {
+            assert!(error(&["a.lignum", "--export", "a.png", "--dpi", dpi]).contains("--dpi expects"), "dpi {}", dpi);
+        }
+        for region in ["1,2,3", "1,2,3,4,5", "0,0,0,10", "0,0,10,-1", "a,b,c,d", ""] {
+            assert!(error(&["a.lignum", "--export", "a.png", "--region", region]).contains("--region expects"), "region {}", region);
+        }
+    }
+}
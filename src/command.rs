@@ -1,16 +1,71 @@
-use serde::Deserialize;
-
+use crate::canvas_image::{CanvasImage, ImageId};
+use crate::journal::{Entry, Journal};
+use crate::layer::{Layer, LayerId, LayerProps};
+use crate::page::PageLayout;
+use crate::pdf_background::{PdfBackground, PdfPage};
+use crate::text_box::TextBox;
 use crate::Stroke;
 
 
+/// A stroke that was translated along with its page.
+pub struct MovedStroke {
+    pub layer: LayerId,
+    pub before: Stroke,
+    pub after: Stroke,
+}
+
+/// Everything that was on pages that got deleted. Images, text boxes and pdf
+/// pages keep the index they had, so undo puts them back in the same order.
+#[derive(Default)]
+pub struct RemovedContent {
+    pub strokes: Vec<(LayerId, Stroke)>,
+    pub images: Vec<(usize, CanvasImage)>,
+    pub texts: Vec<(usize, TextBox)>,
+    pub pdf_pages: Vec<(usize, PdfPage)>,
+}
+
+/// Everything that was translated along with its page.
+#[derive(Default)]
+pub struct MovedContent {
+    pub strokes: Vec<MovedStroke>,
+    pub images: Vec<(ImageId, [f32;4], [f32;4])>, // rect before and after
+    pub texts: Vec<(TextBox, TextBox)>, // before and after
+    pub pdf_pages: Vec<(u32, [f32;2], [f32;2])>, // page in the pdf, offset before and after
+}
+
 pub enum Command {
-    AddStroke(Stroke),
-    RemoveStroke(Stroke),
+    AddStroke { layer: LayerId, stroke: Stroke },
+    RemoveStroke { layer: LayerId, stroke: Stroke },
+    AddLayer { index: usize, layer: Layer },
+    RemoveLayer { index: usize, layer: Layer },
+    MoveLayer { layer: LayerId, from: usize, to: usize },
+    SetLayerProps { layer: LayerId, before: LayerProps, after: LayerProps },
+    // inserting, deleting, reordering and restyling pages, content on moved
+    // pages moves with them and content on deleted pages is removed
+    EditPages {
+        before: PageLayout,
+        after: PageLayout,
+        removed: RemovedContent,
+        moved: MovedContent,
+    },
+    AddImage { index: usize, image: CanvasImage },
+    RemoveImage { index: usize, image: CanvasImage },
+    SetImageRect { image: ImageId, before: [f32;4], after: [f32;4] },
+    AddText { index: usize, text: TextBox },
+    RemoveText { index: usize, text: TextBox },
+    // content, position or formatting of a text box, matched by id
+    UpdateText { before: TextBox, after: TextBox },
+    // importing or removing the pdf shown behind the ink
+    SetPdf { before: Option<PdfBackground>, after: Option<PdfBackground> },
 }
 
 pub struct CommandStack {
     undo_stack: Vec<Command>,
     redo_stack: Vec<Command>,
+    journal: Option<Journal>,
+    // length of the undo stack when the document was saved, None once a new
+    // command replaced part of it and the saved state can't come back
+    saved: Option<usize>,
 }
 
 impl CommandStack {
@@ -18,19 +73,64 @@ impl CommandStack {
         CommandStack {
             undo_stack: vec![],
             redo_stack: vec![],
+            journal: None,
+            saved: Some(0),
         }
     }
 
+    /// Remembers the current state as the saved one.
+    pub fn mark_saved(&mut self) {
+        self.saved = Some(self.undo_stack.len());
+    }
+
+    /// Whether undoing and redoing led back to the saved state.
+    pub fn is_saved(&self) -> bool {
+        self.saved == Some(self.undo_stack.len())
+    }
+
+    pub fn set_journal(&mut self, journal: Journal) {
+        self.journal = Some(journal);
+    }
+
+    pub fn journal_mut(&mut self) -> Option<&mut Journal> {
+        self.journal.as_mut()
+    }
+
+    pub fn take_journal(&mut self) -> Option<Journal> {
+        self.journal.take()
+    }
+
     pub fn clear(&mut self) {
         self.undo_stack = vec![];
         self.redo_stack = vec![];
     }
 
+    /// Pushes a new command, the undone ones can't be redone after it.
     pub fn push_undo(&mut self, comm: Command) {
+        // the saved state had the commands that were undone to get here
+        if self.saved.is_some_and(|saved| saved > self.undo_stack.len()) {
+            self.saved = None;
+        }
+        self.redo_stack.clear();
+        if let Some(journal) = &mut self.journal {
+            journal.record_command(Entry::Applied, &comm);
+        }
+        self.undo_stack.push(comm);
+    }
+
+    /// Like `push_undo` for a command taken from the redo stack, which
+    /// returns to a state the document had before.
+    pub fn push_redone(&mut self, comm: Command) {
+        if let Some(journal) = &mut self.journal {
+            journal.record_command(Entry::Redone, &comm);
+        }
         self.undo_stack.push(comm);
     }
 
     pub fn push_redo(&mut self, comm: Command) {
+        if let Some(journal) = &mut self.journal {
+            journal.record_command(Entry::Reverted, &comm);
+        }
         self.redo_stack.push(comm);
     }
 
@@ -41,4 +141,71 @@ impl CommandStack {
     pub fn pop_redo(&mut self) -> Option<Command> {
         self.redo_stack.pop()
     }
-}
\ No newline at end of file
+}
+
+impl Default for CommandStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stroke_style::StrokeStyle;
+
+    fn add_stroke() -> Command {
+        Command::AddStroke { layer: 0, stroke: Stroke::new(StrokeStyle::default()) }
+    }
+
+    fn undo(stack: &mut CommandStack) {
+        let comm = stack.pop_undo().unwrap();
+        stack.push_redo(comm);
+    }
+
+    fn redo(stack: &mut CommandStack) {
+        let comm = stack.pop_redo().unwrap();
+        stack.push_redone(comm);
+    }
+
+    #[test]
+    fn undoing_back_to_the_save_is_clean() {
+        let mut stack = CommandStack::new();
+        assert!(stack.is_saved());
+        stack.push_undo(add_stroke());
+        stack.mark_saved();
+        stack.push_undo(add_stroke());
+        stack.push_undo(add_stroke());
+        assert!(!stack.is_saved());
+        undo(&mut stack);
+        undo(&mut stack);
+        assert!(stack.is_saved());
+        undo(&mut stack);
+        assert!(!stack.is_saved());
+        redo(&mut stack);
+        assert!(stack.is_saved());
+        redo(&mut stack);
+        assert!(!stack.is_saved());
+    }
+
+    #[test]
+    fn new_commands_replace_the_saved_state() {
+        let mut stack = CommandStack::new();
+        stack.push_undo(add_stroke());
+        stack.push_undo(add_stroke());
+        stack.mark_saved();
+        undo(&mut stack);
+        stack.push_undo(add_stroke());
+        assert!(!stack.is_saved());
+        undo(&mut stack);
+        assert!(!stack.is_saved());
+        redo(&mut stack);
+        assert!(!stack.is_saved());
+
+        // commands after the saved state don't replace it
+        stack.mark_saved();
+        stack.push_undo(add_stroke());
+        undo(&mut stack);
+        assert!(stack.is_saved());
+    }
+}
@@ -0,0 +1,80 @@
+use serde::{Serialize, Deserialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::paths;
+use crate::pdf_background;
+use crate::pressure_curve::PressureCurve;
+use crate::tablet_mapping::TabletMapping;
+use crate::tablet_profile::TabletProfile;
+
+/// Settings read from `config.json`, every field is optional in the file.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// evdev node of the stylus, e.g. `/dev/input/event15`, found
+    /// automatically if empty
+    pub stylus_device: String,
+    /// written into documents that don't name an author yet
+    pub author: String,
+    /// renders imported pdf pages, called like poppler's `pdftoppm`
+    pub pdf_renderer: String,
+    /// which part of the tablet covers which part of the screen
+    pub tablet_mapping: TabletMapping,
+    /// tried before the built in profiles, the first matching one is used
+    pub tablet_profiles: Vec<TabletProfile>,
+    /// pressure to stroke width, for tablets whose profile has no curve
+    pub pressure_curve: PressureCurve,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            stylus_device: String::new(),
+            author: String::new(),
+            pdf_renderer: pdf_background::DEFAULT_RENDERER.to_string(),
+            tablet_mapping: TabletMapping::default(),
+            tablet_profiles: Vec::new(),
+            pressure_curve: PressureCurve::default(),
+        }
+    }
+}
+
+pub fn default_path() -> PathBuf {
+    paths::config_dir().join("config.json")
+}
+
+impl Config {
+    /// Reads the config at `path`. A missing file is only an error if
+    /// `required` is set, otherwise the defaults are used.
+    pub fn load(path: &Path, required: bool) -> Result<Config, String> {
+        match fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json).map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !required => Ok(Config::default()),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        }
+        fs::write(path, json).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Stores `curve` where the curve of a tablet with `profile` comes
+    /// from: its own if it has one, the global one otherwise. A built in
+    /// profile with its own curve is copied into the configured ones.
+    pub fn set_pressure_curve(&mut self, profile: &TabletProfile, curve: PressureCurve) {
+        if profile.curve.is_none() {
+            self.pressure_curve = curve;
+            return;
+        }
+        match self.tablet_profiles.iter_mut().find(|p| p.name == profile.name) {
+            Some(configured) => configured.curve = Some(curve),
+            None => self.tablet_profiles.push(TabletProfile { curve: Some(curve), ..profile.clone() }),
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use macroquad::prelude::Rect;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::canvas_image::CanvasImage;
+use crate::file_format::CanvasData;
+use crate::inkml_format;
+use crate::page::{self, Page, PageLayout};
+use crate::pdf_background::{self, PdfPage};
+use crate::pdf_export;
+use crate::raster::{self, RenderOptions};
+use crate::svg_format;
+use crate::text_box::TextBox;
+use crate::xopp_format;
+use crate::Stroke;
+
+/// Strokes of all visible layers, bottom to top, with the layer opacity
+/// folded into their color.
+pub fn visible_strokes(data: &CanvasData) -> Vec<Stroke> {
+    let mut strokes = Vec::new();
+    for layer in data.layers.iter().filter(|l| l.props.visible) {
+        for sd in &layer.strokes {
+            let mut stroke = Stroke::from(sd.clone());
+            stroke.style.color[3] *= layer.props.opacity;
+            strokes.push(stroke);
+        }
+    }
+    strokes
+}
+
+/// Areas covered by images, text boxes and imported pdf pages.
+pub fn element_areas(data: &CanvasData) -> Vec<Rect> {
+    let pdf_pages = data.pdf.iter().flat_map(|pdf| pdf.pages.iter().map(PdfPage::rect));
+    data.images.iter().map(CanvasImage::rect).chain(data.texts.iter().map(TextBox::rect)).chain(pdf_pages).collect()
+}
+
+/// The pages an export consists of: the document's page list, or one A4
+/// page for every cell of the endless canvas that has ink, images or text
+/// on it.
+pub fn export_pages(data: &CanvasData, strokes: &[Stroke]) -> Vec<Page> {
+    match &data.pages {
+        PageLayout::Pages(pages) => pages.clone(),
+        PageLayout::Infinite => page::pages_from_ink(strokes.iter(), &element_areas(data)),
+    }
+}
+
+/// Writes `data` to `path` in the format given by its extension. Returns a
+/// short description of what was written. `render` only applies to images.
+pub fn export_document(data: &CanvasData, path: &Path, render: &RenderOptions) -> io::Result<String> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    match extension.as_str() {
+        "pdf" => {
+            let strokes = visible_strokes(data);
+            let pages = export_pages(data, &strokes);
+            if pages.is_empty() {
+                return Err(io::Error::other("there is nothing to export, the canvas is empty"));
+            }
+            fs::write(path, pdf_export::write_pdf(&pages, &strokes, &data.images, &data.texts, &data.meta)?)?;
+            let lost = lost_characters(data, pages.iter().map(|p| p.rect()));
+            Ok(format!("{} page(s){}", pages.len(), lost))
+        }
+        "svg" => {
+            fs::write(path, svg_format::canvas_to_svg(data))?;
+            Ok("drawing".to_string())
+        }
+        "png" => {
+            let image = raster::render(data, render)?;
+            raster::write_png(path, &image)?;
+            Ok(format!("{}x{} image", image.width, image.height))
+        }
+        "inkml" => {
+            fs::write(path, inkml_format::canvas_to_inkml(data))?;
+            Ok("ink traces".to_string())
+        }
+        "xopp" => {
+            fs::write(path, xopp_format::canvas_to_xopp(data)?)?;
+            Ok("Xournal++ notebook".to_string())
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("can't export to '.{}' files, use .pdf, .svg, .png, .xopp or .inkml", extension),
+        )),
+    }
+}
+
+/// Writes the imported pdf of `data` with the ink drawn over its pages.
+pub fn export_annotated(data: &CanvasData, path: &Path) -> io::Result<String> {
+    let is_pdf = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("pdf"));
+    if !is_pdf {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "annotated documents can only be exported as .pdf"));
+    }
+    let (pdf, pages) = pdf_background::write_annotated(data)?;
+    fs::write(path, pdf)?;
+    let areas = data.pdf.iter().flat_map(|pdf| pdf.pages.iter().map(|p| p.rect()));
+    Ok(format!("{} annotated page(s){}", pages, lost_characters(data, areas)))
+}
+
+/// A note on the characters of the text boxes inside `areas` that the pdf
+/// font can't encode, empty if there are none.
+fn lost_characters(data: &CanvasData, areas: impl Iterator<Item = Rect> + Clone) -> String {
+    let texts = data.texts.iter().filter(|t| areas.clone().any(|a| a.overlaps(&t.rect())));
+    match pdf_export::unencodable_chars(texts) {
+        0 => String::new(),
+        lost => format!(", {} character(s) of text the pdf font can't show were replaced by '?'", lost),
+    }
+}
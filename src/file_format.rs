@@ -0,0 +1,419 @@
+use serde::{Serialize, Deserialize};
+use serde_json::{self, Value};
+
+use crate::ToolMode;
+use crate::canvas_image::CanvasImage;
+use crate::layer::{LayerId, LayerProps};
+use crate::page::PageLayout;
+use crate::pdf_background::PdfBackground;
+use crate::persistence::{LoadReport, PersistenceError};
+use crate::stroke_style::StrokeStyle;
+use crate::text_box::TextBox;
+
+/// Version written into every saved document. Whenever `CanvasData` or
+/// anything it contains changes shape, bump this and append a step to
+/// `MIGRATIONS` that upgrades the previous version.
+pub const FORMAT_VERSION: u32 = 10;
+
+/// One report of the input device while a stroke was drawn, before smoothing.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct InkSample {
+    pub pos: [f32;2],   // world coords
+    pub width: f32,     // thickness the point was drawn with
+    pub pressure: f32,  // 0..1
+    pub tilt: [f32;2],  // degrees, as reported by the device
+    pub time: f32,      // milliseconds since the first sample
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StrokeData {
+    pub points: Vec<([f32;2], f32)>,
+    pub style: StrokeStyle,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub samples: Vec<InkSample>,
+    /// When the stroke was finished, in milliseconds since the unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<u64>,
+}
+
+/// Descriptive fields of a document, timestamps are milliseconds since the
+/// unix epoch and unknown for documents from before they were recorded.
+#[derive(Clone, Default, PartialEq, Debug, Serialize, Deserialize)]
+pub struct DocumentMeta {
+    pub title: String,
+    pub author: String,
+    pub created: Option<u64>,
+    pub modified: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LayerData {
+    pub id: LayerId,
+    #[serde(flatten)]
+    pub props: LayerProps,
+    pub strokes: Vec<StrokeData>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CanvasData {
+    pub format_version: u32,
+    pub meta: DocumentMeta,
+    pub layers: Vec<LayerData>, // bottom to top
+    pub active_layer: usize,
+    pub pages: PageLayout,
+    pub images: Vec<CanvasImage>, // bottom to top, below all layers
+    pub texts: Vec<TextBox>, // bottom to top, above all layers
+    pub pdf: Option<PdfBackground>, // imported pages shown below everything
+    pub tool_mode: ToolMode,
+    pub offset: [f32;2],
+    pub zoom: f32,
+}
+
+impl StrokeData {
+    fn is_valid(&self) -> bool {
+        !self.points.is_empty()
+            && self.points.iter().all(|(p, w)| p[0].is_finite() && p[1].is_finite() && w.is_finite())
+    }
+}
+
+impl CanvasData {
+    /// Every stroke in the document, in the order they are stored.
+    pub fn strokes_mut(&mut self) -> impl Iterator<Item = &mut StrokeData> {
+        self.layers.iter_mut().flat_map(|layer| layer.strokes.iter_mut())
+    }
+
+    pub fn retain_strokes(&mut self, mut f: impl FnMut(&StrokeData) -> bool) {
+        for layer in &mut self.layers {
+            layer.strokes.retain(&mut f);
+        }
+    }
+}
+
+type Migration = fn(&mut Value);
+
+// MIGRATIONS[n] upgrades a version n document to version n + 1
+const MIGRATIONS: [Migration; FORMAT_VERSION as usize] = [
+    migrate_v0_to_v1,
+    migrate_v1_to_v2,
+    migrate_v2_to_v3,
+    migrate_v3_to_v4,
+    migrate_v4_to_v5,
+    migrate_v5_to_v6,
+    migrate_v6_to_v7,
+    migrate_v7_to_v8,
+    migrate_v8_to_v9,
+    migrate_v9_to_v10,
+];
+
+// v0 is the original unversioned {strokes, tool_mode, offset, zoom} shape.
+// v1 only adds the version field, which `migrate` writes after every step.
+fn migrate_v0_to_v1(_doc: &mut Value) {}
+
+// v2 adds a style to every stroke, older strokes were all drawn with the default pen
+fn migrate_v1_to_v2(doc: &mut Value) {
+    let default_style = serde_json::to_value(StrokeStyle::default()).unwrap();
+    if let Some(strokes) = doc.get_mut("strokes").and_then(Value::as_array_mut) {
+        for stroke in strokes.iter_mut().filter_map(Value::as_object_mut) {
+            stroke.entry("style").or_insert_with(|| default_style.clone());
+        }
+    }
+}
+
+// v3 moves the flat stroke list into a single layer
+fn migrate_v2_to_v3(doc: &mut Value) {
+    let Some(obj) = doc.as_object_mut() else { return };
+    let strokes = obj.remove("strokes").unwrap_or_else(|| Value::Array(Vec::new()));
+    let mut layer = serde_json::to_value(LayerProps::named("Layer 1")).unwrap();
+    layer["id"] = Value::from(0);
+    layer["strokes"] = strokes;
+    obj.insert("layers".to_string(), Value::Array(vec![layer]));
+    obj.insert("active_layer".to_string(), Value::from(0));
+}
+
+// v4 adds the page list, older documents all used the endless A4 grid
+fn migrate_v3_to_v4(doc: &mut Value) {
+    let Some(obj) = doc.as_object_mut() else { return };
+    obj.insert("pages".to_string(), serde_json::to_value(PageLayout::Infinite).unwrap());
+}
+
+// v5 keeps the raw input samples of new strokes, older strokes have none
+fn migrate_v4_to_v5(_doc: &mut Value) {}
+
+// v6 adds the metadata block and creation times of new strokes
+fn migrate_v5_to_v6(doc: &mut Value) {
+    let Some(obj) = doc.as_object_mut() else { return };
+    obj.insert("meta".to_string(), serde_json::to_value(DocumentMeta::default()).unwrap());
+}
+
+// v7 adds embedded images
+fn migrate_v6_to_v7(doc: &mut Value) {
+    let Some(obj) = doc.as_object_mut() else { return };
+    obj.insert("images".to_string(), Value::Array(Vec::new()));
+}
+
+// v8 adds text boxes
+fn migrate_v7_to_v8(doc: &mut Value) {
+    let Some(obj) = doc.as_object_mut() else { return };
+    obj.insert("texts".to_string(), Value::Array(Vec::new()));
+}
+
+// v9 adds the imported pdf pages
+fn migrate_v8_to_v9(doc: &mut Value) {
+    let Some(obj) = doc.as_object_mut() else { return };
+    obj.insert("pdf".to_string(), Value::Null);
+}
+
+// v10 lets pdf pages move with the page they lie on, older ones sit in their cell
+fn migrate_v9_to_v10(_doc: &mut Value) {}
+
+/// Version of a raw json document, files without a version field are v0.
+pub fn document_version(doc: &Value) -> u32 {
+    doc.get("format_version")
+        .and_then(Value::as_u64)
+        .map_or(0, |v| v as u32)
+}
+
+/// Runs every migration between the document's version and `FORMAT_VERSION`.
+/// Documents from a newer version are left untouched.
+pub fn migrate(doc: &mut Value) {
+    let mut version = document_version(doc);
+    while (version as usize) < MIGRATIONS.len() {
+        MIGRATIONS[version as usize](doc);
+        version += 1;
+        if let Some(obj) = doc.as_object_mut() {
+            obj.insert("format_version".to_string(), Value::from(version));
+        }
+    }
+}
+
+/// Rejects documents written by a newer version instead of guessing at them.
+pub fn check_version(doc: &Value) -> Result<(), PersistenceError> {
+    let found = document_version(doc);
+    if found > FORMAT_VERSION {
+        return Err(PersistenceError::UnsupportedVersion { found, supported: FORMAT_VERSION });
+    }
+    Ok(())
+}
+
+/// Deserializes a migrated document. Strokes that don't deserialize are
+/// dropped and recorded in the report. Also returns the stored index of
+/// every stroke that was kept, in document order.
+pub fn deserialize_document(mut doc: Value, report: &mut LoadReport) -> Result<(CanvasData, Vec<usize>), PersistenceError> {
+    let mut layer_strokes = Vec::new();
+    if let Some(Value::Array(layers)) = doc.get_mut("layers") {
+        for layer in layers {
+            layer_strokes.push(match layer.get_mut("strokes") {
+                Some(Value::Array(strokes)) => std::mem::take(strokes),
+                _ => Vec::new(),
+            });
+        }
+    }
+
+    let mut data: CanvasData = serde_json::from_value(doc)?;
+    let mut kept = Vec::new();
+    let mut index = 0;
+    for (layer, strokes) in data.layers.iter_mut().zip(layer_strokes) {
+        for stroke in strokes {
+            match serde_json::from_value::<StrokeData>(stroke) {
+                Ok(sd) => {
+                    layer.strokes.push(sd);
+                    kept.push(index);
+                }
+                Err(e) => report.skip(index, e.to_string()),
+            }
+            index += 1;
+        }
+    }
+    Ok((data, kept))
+}
+
+/// Drops strokes without points or with non-finite values, `kept` maps the
+/// document's strokes to their stored index for the report.
+pub fn drop_invalid_strokes(data: &mut CanvasData, kept: &[usize], report: &mut LoadReport) {
+    let mut index = kept.iter();
+    data.retain_strokes(|stroke| {
+        let stored = *index.next().unwrap();
+        if !stroke.is_valid() {
+            report.skip(stored, "empty stroke or non-finite coordinates");
+        }
+        stroke.is_valid()
+    });
+}
+
+pub fn canvas_from_json(contents: &str) -> Result<(CanvasData, LoadReport), PersistenceError> {
+    let mut doc: Value = serde_json::from_str(contents)?;
+    check_version(&doc)?;
+    migrate(&mut doc);
+
+    let mut report = LoadReport::default();
+    let (mut data, kept) = deserialize_document(doc, &mut report)?;
+    drop_invalid_strokes(&mut data, &kept, &mut report);
+    Ok((data, report))
+}
+
+pub fn canvas_to_json(data: &CanvasData) -> serde_json::Result<String> {
+    serde_json::to_string_pretty(data)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::page::{Background, Page, Pattern};
+    use crate::pdf_background::PdfPage;
+    use crate::stroke_style::{CapStyle, ToolKind};
+
+    // one document per format version, as that version saved it
+    const FIXTURES: [&str; FORMAT_VERSION as usize + 1] = [
+        include_str!("../tests/fixtures/v0.json"),
+        include_str!("../tests/fixtures/v1.json"),
+        include_str!("../tests/fixtures/v2.json"),
+        include_str!("../tests/fixtures/v3.json"),
+        include_str!("../tests/fixtures/v4.json"),
+        include_str!("../tests/fixtures/v5.json"),
+        include_str!("../tests/fixtures/v6.json"),
+        include_str!("../tests/fixtures/v7.json"),
+        include_str!("../tests/fixtures/v8.json"),
+        include_str!("../tests/fixtures/v9.json"),
+        include_str!("../tests/fixtures/v10.json"),
+    ];
+
+    // what the fixture of `version` is expected to load as, everything
+    // it couldn't store yet has its default. `FORMAT_VERSION` gives a
+    // document using every feature.
+    pub(crate) fn expected(version: u32) -> CanvasData {
+        let (marker, highlighter) = if version >= 2 {
+            (
+                StrokeStyle { color: [0.85, 0.1, 0.1, 1.0], width: 2.5, tool: ToolKind::Marker, cap: CapStyle::Round },
+                StrokeStyle::for_tool(ToolKind::Highlighter),
+            )
+        } else {
+            (StrokeStyle::default(), StrokeStyle::default())
+        };
+        let first = StrokeData {
+            points: vec![([10.0, 20.0], 1.5), ([30.0, 40.0], 2.0)],
+            style: marker,
+            samples: if version >= 5 {
+                vec![
+                    InkSample { pos: [10.0, 20.0], width: 1.5, pressure: 0.5, tilt: [10.0, -5.0], time: 0.0 },
+                    InkSample { pos: [30.0, 40.0], width: 2.0, pressure: 0.75, tilt: [12.0, -4.0], time: 16.5 },
+                ]
+            } else {
+                Vec::new()
+            },
+            created: (version >= 6).then_some(1760000000000),
+        };
+        let second = StrokeData {
+            points: vec![([-5.0, 0.5], 0.75), ([0.0, 8.0], 1.25), ([12.0, 9.0], 1.0)],
+            style: highlighter,
+            samples: Vec::new(),
+            created: None,
+        };
+        let (layers, active_layer) = if version >= 3 {
+            let notes = LayerProps { name: "Notes".to_string(), visible: true, opacity: 0.5, locked: true };
+            (vec![
+                LayerData { id: 0, props: LayerProps::named("Layer 1"), strokes: vec![first] },
+                LayerData { id: 3, props: notes, strokes: vec![second] },
+            ], 1)
+        } else {
+            (vec![LayerData { id: 0, props: LayerProps::named("Layer 1"), strokes: vec![first, second] }], 0)
+        };
+        CanvasData {
+            format_version: FORMAT_VERSION,
+            meta: if version >= 6 {
+                DocumentMeta {
+                    title: "Fixture".to_string(),
+                    author: "Tester".to_string(),
+                    created: Some(1750000000000),
+                    modified: Some(1760000000000),
+                }
+            } else {
+                DocumentMeta::default()
+            },
+            layers,
+            active_layer,
+            pages: if version >= 4 {
+                PageLayout::Pages(vec![Page {
+                    background: Background { pattern: Pattern::Ruled { spacing: 24.0 }, ..Background::default() },
+                    ..Page::a4(macroquad::math::Vec2::ZERO)
+                }])
+            } else {
+                PageLayout::Infinite
+            },
+            images: if version >= 7 {
+                vec![CanvasImage { id: 0, rect: [50.0, 60.0, 100.0, 80.0], png: b"\x89PNG\r\n\x1a\n".to_vec() }]
+            } else {
+                Vec::new()
+            },
+            texts: if version >= 8 {
+                vec![TextBox {
+                    id: 0,
+                    pos: [40.0, 200.0],
+                    text: "Grüße".to_string(),
+                    font_size: 16.0,
+                    color: [0.0, 0.0, 0.0, 1.0],
+                    wrap_width: 240.0,
+                }]
+            } else {
+                Vec::new()
+            },
+            pdf: (version >= 9).then(|| PdfBackground {
+                name: "slides.pdf".to_string(),
+                pdf: b"%PDF-1.4".to_vec(),
+                pages: vec![PdfPage {
+                    index: 0,
+                    cell: [1, 0],
+                    size: [842.0, 595.0],
+                    offset: if version >= 10 { [0.0, 882.0] } else { [0.0, 0.0] },
+                }],
+            }),
+            tool_mode: ToolMode::Eraser,
+            offset: [5.0, -5.0],
+            zoom: 1.5,
+        }
+    }
+
+    #[test]
+    fn fixtures_have_their_version() {
+        for (version, fixture) in FIXTURES.iter().enumerate() {
+            let doc: Value = serde_json::from_str(fixture).unwrap();
+            assert_eq!(document_version(&doc), version as u32);
+        }
+    }
+
+    #[test]
+    fn migrate_every_version() {
+        for (version, fixture) in FIXTURES.iter().enumerate() {
+            let mut doc: Value = serde_json::from_str(fixture).unwrap();
+            migrate(&mut doc);
+            assert_eq!(document_version(&doc), FORMAT_VERSION);
+
+            let mut report = LoadReport::default();
+            let (data, kept) = deserialize_document(doc, &mut report).unwrap();
+            assert!(report.skipped.is_empty(), "v{}", version);
+            assert_eq!(kept, vec![0, 1]);
+            assert_eq!(
+                serde_json::to_value(&data).unwrap(),
+                serde_json::to_value(expected(version as u32)).unwrap(),
+                "v{}",
+                version
+            );
+        }
+    }
+
+    #[test]
+    fn current_version_round_trips() {
+        let data = expected(FORMAT_VERSION);
+        let (loaded, report) = canvas_from_json(&canvas_to_json(&data).unwrap()).unwrap();
+        assert!(report.skipped.is_empty());
+        assert_eq!(serde_json::to_value(&loaded).unwrap(), serde_json::to_value(&data).unwrap());
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        let doc = serde_json::json!({ "format_version": FORMAT_VERSION + 1 });
+        assert!(matches!(
+            check_version(&doc),
+            Err(PersistenceError::UnsupportedVersion { found, .. }) if found == FORMAT_VERSION + 1
+        ));
+    }
+}
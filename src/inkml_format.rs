@@ -0,0 +1,640 @@
+use macroquad::prelude::*;
+use roxmltree::Node;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use crate::file_format::{CanvasData, DocumentMeta, InkSample, LayerData, StrokeData, FORMAT_VERSION};
+use crate::layer::LayerProps;
+use crate::page::PageLayout;
+use crate::persistence::{LoadReport, PersistenceError};
+use crate::stroke_style::{CapStyle, StrokeStyle};
+use crate::svg_format;
+use crate::text_box::TextBox;
+use crate::{Stroke, ToolMode};
+
+// InkML (https://www.w3.org/TR/InkML/) keeps ink as <trace> elements, each a
+// comma separated list of points whose values follow the channels of the
+// trace format. Every stroke becomes one trace:
+//
+// - strokes drawn here keep their raw input samples and are written in the
+//   "pen" context: X, Y, W (width), F (pressure), OTx, OTy (tilt) and T
+//   (milliseconds since the stroke began). Reading a trace with a T or F
+//   channel keeps its rows as samples and smooths them like finishing a
+//   stroke does.
+// - imported strokes have no samples, their final points are written in the
+//   "ink" context with X, Y and W and read back as they are.
+//
+// Brushes carry color and width, plus our stroke style so tools survive the
+// round trip. Layers are trace groups annotated with the layer name. Text
+// boxes have no InkML equivalent, they are kept as json in annotations
+// that other readers ignore.
+
+const NS: &str = "http://www.w3.org/2003/InkML";
+const XML_NS: &str = "http://www.w3.org/XML/1998/namespace";
+const STYLE_PROPERTY: &str = "lignum:style";
+const TEXT_ANNOTATION: &str = "lignum:text";
+const MARGIN: f32 = 20.0;
+
+pub fn is_inkml(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("inkml"))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn hex_color(color: [f32; 4]) -> String {
+    let c = |v: f32| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+    format!("#{:02X}{:02X}{:02X}", c(color[0]), c(color[1]), c(color[2]))
+}
+
+fn write_brush(xml: &mut String, id: usize, style: &StrokeStyle) {
+    let property = |xml: &mut String, name: &str, value: &str, units: &str| {
+        let units = if units.is_empty() { String::new() } else { format!(r#" units="{}""#, units) };
+        let _ = writeln!(xml, r#"      <brushProperty name="{}" value="{}"{}/>"#, name, escape(value), units);
+    };
+    let _ = writeln!(xml, r#"    <brush xml:id="brush{}">"#, id);
+    property(xml, "width", &format!("{:.2}", 2.0 * style.width), "pt");
+    property(xml, "color", &hex_color(style.color), "");
+    property(xml, "transparency", &format!("{}", ((1.0 - style.color[3].clamp(0.0, 1.0)) * 255.0).round()), "");
+    property(xml, "tip", if style.cap == CapStyle::Round { "ellipse" } else { "rectangle" }, "");
+    property(xml, STYLE_PROPERTY, &serde_json::to_string(style).unwrap_or_default(), "");
+    xml.push_str("    </brush>\n");
+}
+
+pub fn canvas_to_inkml(data: &CanvasData) -> String {
+    let mut brushes: Vec<StrokeStyle> = Vec::new();
+    for sd in data.layers.iter().flat_map(|l| &l.strokes) {
+        if !brushes.contains(&sd.style) {
+            brushes.push(sd.style);
+        }
+    }
+
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(xml, r#"<ink xmlns="{}">"#, NS);
+    for (kind, text) in [("title", &data.meta.title), ("author", &data.meta.author)] {
+        if !text.is_empty() {
+            let _ = writeln!(xml, r#"  <annotation type="{}">{}</annotation>"#, kind, escape(text));
+        }
+    }
+    xml.push_str("  <definitions>\n");
+    xml.push_str(r#"    <context xml:id="pen">
+      <traceFormat>
+        <channel name="X" type="decimal" units="pt"/>
+        <channel name="Y" type="decimal" units="pt"/>
+        <channel name="W" type="decimal" units="pt"/>
+        <channel name="F" type="decimal" min="0" max="1"/>
+        <channel name="OTx" type="decimal" units="deg"/>
+        <channel name="OTy" type="decimal" units="deg"/>
+        <channel name="T" type="decimal" units="ms"/>
+      </traceFormat>
+    </context>
+    <context xml:id="ink">
+      <traceFormat>
+        <channel name="X" type="decimal" units="pt"/>
+        <channel name="Y" type="decimal" units="pt"/>
+        <channel name="W" type="decimal" units="pt"/>
+      </traceFormat>
+    </context>
+"#);
+    for (i, style) in brushes.iter().enumerate() {
+        write_brush(&mut xml, i, style);
+    }
+    xml.push_str("  </definitions>\n");
+
+    for layer in &data.layers {
+        xml.push_str("  <traceGroup>\n");
+        let _ = writeln!(xml, r#"    <annotation type="layer">{}</annotation>"#, escape(&layer.props.name));
+        for sd in &layer.strokes {
+            let brush = brushes.iter().position(|b| *b == sd.style).unwrap_or(0);
+            // widths are stored as the full drawn width
+            let w = |th: f32| th * 2.0 * sd.style.width;
+            let points: Vec<String> = if sd.samples.is_empty() {
+                sd.points.iter().map(|(p, th)| format!("{:.2} {:.2} {:.3}", p[0], p[1], w(*th))).collect()
+            } else {
+                sd.samples.iter()
+                    .map(|s| format!(
+                        "{:.2} {:.2} {:.3} {:.3} {} {} {:.1}",
+                        s.pos[0], s.pos[1], w(s.width), s.pressure, s.tilt[0], s.tilt[1], s.time
+                    ))
+                    .collect()
+            };
+            let context = if sd.samples.is_empty() { "ink" } else { "pen" };
+            let _ = writeln!(
+                xml,
+                "    <trace contextRef=\"#{}\" brushRef=\"#brush{}\">{}</trace>",
+                context, brush, points.join(", ")
+            );
+        }
+        xml.push_str("  </traceGroup>\n");
+    }
+    for text in &data.texts {
+        let json = serde_json::to_string(text).unwrap_or_default();
+        let _ = writeln!(xml, r#"  <annotation type="{}">{}</annotation>"#, TEXT_ANNOTATION, escape(&json));
+    }
+    xml.push_str("</ink>\n");
+    xml
+}
+
+struct Channel {
+    name: String,
+    scale: f32, // to points, milliseconds or degrees
+    min: Option<f32>,
+    max: Option<f32>,
+}
+
+#[derive(Clone, Copy)]
+struct Brush {
+    style: StrokeStyle,
+    width: f32,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self { style: StrokeStyle::default(), width: 1.5 }
+    }
+}
+
+fn unit_scale(units: Option<&str>) -> f32 {
+    match units.unwrap_or("") {
+        "mm" => 72.0 / 25.4,
+        "cm" => 720.0 / 25.4,
+        "m" => 72000.0 / 25.4,
+        "in" => 72.0,
+        "px" => 0.75,
+        "himetric" => 72.0 / 2540.0,
+        "s" => 1000.0,
+        "rad" => 180.0 / std::f32::consts::PI,
+        _ => 1.0,
+    }
+}
+
+fn id_of<'a>(node: Node<'a, '_>) -> Option<&'a str> {
+    node.attribute((XML_NS, "id")).or_else(|| node.attribute("id"))
+}
+
+fn reference<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.attribute(name).map(|r| r.trim_start_matches('#'))
+}
+
+fn channels_of(format: Node) -> Vec<Channel> {
+    // intermittent channels are optional per point, they are left out
+    format.children()
+        .filter(|n| n.has_tag_name("channel"))
+        .map(|n| {
+            let limit = |name: &str| n.attribute(name).and_then(|v| v.parse::<f32>().ok());
+            Channel {
+                name: n.attribute("name").unwrap_or("").to_string(),
+                scale: unit_scale(n.attribute("units")),
+                min: limit("min"),
+                max: limit("max"),
+            }
+        })
+        .collect()
+}
+
+fn brush_of(node: Node) -> Brush {
+    let mut brush = Brush::default();
+    let mut own_style = false;
+    let mut alpha = 1.0;
+    for property in node.children().filter(|n| n.has_tag_name("brushProperty")) {
+        let value = property.attribute("value").unwrap_or("");
+        match property.attribute("name").unwrap_or("") {
+            "width" => {
+                if let Ok(w) = value.parse::<f32>() {
+                    brush.width = w * unit_scale(property.attribute("units"));
+                }
+            }
+            "color" => {
+                if let Some([r, g, b]) = svg_format::parse_color(value) {
+                    brush.style.color = [r, g, b, brush.style.color[3]];
+                }
+            }
+            "transparency" => {
+                if let Ok(t) = value.parse::<f32>() {
+                    alpha = 1.0 - (t / 255.0).clamp(0.0, 1.0);
+                }
+            }
+            STYLE_PROPERTY => {
+                if let Ok(style) = serde_json::from_str(value) {
+                    brush.style = style;
+                    own_style = true;
+                }
+            }
+            _ => {}
+        }
+    }
+    if !own_style {
+        brush.style.color[3] = alpha;
+        brush.style.width = 1.0;
+    }
+    brush
+}
+
+/// Trace formats, brushes and contexts of the document by their id.
+#[derive(Default)]
+struct Definitions<'a, 'input> {
+    formats: HashMap<&'a str, Node<'a, 'input>>,
+    brushes: HashMap<&'a str, Node<'a, 'input>>,
+    contexts: HashMap<&'a str, Node<'a, 'input>>,
+}
+
+impl<'a, 'input> Definitions<'a, 'input> {
+    fn collect(doc: &'a roxmltree::Document<'input>) -> Self {
+        let mut defs = Self::default();
+        for node in doc.descendants().filter(|n| n.is_element()) {
+            let Some(id) = id_of(node) else { continue };
+            match node.tag_name().name() {
+                "traceFormat" => { defs.formats.insert(id, node); }
+                "brush" => { defs.brushes.insert(id, node); }
+                "context" => { defs.contexts.insert(id, node); }
+                _ => {}
+            }
+        }
+        defs
+    }
+
+    /// Format and brush in effect inside `node`, starting from the inherited ones.
+    fn apply(&self, node: Node<'a, 'input>, format: &mut Option<Node<'a, 'input>>, brush: &mut Brush) {
+        if let Some(context) = reference(node, "contextRef").and_then(|id| self.contexts.get(id)) {
+            self.apply(*context, format, brush);
+        }
+        if node.has_tag_name("context") {
+            if let Some(f) = node.children().find(|n| n.has_tag_name("traceFormat")) {
+                *format = Some(f);
+            }
+            if let Some(b) = node.children().find(|n| n.has_tag_name("brush")) {
+                *brush = brush_of(b);
+            }
+        }
+        if let Some(f) = reference(node, "traceFormatRef").and_then(|id| self.formats.get(id)) {
+            *format = Some(*f);
+        }
+        if let Some(b) = reference(node, "brushRef").and_then(|id| self.brushes.get(id)) {
+            *brush = brush_of(*b);
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Explicit,
+    FirstDifference,
+    SecondDifference,
+}
+
+/// Decodes the points of a trace. Values may be prefixed with `!`, `'` or
+/// `"` to switch the channel between explicit values, first and second
+/// differences, and numbers don't need a separator before a sign.
+fn decode_trace(text: &str, channels: usize) -> Result<Vec<Vec<f32>>, String> {
+    let mut state = vec![(Mode::Explicit, 0.0f32, 0.0f32); channels];
+    let mut rows = Vec::new();
+    for point in text.split(',').filter(|p| !p.trim().is_empty()) {
+        let mut row = Vec::with_capacity(channels);
+        let mut mode = None;
+        let mut chars = point.char_indices().peekable();
+        while let Some(&(start, c)) = chars.peek() {
+            let value = match c {
+                c if c.is_whitespace() => {
+                    chars.next();
+                    continue;
+                }
+                '!' | '\'' | '"' => {
+                    mode = Some(match c { '!' => Mode::Explicit, '\'' => Mode::FirstDifference, _ => Mode::SecondDifference });
+                    chars.next();
+                    continue;
+                }
+                'T' => { chars.next(); Some(1.0) }
+                'F' => { chars.next(); Some(0.0) }
+                // unknown or repeated value: keep the previous one
+                '?' | '*' => { chars.next(); None }
+                _ => {
+                    let mut end = start;
+                    let mut prev = ' ';
+                    while let Some(&(i, c)) = chars.peek() {
+                        let sign_ok = (c == '-' || c == '+') && (i == start || prev == 'e' || prev == 'E');
+                        if !(c.is_ascii_digit() || c == '.' || c == 'e' || c == 'E' || sign_ok) {
+                            break;
+                        }
+                        prev = c;
+                        end = i + c.len_utf8();
+                        chars.next();
+                    }
+                    if end == start {
+                        return Err(format!("unexpected '{}' in trace", c));
+                    }
+                    Some(point[start..end].parse::<f32>().map_err(|_| format!("bad number '{}'", &point[start..end]))?)
+                }
+            };
+            let Some(channel) = state.get_mut(row.len()) else { break };
+            if let Some(m) = mode.take() {
+                channel.0 = m;
+            }
+            let (mode, last, velocity) = channel;
+            match (value, *mode) {
+                (None, _) => {}
+                (Some(v), Mode::Explicit) => {
+                    *velocity = v - *last;
+                    *last = v;
+                }
+                (Some(d), Mode::FirstDifference) => {
+                    *velocity = d;
+                    *last += d;
+                }
+                (Some(d), Mode::SecondDifference) => {
+                    *velocity += d;
+                    *last += *velocity;
+                }
+            }
+            row.push(*last);
+        }
+        if row.len() < channels.min(2) {
+            return Err("trace point without coordinates".to_string());
+        }
+        // missing trailing values repeat the previous point
+        while row.len() < channels {
+            row.push(state[row.len()].1);
+        }
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+fn stroke_of(text: &str, channels: &[Channel], brush: &Brush) -> Result<StrokeData, String> {
+    let find = |name: &str| channels.iter().position(|c| c.name == name);
+    let (Some(x), Some(y)) = (find("X"), find("Y")) else {
+        return Err("trace format without X and Y".to_string());
+    };
+    let (w, f, tx, ty, t) = (find("W"), find("F"), find("OTx"), find("OTy"), find("T"));
+    let rows = decode_trace(text, channels.len())?;
+    if rows.is_empty() {
+        return Err("empty trace".to_string());
+    }
+    if rows.iter().flatten().any(|v| !v.is_finite()) {
+        return Err("non-finite value in trace".to_string());
+    }
+
+    // pressure without a declared range is scaled by the largest value in the trace
+    let pressure_range = f.map(|i| {
+        let channel = &channels[i];
+        let max = channel.max.unwrap_or_else(|| rows.iter().map(|r| r[i]).fold(0.0, f32::max));
+        (i, channel.min.unwrap_or(0.0), max)
+    });
+    let pressure = |row: &[f32]| {
+        let (i, min, max) = pressure_range?;
+        Some(if max > min { ((row[i] - min) / (max - min)).clamp(0.0, 1.0) } else { 1.0 })
+    };
+    let value = |row: &[f32], i: Option<usize>| i.map_or(0.0, |i| row[i] * channels[i].scale);
+    let style = brush.style;
+    let t0 = t.map_or(0.0, |t| rows[0][t] * channels[t].scale);
+    // time or pressure make the trace a recording of the pen
+    let recorded = t.is_some() || f.is_some();
+
+    let mut points = Vec::with_capacity(rows.len());
+    let mut samples = Vec::new();
+    for row in &rows {
+        let pos = vec2(value(row, Some(x)), value(row, Some(y)));
+        let width = match (w, pressure(row)) {
+            (Some(_), _) => value(row, w),
+            (None, Some(p)) => brush.width * p,
+            (None, None) => brush.width,
+        };
+        let thickness = width / (2.0 * style.width.max(f32::EPSILON));
+        points.push((pos, thickness));
+        if recorded {
+            samples.push(InkSample {
+                pos: [pos.x, pos.y],
+                width: thickness,
+                pressure: pressure(row).unwrap_or(1.0),
+                tilt: [value(row, tx), value(row, ty)],
+                time: value(row, t) - t0,
+            });
+        }
+    }
+
+    let mut stroke = Stroke::new(style);
+    stroke.points = points;
+    // raw samples are shaped the same way as strokes drawn here, any other
+    // trace already holds the final points
+    if recorded {
+        stroke.smooth(1.0);
+    }
+    stroke.samples = samples;
+    Ok(StrokeData::from(&stroke))
+}
+
+fn default_format(channels: &mut Vec<Channel>) {
+    for name in ["X", "Y"] {
+        channels.push(Channel { name: name.to_string(), scale: 1.0, min: None, max: None });
+    }
+}
+
+struct Reader<'a, 'input> {
+    defs: Definitions<'a, 'input>,
+    report: LoadReport,
+    index: usize,
+}
+
+impl<'a, 'input> Reader<'a, 'input> {
+    fn read_node(&mut self, node: Node<'a, 'input>, format: &mut Option<Node<'a, 'input>>, brush: &mut Brush, out: &mut Vec<StrokeData>) {
+        match node.tag_name().name() {
+            // a context outside the definitions switches the current one
+            "context" if !node.parent().is_some_and(|p| p.has_tag_name("definitions")) => {
+                self.defs.apply(node, format, brush);
+            }
+            "traceFormat" if node.parent().is_some_and(|p| p.has_tag_name("ink")) => *format = Some(node),
+            "traceGroup" => {
+                let (mut format, mut brush) = (*format, *brush);
+                self.defs.apply(node, &mut format, &mut brush);
+                for child in node.children().filter(|n| n.is_element()) {
+                    self.read_node(child, &mut format, &mut brush, out);
+                }
+            }
+            "trace" => {
+                let (mut format, mut brush) = (*format, *brush);
+                self.defs.apply(node, &mut format, &mut brush);
+                let mut channels = format.map(channels_of).unwrap_or_default();
+                if channels.is_empty() {
+                    default_format(&mut channels);
+                }
+                match stroke_of(node.text().unwrap_or(""), &channels, &brush) {
+                    Ok(sd) => out.push(sd),
+                    Err(reason) => self.report.skip(self.index, reason),
+                }
+                self.index += 1;
+            }
+            _ => {}
+        }
+    }
+}
+
+fn annotation(node: Node, kind: &str) -> Option<String> {
+    node.children()
+        .find(|n| n.has_tag_name("annotation") && n.attribute("type") == Some(kind))
+        .map(|n| n.text().unwrap_or("").trim().to_string())
+}
+
+fn layer_name(group: Node) -> Option<String> {
+    annotation(group, "layer")
+}
+
+pub fn canvas_from_inkml(bytes: &[u8]) -> Result<(CanvasData, LoadReport), PersistenceError> {
+    let text = std::str::from_utf8(bytes).map_err(|e| PersistenceError::Parse(e.to_string()))?;
+    let doc = roxmltree::Document::parse(text).map_err(|e| PersistenceError::Parse(e.to_string()))?;
+    let root = doc.root_element();
+    if !root.has_tag_name("ink") {
+        return Err(PersistenceError::Parse("not an InkML document".to_string()));
+    }
+
+    let mut reader = Reader { defs: Definitions::collect(&doc), report: LoadReport::default(), index: 0 };
+    let mut layers: Vec<LayerData> = Vec::new();
+    let mut loose = Vec::new();
+    let mut texts = Vec::new();
+    let (mut format, mut brush) = (None, Brush::default());
+
+    // top level groups with a layer annotation are layers, any other ink
+    // ends up on a layer of its own
+    for node in root.children().filter(|n| n.is_element()) {
+        if node.has_tag_name("annotation") && node.attribute("type") == Some(TEXT_ANNOTATION) {
+            match serde_json::from_str::<TextBox>(node.text().unwrap_or("")) {
+                Ok(text) => texts.push(text),
+                Err(e) => reader.report.skip(reader.index, e.to_string()),
+            }
+            reader.index += 1;
+            continue;
+        }
+        match layer_name(node).filter(|_| node.has_tag_name("traceGroup")) {
+            Some(name) => {
+                let mut strokes = Vec::new();
+                reader.read_node(node, &mut format, &mut brush, &mut strokes);
+                layers.push(LayerData { id: layers.len() as u32, props: LayerProps::named(name), strokes });
+            }
+            None => reader.read_node(node, &mut format, &mut brush, &mut loose),
+        }
+    }
+    if !loose.is_empty() || layers.is_empty() {
+        let name = format!("Layer {}", layers.len() + 1);
+        layers.push(LayerData { id: layers.len() as u32, props: LayerProps::named(name), strokes: loose });
+    }
+
+    // start with the ink in view, other tools often use device coordinates
+    let mut min = Vec2::splat(f32::INFINITY);
+    for sd in layers.iter().flat_map(|l| &l.strokes) {
+        for (p, _) in &sd.points {
+            min = min.min(vec2(p[0], p[1]));
+        }
+    }
+    let offset = if min.is_finite() { [min.x - MARGIN, min.y - MARGIN] } else { [0.0, 0.0] };
+
+    let data = CanvasData {
+        format_version: FORMAT_VERSION,
+        meta: DocumentMeta {
+            title: annotation(root, "title").unwrap_or_default(),
+            author: annotation(root, "author").unwrap_or_default(),
+            ..Default::default()
+        },
+        layers,
+        active_layer: 0,
+        pages: PageLayout::Infinite,
+        images: Vec::new(),
+        texts,
+        pdf: None,
+        tool_mode: ToolMode::Pen,
+        offset,
+        zoom: 1.0,
+    };
+    Ok((data, reader.report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn brush_with_non_ascii_color() {
+        let ink = r##"<ink xmlns="http://www.w3.org/2003/InkML">
+            <definitions>
+                <brush xml:id="a"><brushProperty name="color" value="#aé"/></brush>
+                <brush xml:id="b"><brushProperty name="color" value="#ff0000"/></brush>
+            </definitions>
+            <trace brushRef="#a">0 0, 10 10</trace>
+            <trace brushRef="#b">0 0, 10 10</trace>
+        </ink>"##;
+        let (data, _) = canvas_from_inkml(ink.as_bytes()).unwrap();
+        let strokes = &data.layers[0].strokes;
+        assert_eq!(strokes.len(), 2);
+        assert_eq!(strokes[0].style.color, Brush::default().style.color);
+        assert_eq!(strokes[1].style.color[..3], [1.0, 0.0, 0.0]);
+    }
+
+    fn document(strokes: Vec<StrokeData>) -> CanvasData {
+        CanvasData {
+            format_version: FORMAT_VERSION,
+            meta: DocumentMeta::default(),
+            layers: vec![LayerData { id: 0, props: LayerProps::named("Ink".to_string()), strokes }],
+            active_layer: 0,
+            pages: PageLayout::Infinite,
+            images: Vec::new(),
+            texts: Vec::new(),
+            pdf: None,
+            tool_mode: ToolMode::Pen,
+            offset: [0.0, 0.0],
+            zoom: 1.0,
+        }
+    }
+
+    #[test]
+    fn samples_round_trip() {
+        let samples: Vec<InkSample> = (0..20)
+            .map(|i| {
+                let i = i as f32;
+                InkSample {
+                    pos: [10.0 + 3.137 * i, 50.0 - 1.234 * i],
+                    width: 0.4 + 0.031 * i,
+                    pressure: 0.1 + 0.0437 * i,
+                    tilt: [-30.0 + 2.5 * i, 12.0 - i],
+                    time: 16.667 * i,
+                }
+            })
+            .collect();
+        let mut drawn = Stroke::new(StrokeStyle::default());
+        drawn.points = samples.iter().map(|s| (vec2(s.pos[0], s.pos[1]), s.width)).collect();
+        drawn.smooth(1.0);
+        drawn.samples = samples.clone();
+        // imported strokes keep their final points, they are not smoothed again
+        let imported = StrokeData {
+            points: vec![([0.0, 0.0], 1.0), ([5.0, 1.0], 1.5), ([10.0, 0.0], 1.0)],
+            style: StrokeStyle::default(),
+            samples: Vec::new(),
+            created: None,
+        };
+
+        let data = document(vec![StrokeData::from(&drawn), imported.clone()]);
+        let (loaded, report) = canvas_from_inkml(canvas_to_inkml(&data).as_bytes()).unwrap();
+        assert!(report.is_clean(), "{}", report);
+        let strokes = &loaded.layers[0].strokes;
+        assert_eq!(strokes.len(), 2);
+
+        // values are written with 2 decimals for X and Y, 3 for W and F and 1 for T
+        let close = |a: f32, b: f32, decimals: i32| (a - b).abs() <= 0.5 * 10f32.powi(-decimals) + 1e-4;
+        assert_eq!(strokes[0].samples.len(), samples.len());
+        for (l, e) in strokes[0].samples.iter().zip(&samples) {
+            assert!(close(l.pos[0], e.pos[0], 2) && close(l.pos[1], e.pos[1], 2), "{:?} != {:?}", l.pos, e.pos);
+            assert!(close(l.width, e.width, 3), "width {} != {}", l.width, e.width);
+            assert!(close(l.pressure, e.pressure, 3), "pressure {} != {}", l.pressure, e.pressure);
+            assert_eq!(l.tilt, e.tilt);
+            assert!(close(l.time, e.time, 1), "time {} != {}", l.time, e.time);
+        }
+        // the points are rebuilt from the samples the way drawing does
+        assert_eq!(strokes[0].points.len(), drawn.points.len());
+
+        assert_eq!(strokes[1].samples.len(), 0);
+        assert_eq!(strokes[1].points.len(), imported.points.len());
+        for ((lp, lw), (ep, ew)) in strokes[1].points.iter().zip(&imported.points) {
+            assert!(close(lp[0], ep[0], 2) && close(lp[1], ep[1], 2), "{:?} != {:?}", lp, ep);
+            assert!(close(*lw, *ew, 3), "width {} != {}", lw, ew);
+        }
+    }
+}
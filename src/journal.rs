@@ -0,0 +1,668 @@
+use serde::{Serialize, Deserialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::canvas_image::{self, CanvasImage, ImageId};
+use crate::command::{Command, CommandStack, MovedContent, MovedStroke, RemovedContent};
+use crate::file_format::{LayerData, StrokeData};
+use crate::layer::{Layer, LayerId, LayerProps};
+use crate::page::PageLayout;
+use crate::paths;
+use crate::pdf_background::{PdfBackground, PdfPage};
+use crate::text_box::TextBox;
+use crate::Stroke;
+
+// Every command pushed onto the command stack is appended to a per-process
+// journal as one json line. A clean shutdown deletes the journal, so any
+// journal whose process is gone belongs to a session that crashed and can be
+// replayed onto the document it started from.
+//
+// Images and pdfs can be megabytes and show up in several entries (added,
+// undone, redone, removed with a page), their bytes go into a side file
+// named by their hash that is written once, the entries only reference it.
+
+/// Bytes of an image or pdf in a journal entry.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Blob {
+    Stored { blob: String },
+    // older builds put the bytes into every entry
+    Inline(
+        #[serde(serialize_with = "canvas_image::to_base64", deserialize_with = "canvas_image::from_base64")]
+        Vec<u8>,
+    ),
+}
+
+/// Side files of a journal, in a directory next to it.
+struct Blobs {
+    dir: PathBuf,
+    written: HashSet<String>,
+}
+
+impl Blobs {
+    fn of(journal: &Path) -> Blobs {
+        Blobs { dir: journal.with_extension("blobs"), written: HashSet::new() }
+    }
+
+    fn store(&mut self, bytes: &[u8]) -> io::Result<Blob> {
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        let name = format!("{:016x}-{}", hasher.finish(), bytes.len());
+        if !self.written.contains(&name) {
+            fs::create_dir_all(&self.dir)?;
+            fs::write(self.dir.join(&name), bytes)?;
+            self.written.insert(name.clone());
+        }
+        Ok(Blob::Stored { blob: name })
+    }
+
+    fn load(&self, blob: Blob) -> io::Result<Vec<u8>> {
+        match blob {
+            Blob::Stored { blob } => fs::read(self.dir.join(blob)),
+            Blob::Inline(bytes) => Ok(bytes),
+        }
+    }
+
+    fn remove(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+        self.written.clear();
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct JournalImage {
+    id: ImageId,
+    rect: [f32;4],
+    png: Blob,
+}
+
+impl JournalImage {
+    fn new(image: &CanvasImage, blobs: &mut Blobs) -> io::Result<Self> {
+        Ok(JournalImage { id: image.id, rect: image.rect, png: blobs.store(&image.png)? })
+    }
+
+    fn into_image(self, blobs: &Blobs) -> io::Result<CanvasImage> {
+        Ok(CanvasImage { id: self.id, rect: self.rect, png: blobs.load(self.png)? })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct JournalPdf {
+    name: String,
+    pdf: Blob,
+    pages: Vec<PdfPage>,
+}
+
+impl JournalPdf {
+    fn new(pdf: &Option<PdfBackground>, blobs: &mut Blobs) -> io::Result<Option<Self>> {
+        let Some(pdf) = pdf else { return Ok(None) };
+        Ok(Some(JournalPdf { name: pdf.name.clone(), pdf: blobs.store(&pdf.pdf)?, pages: pdf.pages.clone() }))
+    }
+
+    fn into_pdf(pdf: Option<Self>, blobs: &Blobs) -> io::Result<Option<PdfBackground>> {
+        let Some(pdf) = pdf else { return Ok(None) };
+        Ok(Some(PdfBackground { name: pdf.name, pdf: blobs.load(pdf.pdf)?, pages: pdf.pages }))
+    }
+}
+
+// serializable mirror of `Command`
+#[derive(Serialize, Deserialize)]
+pub enum JournalCommand {
+    AddStroke { layer: LayerId, stroke: StrokeData },
+    RemoveStroke { layer: LayerId, stroke: StrokeData },
+    AddLayer { index: usize, layer: LayerData },
+    RemoveLayer { index: usize, layer: LayerData },
+    MoveLayer { layer: LayerId, from: usize, to: usize },
+    SetLayerProps { layer: LayerId, before: LayerProps, after: LayerProps },
+    EditPages {
+        before: PageLayout,
+        after: PageLayout,
+        removed: Vec<(LayerId, StrokeData)>,
+        moved: Vec<(LayerId, StrokeData, StrokeData)>,
+        // missing in journals of older builds
+        #[serde(default)]
+        removed_images: Vec<(usize, JournalImage)>,
+        #[serde(default)]
+        moved_images: Vec<(ImageId, [f32;4], [f32;4])>,
+        #[serde(default)]
+        removed_texts: Vec<(usize, TextBox)>,
+        #[serde(default)]
+        moved_texts: Vec<(TextBox, TextBox)>,
+        #[serde(default)]
+        removed_pdf_pages: Vec<(usize, PdfPage)>,
+        #[serde(default)]
+        moved_pdf_pages: Vec<(u32, [f32;2], [f32;2])>,
+    },
+    AddImage { index: usize, image: JournalImage },
+    RemoveImage { index: usize, image: JournalImage },
+    SetImageRect { image: ImageId, before: [f32;4], after: [f32;4] },
+    AddText { index: usize, text: TextBox },
+    RemoveText { index: usize, text: TextBox },
+    UpdateText { before: TextBox, after: TextBox },
+    SetPdf { before: Option<JournalPdf>, after: Option<JournalPdf> },
+}
+
+impl JournalCommand {
+    fn new(comm: &Command, blobs: &mut Blobs) -> io::Result<Self> {
+        Ok(match comm {
+            Command::AddStroke { layer, stroke } => JournalCommand::AddStroke { layer: *layer, stroke: stroke.into() },
+            Command::RemoveStroke { layer, stroke } => JournalCommand::RemoveStroke { layer: *layer, stroke: stroke.into() },
+            Command::AddLayer { index, layer } => JournalCommand::AddLayer { index: *index, layer: layer.into() },
+            Command::RemoveLayer { index, layer } => JournalCommand::RemoveLayer { index: *index, layer: layer.into() },
+            Command::MoveLayer { layer, from, to } => JournalCommand::MoveLayer { layer: *layer, from: *from, to: *to },
+            Command::SetLayerProps { layer, before, after } => JournalCommand::SetLayerProps {
+                layer: *layer,
+                before: before.clone(),
+                after: after.clone(),
+            },
+            Command::EditPages { before, after, removed, moved } => JournalCommand::EditPages {
+                before: before.clone(),
+                after: after.clone(),
+                removed: removed.strokes.iter().map(|(layer, stroke)| (*layer, stroke.into())).collect(),
+                moved: moved.strokes.iter().map(|m| (m.layer, (&m.before).into(), (&m.after).into())).collect(),
+                removed_images: removed.images.iter()
+                    .map(|(index, image)| Ok((*index, JournalImage::new(image, blobs)?)))
+                    .collect::<io::Result<_>>()?,
+                moved_images: moved.images.clone(),
+                removed_texts: removed.texts.clone(),
+                moved_texts: moved.texts.clone(),
+                removed_pdf_pages: removed.pdf_pages.clone(),
+                moved_pdf_pages: moved.pdf_pages.clone(),
+            },
+            Command::AddImage { index, image } => JournalCommand::AddImage { index: *index, image: JournalImage::new(image, blobs)? },
+            Command::RemoveImage { index, image } => JournalCommand::RemoveImage { index: *index, image: JournalImage::new(image, blobs)? },
+            Command::SetImageRect { image, before, after } => JournalCommand::SetImageRect { image: *image, before: *before, after: *after },
+            Command::AddText { index, text } => JournalCommand::AddText { index: *index, text: text.clone() },
+            Command::RemoveText { index, text } => JournalCommand::RemoveText { index: *index, text: text.clone() },
+            Command::UpdateText { before, after } => JournalCommand::UpdateText { before: before.clone(), after: after.clone() },
+            Command::SetPdf { before, after } => JournalCommand::SetPdf {
+                before: JournalPdf::new(before, blobs)?,
+                after: JournalPdf::new(after, blobs)?,
+            },
+        })
+    }
+
+    fn into_command(self, blobs: &Blobs) -> io::Result<Command> {
+        Ok(match self {
+            JournalCommand::AddStroke { layer, stroke } => Command::AddStroke { layer, stroke: Stroke::from(stroke) },
+            JournalCommand::RemoveStroke { layer, stroke } => Command::RemoveStroke { layer, stroke: Stroke::from(stroke) },
+            JournalCommand::AddLayer { index, layer } => Command::AddLayer { index, layer: Layer::from(layer) },
+            JournalCommand::RemoveLayer { index, layer } => Command::RemoveLayer { index, layer: Layer::from(layer) },
+            JournalCommand::MoveLayer { layer, from, to } => Command::MoveLayer { layer, from, to },
+            JournalCommand::SetLayerProps { layer, before, after } => Command::SetLayerProps { layer, before, after },
+            JournalCommand::EditPages {
+                before,
+                after,
+                removed,
+                moved,
+                removed_images,
+                moved_images,
+                removed_texts,
+                moved_texts,
+                removed_pdf_pages,
+                moved_pdf_pages,
+            } => Command::EditPages {
+                before,
+                after,
+                removed: RemovedContent {
+                    strokes: removed.into_iter().map(|(layer, stroke)| (layer, Stroke::from(stroke))).collect(),
+                    images: removed_images.into_iter()
+                        .map(|(index, image)| Ok((index, image.into_image(blobs)?)))
+                        .collect::<io::Result<_>>()?,
+                    texts: removed_texts,
+                    pdf_pages: removed_pdf_pages,
+                },
+                moved: MovedContent {
+                    strokes: moved.into_iter()
+                        .map(|(layer, before, after)| MovedStroke { layer, before: Stroke::from(before), after: Stroke::from(after) })
+                        .collect(),
+                    images: moved_images,
+                    texts: moved_texts,
+                    pdf_pages: moved_pdf_pages,
+                },
+            },
+            JournalCommand::AddImage { index, image } => Command::AddImage { index, image: image.into_image(blobs)? },
+            JournalCommand::RemoveImage { index, image } => Command::RemoveImage { index, image: image.into_image(blobs)? },
+            JournalCommand::SetImageRect { image, before, after } => Command::SetImageRect { image, before, after },
+            JournalCommand::AddText { index, text } => Command::AddText { index, text },
+            JournalCommand::RemoveText { index, text } => Command::RemoveText { index, text },
+            JournalCommand::UpdateText { before, after } => Command::UpdateText { before, after },
+            JournalCommand::SetPdf { before, after } => Command::SetPdf {
+                before: JournalPdf::into_pdf(before, blobs)?,
+                after: JournalPdf::into_pdf(after, blobs)?,
+            },
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum Entry {
+    /// First line of every journal, the saved document the entries apply to.
+    Base { document: Option<PathBuf> },
+    /// Pushed onto the undo stack, the command was done. Older builds also
+    /// wrote redone commands like this.
+    Applied(JournalCommand),
+    /// Moved from the redo to the undo stack, the command was redone.
+    Redone(JournalCommand),
+    /// Moved from the undo to the redo stack, the command was undone.
+    Reverted(JournalCommand),
+}
+
+fn journal_dir() -> PathBuf {
+    paths::state_dir().join("recovery")
+}
+
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    blobs: Blobs,
+    failed: bool,
+    error: Option<String>,
+}
+
+impl Journal {
+    pub fn create() -> io::Result<Journal> {
+        Journal::create_in(&journal_dir())
+    }
+
+    fn create_in(dir: &Path) -> io::Result<Journal> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}.journal", process::id()));
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let blobs = Blobs::of(&path);
+        let mut journal = Journal { path, file, blobs, failed: false, error: None };
+        journal.reset(None);
+        Ok(journal)
+    }
+
+    /// Drops all entries, called whenever the canvas matches a file on disk.
+    pub fn reset(&mut self, document: Option<&Path>) {
+        if let Err(e) = self.file.set_len(0) {
+            self.fail(e);
+            return;
+        }
+        self.blobs.remove();
+        self.failed = false;
+        self.record(&Entry::Base { document: document.map(Path::to_path_buf) });
+    }
+
+    /// Appends `comm` as the entry `kind` makes of it, e.g. `Entry::Applied`.
+    pub fn record_command(&mut self, kind: fn(JournalCommand) -> Entry, comm: &Command) {
+        if self.failed {
+            return;
+        }
+        match JournalCommand::new(comm, &mut self.blobs) {
+            Ok(comm) => self.record(&kind(comm)),
+            Err(e) => self.fail(e),
+        }
+    }
+
+    fn record(&mut self, entry: &Entry) {
+        if self.failed {
+            return;
+        }
+        let mut line = match serde_json::to_vec(entry) {
+            Ok(line) => line,
+            Err(e) => return self.fail(e.into()),
+        };
+        line.push(b'\n');
+        // a single write per line, so a crash leaves at most one partial line
+        if let Err(e) = self.file.write_all(&line) {
+            self.fail(e);
+        }
+    }
+
+    fn fail(&mut self, e: io::Error) {
+        eprintln!("Recovery journal {} stopped working: {}", self.path.display(), e);
+        self.failed = true;
+        self.error = Some(e.to_string());
+    }
+
+    /// Returns a write error once, journaling stays off until the next reset.
+    pub fn take_error(&mut self) -> Option<String> {
+        self.error.take()
+    }
+
+    /// Clean shutdown, nothing to recover.
+    pub fn remove(mut self) {
+        let _ = fs::remove_file(&self.path);
+        self.blobs.remove();
+    }
+}
+
+pub struct CrashedSession {
+    pub path: PathBuf,
+    pub document: Option<PathBuf>,
+    pub entries: Vec<Entry>,
+    blobs: Blobs,
+}
+
+impl CrashedSession {
+    pub fn discard(mut self) {
+        let _ = fs::remove_file(&self.path);
+        self.blobs.remove();
+    }
+
+    /// Pushes the entries onto `stack` the way the crashed session did, so
+    /// undo and redo continue where it stopped. `change` applies a command to
+    /// the document, or reverts it if the flag is false, and returns whether
+    /// it still fit. Returns the number of entries replayed.
+    pub fn replay(&mut self, stack: &mut CommandStack, mut change: impl FnMut(&Command, bool) -> bool) -> usize {
+        let mut replayed = 0;
+        for entry in std::mem::take(&mut self.entries) {
+            let ok = match entry {
+                Entry::Base { .. } => true,
+                Entry::Applied(comm) => comm.into_command(&self.blobs).is_ok_and(|comm| {
+                    let ok = change(&comm, true);
+                    if ok { stack.push_undo(comm); }
+                    ok
+                }),
+                Entry::Redone(comm) => {
+                    // the same command as the one on top of the redo stack
+                    stack.pop_redo();
+                    comm.into_command(&self.blobs).is_ok_and(|comm| {
+                        let ok = change(&comm, true);
+                        if ok { stack.push_redone(comm); }
+                        ok
+                    })
+                }
+                Entry::Reverted(comm) => {
+                    stack.pop_undo();
+                    comm.into_command(&self.blobs).is_ok_and(|comm| {
+                        let ok = change(&comm, false);
+                        if ok { stack.push_redo(comm); }
+                        ok
+                    })
+                }
+            };
+            if ok { replayed += 1; }
+        }
+        replayed
+    }
+}
+
+fn process_alive(pid: &str) -> bool {
+    Path::new("/proc").join(pid).exists()
+}
+
+fn read_journal(path: &Path) -> io::Result<CrashedSession> {
+    let mut session = CrashedSession { path: path.to_path_buf(), document: None, entries: Vec::new(), blobs: Blobs::of(path) };
+    for line in BufReader::new(File::open(path)?).lines() {
+        // the last line can be cut off by the crash
+        let Ok(entry) = serde_json::from_str::<Entry>(&line?) else { break };
+        match entry {
+            Entry::Base { document } => {
+                session.document = document;
+                session.entries.clear();
+            }
+            entry => session.entries.push(entry),
+        }
+    }
+    Ok(session)
+}
+
+/// The most recent journal left behind by a process that is no longer
+/// running. Journals without any changes are cleaned up on the way.
+pub fn find_crashed() -> Option<CrashedSession> {
+    find_crashed_in(&journal_dir())
+}
+
+fn find_crashed_in(dir: &Path) -> Option<CrashedSession> {
+    let mut crashed: Vec<(std::time::SystemTime, PathBuf)> = Vec::new();
+    for entry in fs::read_dir(dir).ok()?.flatten() {
+        let path = entry.path();
+        if path.extension().is_none_or(|ext| ext != "journal") {
+            continue;
+        }
+        let Some(pid) = path.file_stem().and_then(|s| s.to_str()) else { continue };
+        if pid == process::id().to_string() || process_alive(pid) {
+            continue;
+        }
+        let modified = entry.metadata().and_then(|m| m.modified()).unwrap_or(std::time::UNIX_EPOCH);
+        crashed.push((modified, path));
+    }
+    crashed.sort();
+
+    while let Some((_, path)) = crashed.pop() {
+        match read_journal(&path) {
+            Ok(session) if !session.entries.is_empty() => return Some(session),
+            _ => {
+                let _ = fs::remove_file(&path);
+                Blobs::of(&path).remove();
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stroke_style::StrokeStyle;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("lignum_journal_{}_{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    // stroke commands told apart by their layer
+    fn add_stroke(layer: LayerId) -> JournalCommand {
+        let comm = Command::AddStroke { layer, stroke: Stroke::new(StrokeStyle::default()) };
+        JournalCommand::new(&comm, &mut Blobs::of(Path::new("unused.journal"))).unwrap()
+    }
+
+    fn layer_of(comm: &Command) -> Option<LayerId> {
+        match comm {
+            Command::AddStroke { layer, .. } => Some(*layer),
+            _ => None,
+        }
+    }
+
+    fn session(entries: Vec<Entry>) -> CrashedSession {
+        CrashedSession { path: PathBuf::new(), document: None, entries, blobs: Blobs::of(Path::new("unused.journal")) }
+    }
+
+    #[test]
+    fn replay_restores_undo_and_redo() {
+        // 1 and 2 drawn, both undone, 1 redone
+        let mut crashed = session(vec![
+            Entry::Applied(add_stroke(1)),
+            Entry::Applied(add_stroke(2)),
+            Entry::Reverted(add_stroke(2)),
+            Entry::Reverted(add_stroke(1)),
+            Entry::Redone(add_stroke(1)),
+        ]);
+        let mut stack = CommandStack::new();
+        let mut changes = Vec::new();
+        let replayed = crashed.replay(&mut stack, |comm, apply| {
+            changes.push((layer_of(comm), apply));
+            true
+        });
+        assert_eq!(replayed, 5);
+        assert_eq!(changes, [(Some(1), true), (Some(2), true), (Some(2), false), (Some(1), false), (Some(1), true)]);
+        assert_eq!(stack.pop_undo().as_ref().and_then(layer_of), Some(1));
+        assert!(stack.pop_undo().is_none());
+        assert_eq!(stack.pop_redo().as_ref().and_then(layer_of), Some(2));
+        assert!(stack.pop_redo().is_none());
+    }
+
+    #[test]
+    fn replayed_edit_after_undo_drops_the_redo_stack() {
+        let mut crashed = session(vec![
+            Entry::Applied(add_stroke(1)),
+            Entry::Reverted(add_stroke(1)),
+            Entry::Applied(add_stroke(2)),
+        ]);
+        let mut stack = CommandStack::new();
+        assert_eq!(crashed.replay(&mut stack, |_, _| true), 3);
+        assert_eq!(stack.pop_undo().as_ref().and_then(layer_of), Some(2));
+        assert!(stack.pop_undo().is_none());
+        assert!(stack.pop_redo().is_none());
+    }
+
+    #[test]
+    fn commands_that_no_longer_fit_are_not_pushed() {
+        let mut crashed = session(vec![
+            Entry::Applied(add_stroke(1)),
+            Entry::Applied(add_stroke(2)),
+        ]);
+        let mut stack = CommandStack::new();
+        let replayed = crashed.replay(&mut stack, |comm, _| layer_of(comm) == Some(1));
+        assert_eq!(replayed, 1);
+        assert_eq!(stack.pop_undo().as_ref().and_then(layer_of), Some(1));
+        assert!(stack.pop_undo().is_none());
+    }
+
+    #[test]
+    fn reads_page_edits_of_older_builds() {
+        let line = r#"{"Applied":{"EditPages":{"before":"Infinite","after":{"Pages":[]},"removed":[],"moved":[]}}}"#;
+        let Ok(Entry::Applied(comm)) = serde_json::from_str::<Entry>(line) else { panic!("not an applied command") };
+        let blobs = Blobs::of(Path::new("unused.journal"));
+        let Ok(Command::EditPages { removed, moved, .. }) = comm.into_command(&blobs) else { panic!("not a page edit") };
+        assert!(removed.images.is_empty() && moved.images.is_empty());
+        assert!(removed.texts.is_empty() && moved.texts.is_empty());
+        assert!(removed.pdf_pages.is_empty() && moved.pdf_pages.is_empty());
+    }
+
+    #[test]
+    fn page_edits_keep_their_content() {
+        let image = CanvasImage { id: 4, rect: [1.0, 2.0, 3.0, 4.0], png: vec![1, 2, 3] };
+        let text = TextBox { id: 1, pos: [5.0, 6.0], text: "Über".to_string(), font_size: 16.0, color: [0.0, 0.0, 0.0, 1.0], wrap_width: 100.0 };
+        let moved_text = TextBox { pos: [5.0, 900.0], ..text.clone() };
+        let pdf_page = PdfPage { index: 3, cell: [0, 3], size: [612.0, 792.0], offset: [0.0, -40.0] };
+        let comm = Command::EditPages {
+            before: PageLayout::Infinite,
+            after: PageLayout::Infinite,
+            removed: RemovedContent {
+                images: vec![(2, image.clone())],
+                texts: vec![(0, text.clone())],
+                pdf_pages: vec![(3, pdf_page.clone())],
+                ..Default::default()
+            },
+            moved: MovedContent {
+                images: vec![(7, [0.0; 4], [1.0; 4])],
+                texts: vec![(text.clone(), moved_text.clone())],
+                pdf_pages: vec![(1, [0.0, 0.0], [0.0, 882.0])],
+                ..Default::default()
+            },
+        };
+        let dir = temp_dir("page_edits");
+        let mut blobs = Blobs::of(&dir.join("1.journal"));
+        let line = serde_json::to_string(&Entry::Applied(JournalCommand::new(&comm, &mut blobs).unwrap())).unwrap();
+        let Ok(Entry::Applied(comm)) = serde_json::from_str::<Entry>(&line) else { panic!("not an applied command") };
+        let comm = comm.into_command(&blobs);
+        let _ = fs::remove_dir_all(&dir);
+        let Ok(Command::EditPages { removed, moved, .. }) = comm else { panic!("not a page edit") };
+        assert_eq!(removed.images, vec![(2, image)]);
+        assert_eq!(moved.images, vec![(7, [0.0; 4], [1.0; 4])]);
+        assert_eq!(removed.texts, vec![(0, text.clone())]);
+        assert_eq!(moved.texts, vec![(text, moved_text)]);
+        assert_eq!(removed.pdf_pages, vec![(3, pdf_page)]);
+        assert_eq!(moved.pdf_pages, vec![(1, [0.0, 0.0], [0.0, 882.0])]);
+    }
+
+    #[test]
+    fn images_and_pdfs_are_written_once() {
+        let dir = temp_dir("blobs");
+        let png = vec![7u8; 10_000];
+        let pdf = PdfBackground { name: "a.pdf".to_string(), pdf: vec![9u8; 20_000], pages: Vec::new() };
+        let mut stack = CommandStack::new();
+        stack.set_journal(Journal::create_in(&dir).unwrap());
+        stack.push_undo(Command::AddImage { index: 0, image: CanvasImage { id: 1, rect: [0.0; 4], png: png.clone() } });
+        stack.push_undo(Command::SetPdf { before: None, after: Some(pdf.clone()) });
+        for _ in 0..3 {
+            for _ in 0..2 {
+                let comm = stack.pop_undo().unwrap();
+                stack.push_redo(comm);
+            }
+            for _ in 0..2 {
+                let comm = stack.pop_redo().unwrap();
+                stack.push_redone(comm);
+            }
+        }
+        let journal = stack.take_journal().unwrap();
+
+        let mut session = read_journal(&journal.path).unwrap();
+        assert_eq!(session.entries.len(), 14);
+        assert!(fs::metadata(&journal.path).unwrap().len() < 2_000);
+        assert_eq!(fs::read_dir(&journal.blobs.dir).unwrap().count(), 2);
+
+        let mut images = Vec::new();
+        let mut pdfs = Vec::new();
+        session.replay(&mut CommandStack::new(), |comm, _| {
+            match comm {
+                Command::AddImage { image, .. } => images.push(image.png.clone()),
+                Command::SetPdf { after, .. } => pdfs.push(after.clone()),
+                _ => {}
+            }
+            true
+        });
+        journal.remove();
+        let _ = fs::remove_dir_all(&dir);
+        assert!(images.len() == 7 && images.iter().all(|image| *image == png));
+        assert!(pdfs.len() == 7 && pdfs.iter().all(|p| *p == Some(pdf.clone())));
+    }
+
+    #[test]
+    fn reads_images_inlined_by_older_builds() {
+        let line = r#"{"Applied":{"AddImage":{"index":0,"image":{"id":2,"rect":[0,0,1,1],"png":"AQID"}}}}"#;
+        let Ok(Entry::Applied(comm)) = serde_json::from_str::<Entry>(line) else { panic!("not an applied command") };
+        let Ok(Command::AddImage { image, .. }) = comm.into_command(&Blobs::of(Path::new("unused.journal"))) else { panic!("not an image") };
+        assert_eq!(image.png, vec![1, 2, 3]);
+    }
+
+    fn base_line() -> String {
+        serde_json::to_string(&Entry::Base { document: Some(PathBuf::from("/notes/a.lignum")) }).unwrap()
+    }
+
+    fn applied_line(layer: LayerId) -> String {
+        serde_json::to_string(&Entry::Applied(add_stroke(layer))).unwrap()
+    }
+
+    #[test]
+    fn finds_the_journal_of_a_dead_process() {
+        let dir = temp_dir("crashed");
+        fs::create_dir_all(&dir).unwrap();
+        let lines = format!("{}\n{}\n", base_line(), applied_line(1));
+        // no process gets this pid, pid 1 and this test are alive
+        fs::write(dir.join("4294967295.journal"), &lines).unwrap();
+        fs::write(dir.join("1.journal"), &lines).unwrap();
+        fs::write(dir.join(format!("{}.journal", process::id())), &lines).unwrap();
+        // dead but without changes, only cleaned up
+        fs::write(dir.join("4294967294.journal"), format!("{}\n", base_line())).unwrap();
+
+        let crashed = find_crashed_in(&dir).unwrap();
+        assert_eq!(crashed.path, dir.join("4294967295.journal"));
+        assert_eq!(crashed.document, Some(PathBuf::from("/notes/a.lignum")));
+        assert_eq!(crashed.entries.len(), 1);
+
+        crashed.discard();
+        assert!(find_crashed_in(&dir).is_none());
+        assert!(!dir.join("4294967294.journal").exists());
+        assert!(dir.join("1.journal").exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn skips_a_line_cut_off_by_the_crash() {
+        let dir = temp_dir("truncated");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("4294967295.journal");
+        let cut = applied_line(3);
+        let lines = format!("{}\n{}\n{}\n{}", base_line(), applied_line(1), applied_line(2), &cut[..cut.len() / 2]);
+        fs::write(&path, lines).unwrap();
+
+        let mut crashed = read_journal(&path).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(crashed.entries.len(), 2);
+        let mut stack = CommandStack::new();
+        assert_eq!(crashed.replay(&mut stack, |_, _| true), 2);
+        assert_eq!(stack.pop_undo().as_ref().and_then(layer_of), Some(2));
+    }
+}
@@ -0,0 +1,218 @@
+use macroquad::miniquad::{BlendFactor, BlendState, BlendValue, Equation};
+use macroquad::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use crate::file_format::LayerData;
+use crate::Stroke;
+
+/// Stable identifier of a layer, unlike its index it survives reordering.
+pub type LayerId = u32;
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct LayerProps {
+    pub name: String,
+    pub visible: bool,
+    pub opacity: f32,
+    pub locked: bool,
+}
+
+impl LayerProps {
+    pub fn named(name: impl Into<String>) -> Self {
+        Self { name: name.into(), visible: true, opacity: 1.0, locked: false }
+    }
+
+    /// Whether strokes can be added to or erased from the layer.
+    pub fn editable(&self) -> bool {
+        self.visible && !self.locked
+    }
+}
+
+pub struct Layer {
+    pub id: LayerId,
+    pub props: LayerProps,
+    pub strokes: Vec<Stroke>,
+    pub stroke_cache: Vec<Option<Vec<Mesh>>>,
+}
+
+impl Layer {
+    pub fn new(id: LayerId, props: LayerProps) -> Self {
+        Self { id, props, strokes: Vec::new(), stroke_cache: Vec::new() }
+    }
+
+    pub fn push_stroke(&mut self, stroke: Stroke) {
+        self.strokes.push(stroke);
+        self.stroke_cache.push(None);
+    }
+
+    pub fn remove_stroke(&mut self, stroke: &Stroke) -> bool {
+        if let Some(idx) = self.strokes.iter().position(|s| s == stroke) {
+            self.strokes.remove(idx);
+            self.stroke_cache.remove(idx);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn replace_stroke(&mut self, old: &Stroke, new: Stroke) -> bool {
+        if let Some(idx) = self.strokes.iter().position(|s| s == old) {
+            self.strokes[idx] = new;
+            self.stroke_cache[idx] = None;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Copy of the layer for the undo history, without the mesh cache.
+    pub fn snapshot(&self) -> Layer {
+        let mut layer = Layer::new(self.id, self.props.clone());
+        for stroke in &self.strokes {
+            layer.push_stroke(stroke.clone());
+        }
+        layer
+    }
+}
+
+impl From<&Layer> for LayerData {
+    fn from(layer: &Layer) -> Self {
+        LayerData {
+            id: layer.id,
+            props: layer.props.clone(),
+            strokes: layer.strokes.iter().map(|s| s.into()).collect(),
+        }
+    }
+}
+
+impl From<LayerData> for Layer {
+    fn from(data: LayerData) -> Self {
+        let mut layer = Layer::new(data.id, data.props);
+        for sd in data.strokes {
+            layer.push_stroke(Stroke::from(sd));
+        }
+        layer
+    }
+}
+
+const LAYER_VERTEX: &str = r#"#version 100
+attribute vec3 position;
+attribute vec2 texcoord;
+attribute vec4 color0;
+
+varying lowp vec2 uv;
+varying lowp vec4 color;
+
+uniform mat4 Model;
+uniform mat4 Projection;
+
+void main() {
+    gl_Position = Projection * Model * vec4(position, 1);
+    color = color0 / 255.0;
+    uv = texcoord;
+}"#;
+
+const LAYER_FRAGMENT: &str = r#"#version 100
+varying lowp vec4 color;
+varying lowp vec2 uv;
+
+uniform sampler2D Texture;
+
+void main() {
+    gl_FragColor = color * texture2D(Texture, uv);
+}"#;
+
+fn layer_material(color: BlendState, alpha: BlendState) -> Material {
+    let params = MaterialParams {
+        pipeline_params: PipelineParams { color_blend: Some(color), alpha_blend: Some(alpha), ..Default::default() },
+        ..Default::default()
+    };
+    load_material(ShaderSource::Glsl { vertex: LAYER_VERTEX, fragment: LAYER_FRAGMENT }, params)
+        .expect("layer shader compiles")
+}
+
+/// Offscreen image a translucent layer is drawn into, so the layer opacity
+/// applies once to the whole layer. Overlapping strokes of a layer at half
+/// opacity cover each other instead of showing through.
+pub struct LayerTarget {
+    target: Option<RenderTarget>,
+    // draws into the target keeping premultiplied colors
+    draw: Material,
+    // blends the premultiplied target onto the screen
+    composite: Material,
+}
+
+impl LayerTarget {
+    pub fn new() -> Self {
+        let one_minus_src = BlendFactor::OneMinusValue(BlendValue::SourceAlpha);
+        Self {
+            target: None,
+            draw: layer_material(
+                BlendState::new(Equation::Add, BlendFactor::Value(BlendValue::SourceAlpha), one_minus_src),
+                BlendState::new(Equation::Add, BlendFactor::One, one_minus_src),
+            ),
+            composite: layer_material(
+                BlendState::new(Equation::Add, BlendFactor::One, one_minus_src),
+                BlendState::new(Equation::Add, BlendFactor::One, one_minus_src),
+            ),
+        }
+    }
+
+    /// Everything drawn until `composite` goes into the cleared target,
+    /// in screen coordinates.
+    pub fn begin(&mut self) {
+        let (w, h) = (screen_width(), screen_height());
+        let size = (w as u32, h as u32);
+        if self.target.as_ref().map(|t| (t.texture.width() as u32, t.texture.height() as u32)) != Some(size) {
+            self.target = Some(render_target(size.0, size.1));
+        }
+        let mut camera = Camera2D::from_display_rect(Rect::new(0.0, 0.0, w, h));
+        camera.render_target = self.target.clone();
+        set_camera(&camera);
+        clear_background(Color::new(0.0, 0.0, 0.0, 0.0));
+        gl_use_material(&self.draw);
+    }
+
+    /// Draws the target onto the screen at `opacity`.
+    pub fn composite(&self, opacity: f32) {
+        gl_use_default_material();
+        set_default_camera();
+        let Some(target) = &self.target else { return };
+        gl_use_material(&self.composite);
+        draw_texture_ex(&target.texture, 0.0, 0.0, Color::new(opacity, opacity, opacity, opacity), DrawTextureParams {
+            dest_size: Some(vec2(screen_width(), screen_height())),
+            flip_y: true,
+            ..Default::default()
+        });
+        gl_use_default_material();
+    }
+}
+
+impl Default for LayerTarget {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Layer list in the top right corner, topmost layer first.
+pub fn draw_layer_panel(layers: &[Layer], active: usize) {
+    let font_size = 18.0;
+    let line_h = 22.0;
+    let w = 230.0;
+    let x = screen_width() - w - 10.0;
+    let y = 10.0;
+
+    draw_rectangle(x, y, w, line_h * layers.len() as f32 + 8.0, Color::new(0.97, 0.97, 0.97, 0.9));
+    for (row, (i, layer)) in layers.iter().enumerate().rev().enumerate() {
+        let props = &layer.props;
+        let line = format!(
+            "{} {}{} {:>3}%  {}",
+            if i == active { ">" } else { " " },
+            if props.visible { "V" } else { "-" },
+            if props.locked { "L" } else { " " },
+            (props.opacity * 100.0).round(),
+            props.name,
+        );
+        let color = if props.visible { BLACK } else { GRAY };
+        draw_text(&line, x + 8.0, y + line_h * (row as f32 + 1.0), font_size, color);
+    }
+}
@@ -0,0 +1,349 @@
+pub mod canvas_image;
+pub mod cli;
+pub mod command;
+pub mod config;
+pub mod export;
+pub mod file_format;
+pub mod inkml_format;
+pub mod journal;
+pub mod layer;
+pub mod lignum_format;
+pub mod notification;
+pub mod outline;
+pub mod page;
+pub mod paths;
+pub mod pdf_background;
+pub mod pdf_export;
+pub mod persistence;
+pub mod point_codec;
+pub mod pressure_curve;
+pub mod raster;
+pub mod read_stylus;
+pub mod recent_files;
+pub mod rnote_import;
+pub mod stroke_style;
+pub mod stylus_device;
+pub mod svg_format;
+pub mod tablet_mapping;
+pub mod tablet_profile;
+pub mod text_box;
+pub mod timestamp;
+pub mod utility;
+pub mod xopp_format;
+
+use file_format::{InkSample, StrokeData};
+use macroquad::prelude::*;
+use serde::{Serialize, Deserialize};
+use stroke_style::{CapStyle, StrokeStyle};
+use utility::{catmull_rom_spline, color_u8, ramer_douglas_peucker};
+
+#[derive(PartialEq, Serialize, Deserialize, Clone)]
+pub enum ToolMode {
+    Pen,
+    Eraser,
+    Select, // moves and resizes images
+    Text, // places and edits text boxes
+}
+
+#[derive(Clone)]
+pub struct Stroke {
+    pub points: Vec<(Vec2,f32)>, // world coords
+    pub style: StrokeStyle,
+    pub samples: Vec<InkSample>, // raw input, empty for imported strokes
+    pub created: Option<u64>,
+}
+
+impl Stroke {
+    pub fn new(style: StrokeStyle) -> Self {
+        Self { points: Vec::new(), style, samples: Vec::new(), created: None }
+    }
+
+    pub fn add_point(&mut self, pos: Vec2, pressure: f32, zoom: f32) {
+        let thickness = pressure * (1.0 / zoom);
+        self.points.push((pos, thickness));
+    }
+
+    pub fn simplify(&mut self, epsilon: f32, zoom: f32) {
+        let epsilon_in_world = epsilon * (1.0 / zoom);
+        self.points = ramer_douglas_peucker(&self.points, epsilon_in_world);
+    }
+
+    /// Turns the raw input points into the curve that is stored and drawn.
+    pub fn smooth(&mut self, zoom: f32) {
+        self.simplify(1.0, zoom); // optional
+        let segments = 10;
+        self.points = catmull_rom_spline(&self.points, segments);
+    }
+
+    pub fn translated(&self, delta: Vec2) -> Stroke {
+        let points = self.points.iter().map(|(pos, th)| (*pos + delta, *th)).collect();
+        let samples = self.samples.iter()
+            .map(|s| InkSample { pos: [s.pos[0] + delta.x, s.pos[1] + delta.y], ..*s })
+            .collect();
+        Stroke { points, style: self.style, samples, created: self.created }
+    }
+}
+
+impl PartialEq for Stroke {
+    fn eq(&self, other: &Self) -> bool {
+        self.points == other.points && self.style == other.style
+    }
+}
+
+impl From<&Stroke> for StrokeData {
+    fn from(stroke: &Stroke) -> Self {
+        let points = stroke.points.iter()
+            .map(|(pos,th)| ([pos.x,pos.y], *th))
+            .collect();
+        StrokeData { points, style: stroke.style, samples: stroke.samples.clone(), created: stroke.created }
+    }
+}
+
+impl From<StrokeData> for Stroke {
+    fn from(sd: StrokeData) -> Self {
+        let mut stroke = Stroke::new(sd.style);
+        for (p, press) in sd.points {
+            stroke.points.push((vec2(p[0], p[1]), press));
+        }
+        stroke.samples = sd.samples;
+        stroke.created = sd.created;
+        stroke
+    }
+}
+
+
+// kp
+const CAP_SEGMENTS: usize = 8; 
+fn draw_cap(
+    vertices: &mut Vec<Vertex>,
+    indices: &mut Vec<u16>,
+    start_left: Vec2,
+    start_right: Vec2,
+    color: [u8;4],
+    normal: [f32;4],
+) {
+    let center = (start_left + start_right)*0.5;
+    let cap_radius = start_left.distance(start_right)*0.5;
+
+    let angle_left = (start_left - center).angle_between(vec2(1.0,0.0));
+    let angle_right = (start_right - center).angle_between(vec2(1.0,0.0));
+
+    let mut a0 = angle_left;
+    let mut a1 = angle_right;
+    if a1 < a0 {
+        a1 += std::f32::consts::TAU;
+    }
+
+    let arc = a1 - a0;
+
+    if arc > std::f32::consts::PI {
+        let temp = a0;
+        a0 = a1;
+        a1 = temp + std::f32::consts::TAU; 
+        let arc2 = a1 - a0;
+        if arc2 > std::f32::consts::PI {
+            a1 = a0 + std::f32::consts::PI;
+        }
+    } else {
+        a1 = a0 + std::f32::consts::PI;
+    }
+
+    let first_cap_index = vertices.len() as u16;
+    vertices.push(Vertex {
+        position: Vec3::new(center.x, center.y, 0.0),
+        uv: Vec2::new(0.0,0.0),
+        color,
+        normal: normal.into(),
+    });
+
+    for j in 0..=CAP_SEGMENTS {
+        let t = j as f32 / CAP_SEGMENTS as f32;
+        let angle = a0 + t*(a1 - a0);
+        let vx = center.x + angle.cos()*cap_radius;
+        let vy = center.y + angle.sin()*cap_radius;
+        vertices.push(Vertex {
+            position: Vec3::new(vx,vy,0.0),
+            uv: Vec2::new(0.0,0.0),
+            color,
+            normal: normal.into(),
+        });
+    }
+
+    for j in 0..CAP_SEGMENTS {
+        let center_i = first_cap_index;
+        let v1 = first_cap_index + 1 + j as u16;
+        let v2 = first_cap_index + 2 + j as u16;
+        indices.push(center_i);
+        indices.push(v1);
+        indices.push(v2);
+    }
+}
+
+// extends the stroke end by half its width, `outward` points away from the stroke
+fn draw_square_cap(
+    vertices: &mut Vec<Vertex>,
+    indices: &mut Vec<u16>,
+    left: Vec2,
+    right: Vec2,
+    outward: Vec2,
+    color: [u8;4],
+    normal: [f32;4],
+) {
+    let extent = outward * left.distance(right) * 0.5;
+    let first = vertices.len() as u16;
+    for pos in [left, right, left + extent, right + extent] {
+        vertices.push(Vertex {
+            position: Vec3::new(pos.x, pos.y, 0.0),
+            uv: Vec2::new(0.0,0.0),
+            color,
+            normal: normal.into(),
+        });
+    }
+    indices.extend_from_slice(&[first, first + 1, first + 2, first + 2, first + 1, first + 3]);
+}
+
+fn build_stroke_mesh_chunk(
+    points: &[(Vec2, f32)],
+    style: &StrokeStyle,
+    draw_start_cap: bool,
+    draw_end_cap: bool,
+) -> Mesh {
+    if points.len() < 2 {
+        return Mesh {
+            vertices: Vec::new(),
+            indices:  Vec::new(),
+            texture:  None,
+        };
+    }
+
+    let n = points.len();
+    let mut vertices = Vec::with_capacity(n * 2);
+    let mut indices  = Vec::with_capacity((n - 1) * 6);
+
+    // direction array
+    let mut directions = Vec::with_capacity(n);
+    for i in 0..n {
+        let dir = if i == n - 1 {
+            // last point
+            let prev = points[i - 1].0;
+            let curr = points[i].0;
+            (curr - prev).normalize()
+        } else {
+            // any other point
+            let curr = points[i].0;
+            let nxt  = points[i + 1].0;
+            (nxt - curr).normalize()
+        };
+        directions.push(dir);
+    }
+
+    let color  = style.color();
+    let c      = color_u8(color);
+    let normal = [0.0, 0.0, 1.0, 0.0];
+
+    // 2 vertices per stroke point
+    for i in 0..n {
+        let (pos, radius) = points[i];
+        let radius = radius * style.width;
+        let dir  = directions[i];
+        let perp = vec2(-dir.y, dir.x);
+
+        let left_pos  = pos + perp * radius;
+        let right_pos = pos - perp * radius;
+
+        vertices.push(Vertex {
+            position: Vec3::new(left_pos.x, left_pos.y, 0.0),
+            uv:       Vec2::new(0.0, 0.0),
+            color:    c,
+            normal:   normal.into(),
+        });
+
+        vertices.push(Vertex {
+            position: Vec3::new(right_pos.x, right_pos.y, 0.0),
+            uv:       Vec2::new(0.0, 0.0),
+            color:    c,
+            normal:   normal.into(),
+        });
+    }
+
+    // indices 2 triangles per segment
+    for i in 0..(n - 1) {
+        let i0 = (i * 2) as u16;
+        let i1 = (i * 2 + 1) as u16;
+        let i2 = ((i + 1) * 2) as u16;
+        let i3 = ((i + 1) * 2 + 1) as u16;
+
+        indices.push(i0); indices.push(i1); indices.push(i2);
+        indices.push(i2); indices.push(i1); indices.push(i3);
+    }
+
+    if draw_start_cap {
+        // draw cap at the first segment start
+        let start_left  = vertices[0].position.truncate();
+        let start_right = vertices[1].position.truncate();
+        match style.cap {
+            CapStyle::Round => draw_cap(&mut vertices, &mut indices, start_left, start_right, c, normal),
+            CapStyle::Square => draw_square_cap(&mut vertices, &mut indices, start_left, start_right, -directions[0], c, normal),
+            CapStyle::Butt => {}
+        }
+    }
+    if draw_end_cap {
+        // draw cap at the final segment end
+        let end_left_i  = 2 * (n - 1);
+        let end_right_i = 2 * (n - 1) + 1;
+        let end_left  = vertices[end_left_i].position.truncate();
+        let end_right = vertices[end_right_i].position.truncate();
+        match style.cap {
+            CapStyle::Round => draw_cap(&mut vertices, &mut indices, end_left, end_right, c, normal),
+            CapStyle::Square => draw_square_cap(&mut vertices, &mut indices, end_left, end_right, directions[n - 1], c, normal),
+            CapStyle::Butt => {}
+        }
+    }
+
+    Mesh {
+        vertices,
+        indices,
+        texture: None,
+    }
+}
+
+// ? kp was hier abgeht
+pub fn stroke_to_world_submeshes(
+    points: &[(Vec2, f32)],
+    style: &StrokeStyle,
+    max_chunk_points: usize
+) -> Vec<Mesh> {
+    if points.len() < 2 {
+        return Vec::new();
+    }
+
+    let mut result = Vec::new();
+    let n = points.len();
+    let mut start = 0;
+
+    while start < n {
+        let mut end = (start + max_chunk_points).min(n - 1);
+        let is_last_chunk = end == n - 1;
+
+        if !is_last_chunk {
+            end += 1;
+        }
+
+        let sub_points = &points[start..=end];
+
+        let draw_start_cap = start == 0;
+        let draw_end_cap   = end == n - 1;
+
+        let mesh = build_stroke_mesh_chunk(sub_points, style, draw_start_cap, draw_end_cap);
+        result.push(mesh);
+
+        if !is_last_chunk {
+            start = end - 1;
+        } else {
+            // done
+            start = end + 1;
+        }
+    }
+
+    result
+}
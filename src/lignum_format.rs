@@ -0,0 +1,197 @@
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use serde_json::{self, Value};
+use std::io::{self, Read, Write};
+
+use crate::file_format::{self, CanvasData, InkSample};
+use crate::persistence::{LoadReport, PersistenceError};
+use crate::point_codec::{decode_points, decode_samples, encode_points, encode_samples, read_varint, write_varint, Point};
+
+// Layout of a .lignum file:
+//
+//   "LGNM"                    magic
+//   u8                        container version
+//   deflate stream of
+//     varint + bytes          the document as json, with every stroke's points and samples left empty
+//     varint                  number of strokes
+//     per stroke              its point stream followed by its sample stream (see point_codec)
+//
+// The json skeleton carries the regular `format_version`, so binary files go
+// through the same migrations as json files. Container version 1 had no
+// sample streams and kept the samples in the skeleton.
+
+pub const MAGIC: &[u8; 4] = b"LGNM";
+const CONTAINER_VERSION: u8 = 2;
+const SAMPLES_IN_SKELETON: u8 = 1;
+
+pub fn is_lignum(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub fn canvas_to_lignum(data: &mut CanvasData) -> io::Result<Vec<u8>> {
+    let mut streams = Vec::new();
+    let mut count = 0;
+    let mut taken = Vec::new();
+    for stroke in data.strokes_mut() {
+        encode_points(&stroke.points, &mut streams);
+        encode_samples(&stroke.samples, &mut streams);
+        taken.push((std::mem::take(&mut stroke.points), std::mem::take(&mut stroke.samples)));
+        count += 1;
+    }
+    let skeleton = serde_json::to_vec(data);
+    for (stroke, (points, samples)) in data.strokes_mut().zip(taken) {
+        stroke.points = points;
+        stroke.samples = samples;
+    }
+    let skeleton = skeleton?;
+
+    let mut body = Vec::with_capacity(skeleton.len() + streams.len() + 16);
+    write_varint(&mut body, skeleton.len() as u64);
+    body.extend_from_slice(&skeleton);
+    write_varint(&mut body, count as u64);
+    body.extend_from_slice(&streams);
+
+    let mut out = Vec::with_capacity(body.len() / 2);
+    out.extend_from_slice(MAGIC);
+    out.push(CONTAINER_VERSION);
+    let mut encoder = DeflateEncoder::new(out, Compression::default());
+    encoder.write_all(&body)?;
+    encoder.finish()
+}
+
+pub fn canvas_from_lignum(bytes: &[u8]) -> Result<(CanvasData, LoadReport), PersistenceError> {
+    let invalid = |msg: &str| PersistenceError::Parse(msg.to_string());
+
+    if !is_lignum(bytes) {
+        return Err(invalid("not a lignum file"));
+    }
+    let container = match bytes.get(MAGIC.len()) {
+        Some(&found @ (SAMPLES_IN_SKELETON | CONTAINER_VERSION)) => found,
+        Some(&found) => return Err(PersistenceError::UnsupportedVersion {
+            found: found as u32,
+            supported: CONTAINER_VERSION as u32,
+        }),
+        None => return Err(invalid("truncated header")),
+    };
+
+    let mut report = LoadReport::default();
+
+    // a truncated deflate stream still yields everything before the damage
+    let mut body = Vec::new();
+    if DeflateDecoder::new(&bytes[MAGIC.len() + 1..]).read_to_end(&mut body).is_err() {
+        report.truncated = true;
+    }
+
+    let mut pos = 0;
+    let skeleton_len = read_varint(&body, &mut pos).ok_or_else(|| invalid("truncated header"))? as usize;
+    let skeleton = body.get(pos..pos.saturating_add(skeleton_len)).ok_or_else(|| invalid("truncated header"))?;
+    pos += skeleton_len;
+
+    let mut doc: Value = serde_json::from_slice(skeleton)?;
+    file_format::check_version(&doc)?;
+    file_format::migrate(&mut doc);
+    let (mut data, kept) = file_format::deserialize_document(doc, &mut report)?;
+
+    // streams are stored for every stroke, including ones the skeleton lost
+    let count = read_varint(&body, &mut pos).unwrap_or(0) as usize;
+    let mut streams = Vec::with_capacity(count);
+    while streams.len() < count {
+        match decode_stroke(&body, &mut pos, container) {
+            Some(stream) => streams.push(Some(stream)),
+            // everything after a damaged stream is unreadable
+            None => break,
+        }
+    }
+
+    for (stroke, stored) in data.strokes_mut().zip(&kept) {
+        let (points, samples) = streams.get_mut(*stored).and_then(Option::take).unwrap_or_default();
+        stroke.points = points;
+        if container != SAMPLES_IN_SKELETON {
+            stroke.samples = samples;
+        }
+    }
+    file_format::drop_invalid_strokes(&mut data, &kept, &mut report);
+
+    Ok((data, report))
+}
+
+fn decode_stroke(body: &[u8], pos: &mut usize, container: u8) -> Option<(Vec<Point>, Vec<InkSample>)> {
+    let points = decode_points(body, pos)?;
+    let samples = match container {
+        SAMPLES_IN_SKELETON => Vec::new(),
+        _ => decode_samples(body, pos)?,
+    };
+    Some((points, samples))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_format::{tests::expected, FORMAT_VERSION};
+
+    fn body(bytes: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        DeflateDecoder::new(&bytes[MAGIC.len() + 1..]).read_to_end(&mut body).unwrap();
+        body
+    }
+
+    fn container(version: u8, body: &[u8]) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(version);
+        let mut encoder = DeflateEncoder::new(out, Compression::default());
+        encoder.write_all(body).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn samples_stay_out_of_the_skeleton() {
+        let mut data = expected(FORMAT_VERSION);
+        assert!(data.strokes_mut().any(|s| !s.samples.is_empty()));
+        let bytes = canvas_to_lignum(&mut data).unwrap();
+
+        let body = body(&bytes);
+        let mut pos = 0;
+        let len = read_varint(&body, &mut pos).unwrap() as usize;
+        let skeleton: Value = serde_json::from_slice(&body[pos..pos + len]).unwrap();
+        for layer in skeleton["layers"].as_array().unwrap() {
+            for stroke in layer["strokes"].as_array().unwrap() {
+                assert!(stroke.get("samples").is_none());
+            }
+        }
+
+        let (loaded, report) = canvas_from_lignum(&bytes).unwrap();
+        assert!(report.is_clean());
+        assert_eq!(serde_json::to_value(&loaded).unwrap(), serde_json::to_value(&data).unwrap());
+    }
+
+    #[test]
+    fn reads_samples_from_version_1_skeleton() {
+        let mut data = expected(FORMAT_VERSION);
+        let original = serde_json::to_value(&data).unwrap();
+
+        // version 1 only moved the points out of the json
+        let mut streams = Vec::new();
+        for stroke in data.strokes_mut() {
+            encode_points(&std::mem::take(&mut stroke.points), &mut streams);
+        }
+        let skeleton = serde_json::to_vec(&data).unwrap();
+        let mut body = Vec::new();
+        write_varint(&mut body, skeleton.len() as u64);
+        body.extend_from_slice(&skeleton);
+        write_varint(&mut body, data.strokes_mut().count() as u64);
+        body.extend_from_slice(&streams);
+
+        let (loaded, report) = canvas_from_lignum(&container(SAMPLES_IN_SKELETON, &body)).unwrap();
+        assert!(report.is_clean());
+        assert_eq!(serde_json::to_value(&loaded).unwrap(), original);
+    }
+
+    #[test]
+    fn rejects_newer_container() {
+        let mut data = expected(FORMAT_VERSION);
+        let mut bytes = canvas_to_lignum(&mut data).unwrap();
+        bytes[MAGIC.len()] = CONTAINER_VERSION + 1;
+        assert!(matches!(canvas_from_lignum(&bytes), Err(PersistenceError::UnsupportedVersion { .. })));
+    }
+}
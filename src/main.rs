@@ -1,249 +1,1355 @@
-mod read_stylus;
-mod utility;
-mod command;
-
-use command::{Command, CommandStack};
-use macroquad::{math, prelude::*};
+use lignum_draw::{
+    canvas_image, cli, command, config, export, file_format, journal, layer, notification, page,
+    pdf_background, persistence, pressure_curve, raster, read_stylus, recent_files, stroke_style,
+    stylus_device, svg_format, tablet_profile, text_box, timestamp, utility,
+    stroke_to_world_submeshes, Stroke, ToolMode,
+};
+use canvas_image::{CanvasImage, ImageId};
+use cli::Args;
+use command::{Command, CommandStack, MovedContent, MovedStroke, RemovedContent};
+use config::Config;
+use file_format::{CanvasData, DocumentMeta, InkSample, FORMAT_VERSION};
+use macroquad::prelude::*;
 use miniquad::window::set_mouse_cursor;
+use journal::{CrashedSession, Journal};
+use layer::{Layer, LayerId, LayerProps, LayerTarget};
 use miniquad::CursorIcon;
-use read_stylus::{read_input, StylusEvent};
-use rfd::FileDialog;
-use serde::{Serialize, Deserialize};
-use serde_json::{self};
-use std::fs::File;
-use std::io::{Write, Read};
+use notification::Notifications;
+use page::{Orientation, Page, PageLayout};
+use pdf_background::{PdfBackground, PdfPage};
+use persistence::LoadReport;
+use pressure_curve::CurveEditor;
+use read_stylus::{read_input, StylusEvent, Tool};
+use recent_files::RecentFiles;
+use rfd::{FileDialog, MessageButtons, MessageDialog, MessageDialogResult, MessageLevel};
+use stroke_style::{StrokeStyle, ToolKind, PALETTE};
+use stylus_device::{Candidate, Discovery, StylusId};
+use tablet_profile::{ButtonAction, Tablet, TabletProfile};
+use text_box::{TextBox, TextId};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::{self};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 use utility::*;
 
 
 
-#[derive(Serialize, Deserialize)]
-struct StrokeData {
-    points: Vec<([f32;2], f32)>,
-}
-
-#[derive(Serialize, Deserialize)]
-struct CanvasData {
-    strokes: Vec<StrokeData>,
-    tool_mode: ToolMode,
-    offset: [f32;2],
-    zoom: f32,
-}
-
-#[derive(PartialEq, Serialize, Deserialize, Clone)]
-enum ToolMode {
-    Pen,
-    Eraser,
-}
-
-#[derive(Clone)]
-struct Stroke {
-    points: Vec<(Vec2,f32)>, // world coords
-}
-
-impl Stroke {
-    fn new() -> Self {
-        Self { points: Vec::new() }
-    }
-
-    fn add_point(&mut self, pos: Vec2, pressure: f32, zoom: f32) {
-        let thickness = pressure * (1.0 / zoom);
-        self.points.push((pos, thickness));
-    }
-
-    fn simplify(&mut self, epsilon: f32, zoom: f32) {
-        let epsilon_in_world = epsilon * (1.0 / zoom);
-        self.points = ramer_douglas_peucker(&self.points, epsilon_in_world);
-    }
-}
-
-impl PartialEq for Stroke {
-    fn eq(&self, other: &Self) -> bool {
-        self.points == other.points
-    }
+/// An image being moved or resized with the select tool.
+struct ImageDrag {
+    image: Option<ImageId>, // None if the press started on empty canvas
+    start: Rect,
+    grab: Vec2,
+    resize: bool,
 }
 
-impl From<&Stroke> for StrokeData {
-    fn from(stroke: &Stroke) -> Self {
-        let points = stroke.points.iter()
-            .map(|(pos,th)| ([pos.x,pos.y], *th))
-            .collect();
-        StrokeData { points }
-    }
+/// The text box receiving keyboard input.
+struct TextEdit {
+    text: TextId,
+    before: Option<TextBox>, // None for a box created by this edit
+    caret: usize, // char index
+    grab: Option<Vec2>, // pen offset from the box corner while dragging it
 }
 
 struct InfiniteCanvas {
-    strokes: Vec<Stroke>,
-    stroke_cache: Vec<Option<Vec<Mesh>>>,
+    meta: DocumentMeta,
+    default_author: String,
+    layers: Vec<Layer>, // bottom to top, never empty
+    active_layer: usize,
+    next_layer_id: LayerId,
+    pages: PageLayout,
+    images: Vec<CanvasImage>,
+    next_image_id: ImageId,
+    image_textures: HashMap<ImageId, Option<Texture2D>>, // None if the png doesn't decode
+    selected_image: Option<ImageId>,
+    image_drag: Option<ImageDrag>,
+    texts: Vec<TextBox>,
+    next_text_id: TextId,
+    text_edit: Option<TextEdit>,
+    text_pressed: bool,
+    pdf: Option<PdfBackground>,
+    pdf_textures: HashMap<u32, Option<Texture2D>>, // by page index, None if rendering failed
+    pdf_renderer: String,
     current_stroke: Option<Stroke>,
+    stroke_blocked: bool,
     command_stack: CommandStack,
     offset: Vec2,
     last_offset: Vec2,
     zoom: f32,
     last_zoom: f32,
     current_pressure: f32,
+    current_force: f32, // raw pressure 0..1, before the width curve
+    current_tilt: Vec2,
+    stroke_started: Instant,
     stylus_btn_1_pressed: bool,
     last_btn_1_press: Instant,
     tool_mode: ToolMode,
+    current_style: StrokeStyle,
     last_stylus_screen_pos: Option<Vec2>,
+    notifications: Notifications,
+    document_path: Option<PathBuf>,
+    recent_files: RecentFiles,
+    recent_menu_open: bool,
+    layer_target: LayerTarget,
 }
 
 impl InfiniteCanvas {
     fn new() -> Self {
         let c= Self {
-            strokes:Vec::new(),
-            stroke_cache:Vec::new(),
+            meta:DocumentMeta { created:Some(timestamp::now_millis()), ..Default::default() },
+            default_author:String::new(),
+            layers:vec![Layer::new(0, LayerProps::named("Layer 1"))],
+            active_layer:0,
+            next_layer_id:1,
+            pages:PageLayout::Infinite,
+            images:Vec::new(),
+            next_image_id:0,
+            image_textures:HashMap::new(),
+            selected_image:None,
+            image_drag:None,
+            texts:Vec::new(),
+            next_text_id:0,
+            text_edit:None,
+            text_pressed:false,
+            pdf:None,
+            pdf_textures:HashMap::new(),
+            pdf_renderer:pdf_background::DEFAULT_RENDERER.to_string(),
             current_stroke:None,
+            stroke_blocked:false,
             command_stack: CommandStack::new(),
             offset:Vec2::ZERO,
             last_offset: Vec2::ZERO,
             zoom:1.0,
             last_zoom: 1.0,
             current_pressure:0.0,
+            current_force:0.0,
+            current_tilt:Vec2::ZERO,
+            stroke_started:Instant::now(),
             stylus_btn_1_pressed:false,
             last_btn_1_press:Instant::now()-Duration::from_secs(1),
             tool_mode:ToolMode::Pen,
+            current_style:StrokeStyle::default(),
             last_stylus_screen_pos:None,
+            notifications:Notifications::new(),
+            document_path:None,
+            recent_files:RecentFiles::load(),
+            recent_menu_open:false,
+            layer_target:LayerTarget::new(),
         };
         c.update_cursor_icon();
         c
     }
 
-    fn clear(&mut self) {
-        self.stroke_cache.clear();
-        self.strokes.clear();
-    }
-
     fn toggle_eraser(&mut self) {
         self.tool_mode=if self.tool_mode==ToolMode::Pen {ToolMode::Eraser} else {ToolMode::Pen};
         self.update_cursor_icon();
     }
 
+    fn set_eraser(&mut self, on: bool) {
+        if on!=(self.tool_mode==ToolMode::Eraser) {
+            self.tool_mode=if on {ToolMode::Eraser} else {ToolMode::Pen};
+            self.update_cursor_icon();
+        }
+    }
+
+    fn toggle_select(&mut self) {
+        self.tool_mode=if self.tool_mode==ToolMode::Select {ToolMode::Pen} else {ToolMode::Select};
+        self.image_drag=None;
+        self.update_cursor_icon();
+    }
+
+    fn toggle_text(&mut self) {
+        self.finish_text_edit();
+        self.tool_mode=if self.tool_mode==ToolMode::Text {ToolMode::Pen} else {ToolMode::Text};
+        self.update_cursor_icon();
+    }
+
     fn erase_stroke_at(&mut self, pos: Vec2) {
         let radius=10.0*(1.0/self.zoom);
+        let layer=&mut self.layers[self.active_layer];
+        if !layer.props.editable() {
+            return;
+        }
         let mut i=0;
-        while i<self.strokes.len() {
-            if stroke_intersect(&self.strokes[i], pos, radius) {
-                self.command_stack.push_undo(command::Command::RemoveStroke(self.strokes[i].clone()));
-                self.stroke_cache.remove(i);
-                self.strokes.remove(i);
+        while i<layer.strokes.len() {
+            if stroke_intersect(&layer.strokes[i], pos, radius) {
+                self.command_stack.push_undo(Command::RemoveStroke { layer: layer.id, stroke: layer.strokes[i].clone() });
+                layer.stroke_cache.remove(i);
+                layer.strokes.remove(i);
             } else {
                 i+=1;
             }
         }
     }
 
+    fn continue_stroke(&mut self, world_pos: Vec2, at: Instant) {
+        let layer=&self.layers[self.active_layer];
+        if !layer.props.editable() {
+            if !self.stroke_blocked {
+                let reason=if layer.props.locked {"locked"} else {"hidden"};
+                self.notifications.warning(format!("{} is {}, unlock or show it to draw", layer.props.name, reason));
+                self.stroke_blocked=true;
+            }
+            return;
+        }
+        if self.current_stroke.is_none() {
+            self.current_stroke=Some(Stroke::new(self.current_style));
+            self.stroke_started=at;
+        }
+        let stroke=self.current_stroke.as_mut().unwrap();
+        stroke.add_point(world_pos, self.current_pressure, self.zoom);
+        stroke.samples.push(InkSample {
+            pos:[world_pos.x,world_pos.y],
+            width:stroke.points.last().unwrap().1,
+            pressure:self.current_force,
+            tilt:[self.current_tilt.x,self.current_tilt.y],
+            time:at.saturating_duration_since(self.stroke_started).as_secs_f32()*1000.0,
+        });
+    }
+
+    /// Uses the active tool at a screen position while the pen is pressed,
+    /// finishes its use once the pen is lifted.
+    fn pen_input(&mut self, screen_pos: Vec2, at: Instant) {
+        if self.current_pressure>0.1 {
+            let world_pos=self.offset+(screen_pos*(1.0/self.zoom));
+            match self.tool_mode {
+                ToolMode::Pen=>{
+                    self.continue_stroke(world_pos, at);
+                }
+                ToolMode::Eraser=>{
+                    self.erase_stroke_at(world_pos);
+                }
+                ToolMode::Select=>{
+                    self.drag_image(world_pos);
+                }
+                ToolMode::Text=>{
+                    self.press_text(world_pos);
+                }
+            }
+        } else if self.current_pressure<0.1 {
+            match self.tool_mode {
+                ToolMode::Pen=>self.finalize_stroke(),
+                ToolMode::Select=>self.release_image(),
+                ToolMode::Text=>self.release_text(),
+                ToolMode::Eraser=>{}
+            }
+        }
+    }
+
     fn update_cursor_icon(&self) {
         match self.tool_mode {
             ToolMode::Pen=> set_mouse_cursor(CursorIcon::Crosshair),
             ToolMode::Eraser=> set_mouse_cursor(CursorIcon::NotAllowed),
+            ToolMode::Select=> set_mouse_cursor(CursorIcon::Move),
+            ToolMode::Text=> set_mouse_cursor(CursorIcon::Text),
         }
     }
 
     // TODO pass stroke as screern coords, so gets simplified regardless of zoom
     fn finalize_stroke(&mut self) {
         if let Some(mut stroke) = self.current_stroke.take() {
-            stroke.simplify(1.0, self.zoom); // optional
-            let segments = 10;
-            let smoothed = catmull_rom_spline(&stroke.points, segments);
-            stroke.points = smoothed;
-            self.command_stack.push_undo(command::Command::AddStroke(stroke.clone()));
-            self.strokes.push(stroke);
-            self.stroke_cache.push(None);
+            stroke.smooth(self.zoom);
+            stroke.created = Some(timestamp::now_millis());
+            let layer = &mut self.layers[self.active_layer];
+            self.command_stack.push_undo(Command::AddStroke { layer: layer.id, stroke: stroke.clone() });
+            layer.push_stroke(stroke);
         }
+        self.stroke_blocked = false;
     }
 
-    fn stroke_to_screen_cords(&self) -> Stroke {
-        todo!()   
-    }
-
-    fn save_to_json(&mut self) {
-        let data=CanvasData {
-            strokes:self.strokes.iter().map(|s| s.into()).collect(),
+    fn to_canvas_data(&self) -> CanvasData {
+        CanvasData {
+            format_version:FORMAT_VERSION,
+            meta:self.meta.clone(),
+            layers:self.layers.iter().map(|l| l.into()).collect(),
+            active_layer:self.active_layer,
+            pages:self.pages.clone(),
+            images:self.images.clone(),
+            texts:self.texts.clone(),
+            pdf:self.pdf.clone(),
             tool_mode:self.tool_mode.clone(),
             offset:[self.offset.x,self.offset.y],
             zoom:self.zoom,
+        }
+    }
+
+    fn apply_canvas_data(&mut self, data: CanvasData) {
+        self.meta = data.meta;
+        self.layers = data.layers.into_iter().map(Layer::from).collect();
+        if self.layers.is_empty() {
+            self.layers.push(Layer::new(0, LayerProps::named("Layer 1")));
+        }
+        self.active_layer = data.active_layer.min(self.layers.len() - 1);
+        self.next_layer_id = self.layers.iter().map(|l| l.id + 1).max().unwrap_or(0);
+        self.pages = data.pages;
+        self.images = data.images;
+        self.next_image_id = self.images.iter().map(|i| i.id + 1).max().unwrap_or(0);
+        self.image_textures.clear();
+        self.selected_image = None;
+        self.image_drag = None;
+        self.texts = data.texts;
+        self.next_text_id = self.texts.iter().map(|t| t.id + 1).max().unwrap_or(0);
+        self.text_edit = None;
+        self.set_pdf(data.pdf);
+
+        self.tool_mode = data.tool_mode;
+        self.offset = vec2(data.offset[0], data.offset[1]);
+        self.zoom = data.zoom;
+        self.update_cursor_icon();
+
+        // setup undo-redo stack
+        self.command_stack.clear();
+        self.command_stack.mark_saved();
+    }
+
+    fn is_dirty(&self) -> bool {
+        !self.command_stack.is_saved()
+    }
+
+    fn mark_saved(&mut self, path: PathBuf) {
+        self.command_stack.mark_saved();
+        if let Some(journal) = self.command_stack.journal_mut() {
+            journal.reset(Some(&path));
+        }
+        self.recent_files.add(&path);
+        // imported files are never written to, saving asks for a new name
+        self.document_path = if persistence::is_foreign(&path) { None } else { Some(path) };
+    }
+
+    // miniquad can't rename the window after it was created, so the title is
+    // drawn as a header on the canvas instead
+    fn title(&self) -> String {
+        let name = self.document_path.as_deref().map_or_else(|| "Untitled".to_string(), file_name);
+        if self.is_dirty() { format!("{} *", name) } else { name }
+    }
+
+    /// Saves in place, or asks for a file name if the canvas was never saved.
+    fn save(&mut self) -> bool {
+        match self.document_path.clone() {
+            Some(path) => self.save_to(path),
+            None => self.save_as(),
+        }
+    }
+
+    fn save_as(&mut self) -> bool {
+        let mut dialog=FileDialog::new()
+            .add_filter("lignum",&["lignum"])
+            .add_filter("json",&["json"]);
+        if let Some(path)=&self.document_path {
+            dialog=dialog.set_file_name(file_name(path));
+            if let Some(dir)=path.parent() {
+                dialog=dialog.set_directory(dir);
+            }
+        }
+        match dialog.save_file() {
+            Some(path)=>self.save_to(path),
+            None=>false,
+        }
+    }
+
+    fn save_to(&mut self, path: PathBuf) -> bool {
+        if self.meta.title.is_empty() {
+            self.meta.title=path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        }
+        if self.meta.author.is_empty() {
+            self.meta.author=self.default_author.clone();
+        }
+        self.meta.modified=Some(timestamp::now_millis());
+        let mut data=self.to_canvas_data();
+        match persistence::save_document(&path, &mut data) {
+            Ok(())=>{
+                self.notifications.info(format!("Saved {}", file_name(&path)));
+                self.mark_saved(path);
+                true
+            }
+            Err(e)=>{
+                self.notifications.error(format!("Could not save {}: {}", file_name(&path), e));
+                false
+            }
+        }
+    }
+
+    fn export(&mut self) {
+        let mut dialog=FileDialog::new()
+            .add_filter("pdf",&["pdf"])
+            .add_filter("svg",&["svg"])
+            .add_filter("png",&["png"])
+            .add_filter("xournal++",&["xopp"])
+            .add_filter("inkml",&["inkml"]);
+        if let Some(path)=&self.document_path {
+            dialog=dialog.set_file_name(format!("{}.pdf", path.file_stem().unwrap_or_default().to_string_lossy()));
+            if let Some(dir)=path.parent() {
+                dialog=dialog.set_directory(dir);
+            }
+        }
+        let Some(path)=dialog.save_file() else { return };
+        let render=raster::RenderOptions { pdf_renderer:self.pdf_renderer.clone(), ..Default::default() };
+        match export::export_document(&self.to_canvas_data(), &path, &render) {
+            Ok(summary)=>self.notifications.info(format!("Exported {} to {}", summary, file_name(&path))),
+            Err(e)=>self.notifications.error(format!("Could not export {}: {}", file_name(&path), e)),
+        }
+    }
+
+    /// Writes the imported pdf with the ink drawn over its pages.
+    fn export_annotated(&mut self) {
+        let Some(pdf)=&self.pdf else {
+            self.notifications.warning("Import a pdf with Ctrl+I to export it annotated");
+            return;
         };
+        let stem=Path::new(&pdf.name).file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        let mut dialog=FileDialog::new()
+            .add_filter("pdf",&["pdf"])
+            .set_file_name(format!("{}-annotated.pdf", stem));
+        if let Some(dir)=self.document_path.as_deref().and_then(Path::parent) {
+            dialog=dialog.set_directory(dir);
+        }
+        let Some(path)=dialog.save_file() else { return };
+        match export::export_annotated(&self.to_canvas_data(), &path) {
+            Ok(summary)=>self.notifications.info(format!("Exported {} to {}", summary, file_name(&path))),
+            Err(e)=>self.notifications.error(format!("Could not export {}: {}", file_name(&path), e)),
+        }
+    }
 
-        if let Some(path)=FileDialog::new().add_filter("json",&["json"]).save_file() {
-            let json=serde_json::to_string_pretty(&data).unwrap();
-            let mut file=File::create(path).unwrap();
-            file.write_all(json.as_bytes()).unwrap();
+    /// Asks for an svg, whose paths become a new layer, a png to place on
+    /// the canvas, or a pdf to annotate.
+    fn import(&mut self) {
+        let dialog=FileDialog::new()
+            .add_filter("svg / png / pdf",&["svg","png","pdf"])
+            .add_filter("svg",&["svg"])
+            .add_filter("png",&["png"])
+            .add_filter("pdf",&["pdf"]);
+        let Some(path)=dialog.pick_file() else { return };
+        if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png")) {
+            self.add_image(&path);
+        } else if pdf_background::is_pdf(&path) {
+            self.import_pdf(&path);
+        } else {
+            self.import_svg(&path);
         }
     }
 
-    fn load_from_json(&mut self) {
-        if let Some(path) = FileDialog::new().add_filter("json", &["json"]).pick_file() {
-            let mut file = File::open(path).unwrap();
-            let mut contents = String::new();
-            file.read_to_string(&mut contents).unwrap();
-            let data: CanvasData = serde_json::from_str(&contents).unwrap();
-    
-            self.strokes.clear();
-            for sd in data.strokes {
-                let mut stroke = Stroke::new();
-                for (p, press) in sd.points {
-                    stroke.points.push((vec2(p[0], p[1]), press));
+    /// Imports the paths of an svg file into a new layer above the active one.
+    fn import_svg(&mut self, path: &Path) {
+        let result=std::fs::read_to_string(path)
+            .map_err(persistence::PersistenceError::from)
+            .and_then(|text| svg_format::strokes_from_svg(&text));
+        let (strokes, report)=match result {
+            Ok(imported)=>imported,
+            Err(e)=>{
+                self.notifications.error(format!("Could not import {}: {}", file_name(path), e));
+                return;
+            }
+        };
+        for skipped in &report.skipped {
+            eprintln!("{}: skipped path {}: {}", path.display(), skipped.index, skipped.reason);
+        }
+
+        let id=self.next_layer_id;
+        let index=self.active_layer + 1;
+        let mut layer=Layer::new(id, LayerProps::named(file_name(path)));
+        let count=strokes.len();
+        for sd in strokes {
+            layer.push_stroke(Stroke::from(sd));
+        }
+        if self.insert_layer(index, layer.snapshot()) {
+            self.command_stack.push_undo(Command::AddLayer { index, layer });
+        }
+        if report.is_clean() {
+            self.notifications.info(format!("Imported {} stroke(s) from {}", count, file_name(path)));
+        } else {
+            self.notifications.warning(format!("Imported {} stroke(s) from {}, skipped {} path(s)", count, file_name(path), report.skipped.len()));
+        }
+    }
+
+    /// Shows the pages of a pdf behind the ink, from the A4 cell in the
+    /// middle of the view downwards. Replaces a pdf imported before.
+    fn import_pdf(&mut self, path: &Path) {
+        let center=self.offset + vec2(screen_width(), screen_height())*(0.5/self.zoom);
+        let result=std::fs::read(path)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| pdf_background::import(&file_name(path), bytes, page::a4_cell(center)));
+        let pdf=match result {
+            Ok(pdf)=>pdf,
+            Err(e)=>{
+                self.notifications.error(format!("Could not import {}: {}", file_name(path), e));
+                return;
+            }
+        };
+        let count=pdf.pages.len();
+        let comm=Command::SetPdf { before:self.pdf.clone(), after:Some(pdf) };
+        if self.apply_command(&comm) {
+            self.command_stack.push_undo(comm);
+            self.notifications.info(format!("Imported {} page(s) from {}, Ctrl+Shift+E exports them annotated", count, file_name(path)));
+        }
+    }
+
+    fn set_pdf(&mut self, pdf: Option<PdfBackground>) -> bool {
+        self.pdf=pdf;
+        self.pdf_textures.clear();
+        true
+    }
+
+    fn insert_pdf_page(&mut self, at: usize, page: PdfPage) -> bool {
+        let Some(pdf) = &mut self.pdf else { return false };
+        if pdf.pages.iter().any(|p| p.index == page.index) {
+            return false;
+        }
+        pdf.pages.insert(at.min(pdf.pages.len()), page);
+        true
+    }
+
+    fn remove_pdf_page(&mut self, index: u32) -> bool {
+        let Some(pdf) = &mut self.pdf else { return false };
+        let Some(at) = pdf.pages.iter().position(|p| p.index == index) else { return false };
+        pdf.pages.remove(at);
+        true
+    }
+
+    fn set_pdf_page_offset(&mut self, index: u32, offset: [f32;2]) -> bool {
+        let Some(page) = self.pdf.iter_mut().flat_map(|pdf| &mut pdf.pages).find(|p| p.index == index) else { return false };
+        page.offset = offset;
+        true
+    }
+
+    /// Imported pdf pages in view, each rendered the first time it shows up.
+    fn draw_pdf(&mut self, visible: Rect) {
+        let Some(pdf) = &self.pdf else { return };
+        for page in pdf.pages.iter().filter(|p| p.rect().overlaps(&visible)) {
+            let texture = self.pdf_textures.entry(page.index).or_insert_with(|| {
+                let rendered = pdf_background::render_pages(&self.pdf_renderer, &pdf.pdf, pdf_background::SCREEN_DPI, page.index, page.index);
+                let image = match rendered {
+                    Ok(mut pages) if !pages.is_empty() => pages.swap_remove(0).1,
+                    Ok(_) => return None,
+                    Err(e) => {
+                        // once per page, they are not retried until the pdf changes
+                        self.notifications.error(format!("Could not show page {} of {}: {}", page.index + 1, pdf.name, e));
+                        return None;
+                    }
+                };
+                let (w, h) = (u16::try_from(image.width).ok()?, u16::try_from(image.height).ok()?);
+                Some(Texture2D::from_rgba8(w, h, &image.pixels))
+            });
+            let r = page.rect();
+            let (x, y) = ((r.x - self.offset.x) * self.zoom, (r.y - self.offset.y) * self.zoom);
+            let (w, h) = (r.w * self.zoom, r.h * self.zoom);
+            match texture {
+                Some(texture) => {
+                    let params = DrawTextureParams { dest_size: Some(vec2(w, h)), ..Default::default() };
+                    draw_texture_ex(texture, x, y, WHITE, params);
+                }
+                None => {
+                    draw_rectangle(x, y, w, h, WHITE);
+                    draw_rectangle_lines(x, y, w, h, 1.0, Color::new(0.6, 0.6, 0.6, 1.0));
                 }
-                self.strokes.push(stroke);
             }
-    
-            self.tool_mode = data.tool_mode;
-            self.offset = vec2(data.offset[0], data.offset[1]);
-            self.zoom = data.zoom;
-            self.update_cursor_icon();
+        }
+    }
+
+    fn add_image(&mut self, path: &Path) {
+        let (png, decoded)=match canvas_image::load_png(path) {
+            Ok(loaded)=>loaded,
+            Err(e)=>{
+                self.notifications.error(format!("Could not insert {}: {}", file_name(path), e));
+                return;
+            }
+        };
+        let view=Rect::new(self.offset.x, self.offset.y, screen_width()/self.zoom, screen_height()/self.zoom);
+        let mut image=CanvasImage { id:self.next_image_id, rect:[0.0;4], png };
+        image.set_rect(canvas_image::placement(decoded.width, decoded.height, view));
+        let index=self.images.len();
+        if self.insert_image(index, image.clone()) {
+            self.selected_image=Some(image.id);
+            self.command_stack.push_undo(Command::AddImage { index, image });
+            self.notifications.info(format!("Inserted {}, press V to move or resize it", file_name(path)));
+        }
+    }
+
+    fn image_index(&self, id: ImageId) -> Option<usize> {
+        self.images.iter().position(|i| i.id == id)
+    }
+
+    fn insert_image(&mut self, index: usize, image: CanvasImage) -> bool {
+        if self.image_index(image.id).is_some() {
+            return false;
+        }
+        self.next_image_id = self.next_image_id.max(image.id + 1);
+        self.images.insert(index.min(self.images.len()), image);
+        true
+    }
+
+    fn remove_image(&mut self, id: ImageId) -> bool {
+        let Some(index) = self.image_index(id) else { return false };
+        self.images.remove(index);
+        self.image_textures.remove(&id);
+        if self.selected_image == Some(id) {
+            self.selected_image = None;
+        }
+        true
+    }
+
+    fn set_image_rect(&mut self, id: ImageId, rect: [f32;4]) -> bool {
+        let Some(index) = self.image_index(id) else { return false };
+        self.images[index].rect = rect;
+        true
+    }
+
+    fn delete_selected_image(&mut self) {
+        let Some(id) = self.selected_image else { return };
+        let Some(index) = self.image_index(id) else { return };
+        let image = self.images[index].clone();
+        if self.remove_image(id) {
+            self.command_stack.push_undo(Command::RemoveImage { index, image });
+        }
+    }
+
+    /// Select tool while pressed: grabs the image under the pen on the first
+    /// call, then moves it or, at its corner handle, resizes it.
+    fn drag_image(&mut self, pos: Vec2) {
+        let Some(drag) = &self.image_drag else {
+            let selected = self.selected_image.and_then(|id| self.image_index(id));
+            let (index, resize) = match selected {
+                Some(i) if canvas_image::on_handle(self.images[i].rect(), pos, self.zoom) => (Some(i), true),
+                _ => (canvas_image::image_at(&self.images, pos), false),
+            };
+            let image = index.map(|i| &self.images[i]);
+            self.selected_image = image.map(|i| i.id);
+            self.image_drag = Some(ImageDrag {
+                image: self.selected_image,
+                start: image.map_or(Rect::default(), CanvasImage::rect),
+                grab: pos,
+                resize,
+            });
+            return;
+        };
+        let Some(index) = drag.image.and_then(|id| self.image_index(id)) else { return };
+        let rect = if drag.resize {
+            canvas_image::resized(drag.start, pos, 8.0 / self.zoom)
+        } else {
+            drag.start.offset(pos - drag.grab)
+        };
+        self.images[index].set_rect(rect);
+    }
+
+    fn release_image(&mut self) {
+        let Some(drag) = self.image_drag.take() else { return };
+        let Some(id) = drag.image else { return };
+        let Some(index) = self.image_index(id) else { return };
+        let before = [drag.start.x, drag.start.y, drag.start.w, drag.start.h];
+        let after = self.images[index].rect;
+        if before != after {
+            self.command_stack.push_undo(Command::SetImageRect { image: id, before, after });
+        }
+    }
+
+    fn text_index(&self, id: TextId) -> Option<usize> {
+        self.texts.iter().position(|t| t.id == id)
+    }
+
+    fn insert_text(&mut self, index: usize, text: TextBox) -> bool {
+        if self.text_index(text.id).is_some() {
+            return false;
+        }
+        self.next_text_id = self.next_text_id.max(text.id + 1);
+        self.texts.insert(index.min(self.texts.len()), text);
+        true
+    }
+
+    fn remove_text(&mut self, id: TextId) -> bool {
+        let Some(index) = self.text_index(id) else { return false };
+        self.texts.remove(index);
+        true
+    }
+
+    fn replace_text(&mut self, text: TextBox) -> bool {
+        let Some(index) = self.text_index(text.id) else { return false };
+        self.texts[index] = text;
+        true
+    }
+
+    /// Text tool while pressed: the first call starts editing the box under
+    /// the pen, or a new one, later calls drag the box that was hit.
+    fn press_text(&mut self, pos: Vec2) {
+        if self.text_pressed {
+            if let Some(TextEdit { text, grab: Some(grab), .. }) = self.text_edit {
+                if let Some(index) = self.text_index(text) {
+                    let corner = pos - grab;
+                    self.texts[index].pos = [corner.x, corner.y];
+                }
+            }
+            return;
+        }
+        self.text_pressed = true;
+        if let Some(index) = text_box::text_at(&self.texts, pos) {
+            let text = &self.texts[index];
+            let grab = Some(pos - vec2(text.pos[0], text.pos[1]));
+            if let Some(edit) = self.text_edit.as_mut().filter(|e| e.text == text.id) {
+                edit.grab = grab;
+                return;
+            }
+            let (id, before, caret) = (text.id, Some(text.clone()), text.text.chars().count());
+            self.finish_text_edit();
+            self.text_edit = Some(TextEdit { text: id, before, caret, grab });
+            return;
+        }
+        self.finish_text_edit();
+        let text = TextBox::new(self.next_text_id, pos, text_box::DEFAULT_FONT_SIZE, self.current_style.color);
+        self.text_edit = Some(TextEdit { text: text.id, before: None, caret: 0, grab: None });
+        self.insert_text(self.texts.len(), text);
+    }
+
+    fn release_text(&mut self) {
+        self.text_pressed = false;
+        if let Some(edit) = &mut self.text_edit {
+            edit.grab = None;
+        }
+    }
+
+    /// Ends keyboard input and records what changed as one undo step.
+    /// Boxes left empty are removed.
+    fn finish_text_edit(&mut self) {
+        let Some(edit) = self.text_edit.take() else { return };
+        let Some(index) = self.text_index(edit.text) else { return };
+        let after = self.texts[index].clone();
+        if after.text.trim().is_empty() {
+            self.texts.remove(index);
+            if let Some(before) = edit.before {
+                self.command_stack.push_undo(Command::RemoveText { index, text: before });
+            }
+        } else if let Some(before) = edit.before {
+            if before != after {
+                self.command_stack.push_undo(Command::UpdateText { before, after });
+            }
+        } else {
+            self.command_stack.push_undo(Command::AddText { index, text: after });
+        }
+    }
+
+    /// Keyboard input for the edited text box. Ctrl with the arrow keys
+    /// changes the font size and wrapping width, with `4`-`9` the color.
+    fn type_text(&mut self, ctrl: bool) {
+        let chars: Vec<char> = std::iter::from_fn(get_char_pressed).collect();
+        let Some(edit) = &mut self.text_edit else { return };
+        let Some(index) = self.texts.iter().position(|t| t.id == edit.text) else { return };
+        let text = &mut self.texts[index];
+        if ctrl {
+            if is_key_pressed(KeyCode::Up) { text.font_size = (text.font_size + 2.0).min(200.0); }
+            if is_key_pressed(KeyCode::Down) { text.font_size = (text.font_size - 2.0).max(4.0); }
+            if is_key_pressed(KeyCode::Right) { text.wrap_width += 20.0; }
+            if is_key_pressed(KeyCode::Left) { text.wrap_width = (text.wrap_width - 20.0).max(text_box::MIN_WRAP_WIDTH); }
+            let color_keys = [KeyCode::Key4, KeyCode::Key5, KeyCode::Key6, KeyCode::Key7, KeyCode::Key8, KeyCode::Key9];
+            for (key, color) in color_keys.into_iter().zip(PALETTE) {
+                if is_key_pressed(key) {
+                    text.color = color.into();
+                }
+            }
+            return;
+        }
+        for c in chars.into_iter().filter(|c| !c.is_control()) {
+            edit.caret = text_box::insert_char(&mut text.text, edit.caret, c);
+        }
+        if is_key_pressed(KeyCode::Enter) || is_key_pressed(KeyCode::KpEnter) {
+            edit.caret = text_box::insert_char(&mut text.text, edit.caret, '\n');
+        }
+        if is_key_pressed(KeyCode::Backspace) && edit.caret > 0 {
+            edit.caret -= 1;
+            text_box::remove_char(&mut text.text, edit.caret);
+        }
+        if is_key_pressed(KeyCode::Delete) {
+            text_box::remove_char(&mut text.text, edit.caret);
+        }
+        let len = text.text.chars().count();
+        if is_key_pressed(KeyCode::Left) { edit.caret = edit.caret.saturating_sub(1); }
+        if is_key_pressed(KeyCode::Right) { edit.caret = (edit.caret + 1).min(len); }
+        if is_key_pressed(KeyCode::Home) { edit.caret = 0; }
+        if is_key_pressed(KeyCode::End) { edit.caret = len; }
+        if is_key_pressed(KeyCode::Escape) {
+            self.finish_text_edit();
+        }
+    }
 
+    fn draw_texts(&self, visible: Rect) {
+        let to_screen = |p: Vec2| (p - self.offset) * self.zoom;
+        for text in &self.texts {
+            let r = text.rect();
+            if !r.overlaps(&visible) {
+                continue;
+            }
+            let size = text.font_size * self.zoom;
+            let [red, green, blue, alpha] = text.color;
+            let params = TextParams {
+                font_size: size.round().max(1.0) as u16,
+                font_scale: size / size.round().max(1.0),
+                color: Color::new(red, green, blue, alpha),
+                ..Default::default()
+            };
+            for line in text.layout() {
+                let p = to_screen(line.baseline);
+                draw_text_ex(&line.text, p.x, p.y, params.clone());
+            }
+            if let Some(edit) = self.text_edit.as_ref().filter(|e| e.text == text.id) {
+                let tl = to_screen(vec2(r.x, r.y));
+                draw_rectangle_lines(tl.x - 2.0, tl.y - 2.0, r.w * self.zoom + 4.0, r.h * self.zoom + 4.0, 1.0, Color::new(0.2, 0.45, 0.9, 1.0));
+                let caret = to_screen(text.caret_position(edit.caret));
+                draw_line(caret.x, caret.y - text_box::ascent(size), caret.x, caret.y + size * 0.2, 1.5, Color::new(0.2, 0.45, 0.9, 1.0));
+            }
+        }
+    }
 
-            // setup cache
-            self.stroke_cache.clear();
-            for _ in &self.strokes {
-                self.stroke_cache.push(None);
+    fn draw_images(&mut self, visible: Rect) {
+        for image in &self.images {
+            let r = image.rect();
+            if !r.overlaps(&visible) {
+                continue;
+            }
+            let texture = self.image_textures.entry(image.id).or_insert_with(|| {
+                let decoded = canvas_image::decode_png(&image.png).ok()?;
+                let (w, h) = (u16::try_from(decoded.width).ok()?, u16::try_from(decoded.height).ok()?);
+                Some(Texture2D::from_rgba8(w, h, &decoded.pixels))
+            });
+            let (x, y) = ((r.x - self.offset.x) * self.zoom, (r.y - self.offset.y) * self.zoom);
+            let (w, h) = (r.w * self.zoom, r.h * self.zoom);
+            match texture {
+                Some(texture) => {
+                    let params = DrawTextureParams { dest_size: Some(vec2(w, h)), ..Default::default() };
+                    draw_texture_ex(texture, x, y, WHITE, params);
+                }
+                // keep undecodable images visible so they can be deleted
+                None => draw_rectangle(x, y, w, h, Color::new(0.6, 0.6, 0.6, 0.5)),
+            }
+            if self.selected_image == Some(image.id) {
+                let handle = canvas_image::HANDLE_SIZE;
+                draw_rectangle_lines(x, y, w, h, 2.0, Color::new(0.2, 0.45, 0.9, 1.0));
+                draw_rectangle(x + w - handle * 0.5, y + h - handle * 0.5, handle, handle, Color::new(0.2, 0.45, 0.9, 1.0));
             }
+        }
+    }
+
+    fn open(&mut self) {
+        if !self.confirm_discard() {
+            return;
+        }
+        let dialog=FileDialog::new()
+            .add_filter("lignum / json",&["lignum","json"])
+            .add_filter("rnote",&["rnote"])
+            .add_filter("xournal++",&["xopp","xoj"])
+            .add_filter("inkml",&["inkml"]);
+        if let Some(path) = dialog.pick_file() {
+            self.open_path(path);
+        }
+    }
 
-            // setup undo-redo stack
-            self.command_stack.clear();
+    fn open_recent(&mut self, index: usize) {
+        let Some(path) = self.recent_files.paths().get(index).cloned() else { return };
+        self.recent_menu_open = false;
+        if !self.confirm_discard() {
+            return;
+        }
+        if !self.open_path(path.clone()) && !path.exists() {
+            self.recent_files.remove(&path);
         }
     }
+
+    fn open_path(&mut self, path: PathBuf) -> bool {
+        match persistence::load_document(&path) {
+            Ok((data, report)) => {
+                self.apply_canvas_data(data);
+                self.report_load(&path, &report);
+                if persistence::is_foreign(&path) {
+                    self.notifications.info(format!("Imported {}, saving creates a new file", file_name(&path)));
+                }
+                self.mark_saved(path);
+                true
+            }
+            Err(e) => {
+                self.notifications.error(format!("Could not open {}: {}", file_name(&path), e));
+                false
+            }
+        }
+    }
+
+    /// Asks whether unsaved changes may be thrown away, offering to save them
+    /// first. Returns false if the user wants to keep working.
+    fn confirm_discard(&mut self) -> bool {
+        if !self.is_dirty() {
+            return true;
+        }
+        let answer = MessageDialog::new()
+            .set_level(MessageLevel::Warning)
+            .set_title("Unsaved changes")
+            .set_description(format!("Save changes to {} first?", self.title().trim_end_matches(" *")))
+            .set_buttons(MessageButtons::YesNoCancel)
+            .show();
+        match answer {
+            MessageDialogResult::Yes => self.save(),
+            MessageDialogResult::No => true,
+            _ => false,
+        }
+    }
+
+    fn report_load(&mut self, path: &Path, report: &LoadReport) {
+        if report.is_clean() {
+            return;
+        }
+        for skipped in &report.skipped {
+            eprintln!("{}: skipped stroke {}: {}", path.display(), skipped.index, skipped.reason);
+        }
+        self.notifications.warning(format!("Opened {} with problems: {}", file_name(path), report));
+    }
     
 
-    fn undo(&mut self) {
-        if let Some(comm) = self.command_stack.pop_undo() {
-            match comm {
-                Command::AddStroke(stroke) => {
-                    if let Some(idx) = self.strokes.iter().position(|s| *s == stroke) {
-                        self.strokes.remove(idx);
-                        self.stroke_cache.remove(idx); 
-                        self.command_stack.push_redo(Command::AddStroke(stroke));
-                    }
+    fn layer_index(&self, id: LayerId) -> Option<usize> {
+        self.layers.iter().position(|l| l.id == id)
+    }
+
+    fn insert_layer(&mut self, index: usize, layer: Layer) -> bool {
+        if self.layer_index(layer.id).is_some() {
+            return false;
+        }
+        let index = index.min(self.layers.len());
+        self.next_layer_id = self.next_layer_id.max(layer.id + 1);
+        self.layers.insert(index, layer);
+        self.active_layer = index;
+        true
+    }
+
+    fn remove_layer(&mut self, id: LayerId) -> bool {
+        // the canvas always keeps at least one layer
+        if self.layers.len() <= 1 {
+            return false;
+        }
+        let Some(index) = self.layer_index(id) else { return false };
+        self.layers.remove(index);
+        if self.active_layer >= index && self.active_layer > 0 {
+            self.active_layer -= 1;
+        }
+        true
+    }
+
+    fn move_layer(&mut self, id: LayerId, to: usize) -> bool {
+        let Some(from) = self.layer_index(id) else { return false };
+        if to >= self.layers.len() {
+            return false;
+        }
+        let active_id = self.layers[self.active_layer].id;
+        let layer = self.layers.remove(from);
+        self.layers.insert(to, layer);
+        self.active_layer = self.layer_index(active_id).unwrap_or(0);
+        true
+    }
+
+    fn set_layer_props(&mut self, id: LayerId, props: &LayerProps) -> bool {
+        let Some(index) = self.layer_index(id) else { return false };
+        self.layers[index].props = props.clone();
+        true
+    }
+
+    fn apply_command(&mut self, comm: &Command) -> bool {
+        match comm {
+            Command::AddStroke { layer, stroke } => {
+                let Some(index) = self.layer_index(*layer) else { return false };
+                self.layers[index].push_stroke(stroke.clone());
+                true
+            }
+            Command::RemoveStroke { layer, stroke } => {
+                let Some(index) = self.layer_index(*layer) else { return false };
+                self.layers[index].remove_stroke(stroke)
+            }
+            Command::AddLayer { index, layer } => self.insert_layer(*index, layer.snapshot()),
+            Command::RemoveLayer { layer, .. } => self.remove_layer(layer.id),
+            Command::MoveLayer { layer, to, .. } => self.move_layer(*layer, *to),
+            Command::SetLayerProps { layer, after, .. } => self.set_layer_props(*layer, after),
+            Command::EditPages { after, removed, moved, .. } => {
+                let mut ok = true;
+                for (layer, stroke) in &removed.strokes {
+                    ok &= self.layer_index(*layer).is_some_and(|i| self.layers[i].remove_stroke(stroke));
+                }
+                for (_, image) in &removed.images {
+                    ok &= self.remove_image(image.id);
+                }
+                for (_, text) in &removed.texts {
+                    ok &= self.remove_text(text.id);
+                }
+                for (_, page) in &removed.pdf_pages {
+                    ok &= self.remove_pdf_page(page.index);
+                }
+                for m in &moved.strokes {
+                    ok &= self.layer_index(m.layer).is_some_and(|i| self.layers[i].replace_stroke(&m.before, m.after.clone()));
                 }
-                Command::RemoveStroke(stroke) => {
-                    self.strokes.push(stroke.clone());
-                    self.stroke_cache.push(None); 
-                    self.command_stack.push_redo(Command::RemoveStroke(stroke));
+                for (image, _, rect) in &moved.images {
+                    ok &= self.set_image_rect(*image, *rect);
                 }
+                for (_, text) in &moved.texts {
+                    ok &= self.replace_text(text.clone());
+                }
+                for (page, _, offset) in &moved.pdf_pages {
+                    ok &= self.set_pdf_page_offset(*page, *offset);
+                }
+                self.pages = after.clone();
+                ok
+            }
+            Command::AddImage { index, image } => self.insert_image(*index, image.clone()),
+            Command::RemoveImage { image, .. } => self.remove_image(image.id),
+            Command::SetImageRect { image, after, .. } => self.set_image_rect(*image, *after),
+            Command::AddText { index, text } => self.insert_text(*index, text.clone()),
+            Command::RemoveText { text, .. } => self.remove_text(text.id),
+            Command::UpdateText { after, .. } => self.replace_text(after.clone()),
+            Command::SetPdf { after, .. } => self.set_pdf(after.clone()),
+        }
+    }
+
+    fn revert_command(&mut self, comm: &Command) -> bool {
+        match comm {
+            Command::AddStroke { layer, stroke } => {
+                let Some(index) = self.layer_index(*layer) else { return false };
+                self.layers[index].remove_stroke(stroke)
+            }
+            Command::RemoveStroke { layer, stroke } => {
+                let Some(index) = self.layer_index(*layer) else { return false };
+                self.layers[index].push_stroke(stroke.clone());
+                true
+            }
+            Command::AddLayer { layer, .. } => self.remove_layer(layer.id),
+            Command::RemoveLayer { index, layer } => self.insert_layer(*index, layer.snapshot()),
+            Command::MoveLayer { layer, from, .. } => self.move_layer(*layer, *from),
+            Command::SetLayerProps { layer, before, .. } => self.set_layer_props(*layer, before),
+            Command::EditPages { before, removed, moved, .. } => {
+                let mut ok = true;
+                for m in &moved.strokes {
+                    ok &= self.layer_index(m.layer).is_some_and(|i| self.layers[i].replace_stroke(&m.after, m.before.clone()));
+                }
+                for (image, rect, _) in &moved.images {
+                    ok &= self.set_image_rect(*image, *rect);
+                }
+                for (text, _) in &moved.texts {
+                    ok &= self.replace_text(text.clone());
+                }
+                for (page, offset, _) in &moved.pdf_pages {
+                    ok &= self.set_pdf_page_offset(*page, *offset);
+                }
+                for (layer, stroke) in &removed.strokes {
+                    let Some(index) = self.layer_index(*layer) else { ok = false; continue };
+                    self.layers[index].push_stroke(stroke.clone());
+                }
+                // in the order they were taken out, so each index is valid again
+                for (index, image) in &removed.images {
+                    ok &= self.insert_image(*index, image.clone());
+                }
+                for (index, text) in &removed.texts {
+                    ok &= self.insert_text(*index, text.clone());
+                }
+                for (at, page) in &removed.pdf_pages {
+                    ok &= self.insert_pdf_page(*at, page.clone());
+                }
+                self.pages = before.clone();
+                ok
+            }
+            Command::AddImage { image, .. } => self.remove_image(image.id),
+            Command::RemoveImage { index, image } => self.insert_image(*index, image.clone()),
+            Command::SetImageRect { image, before, .. } => self.set_image_rect(*image, *before),
+            Command::AddText { text, .. } => self.remove_text(text.id),
+            Command::RemoveText { index, text } => self.insert_text(*index, text.clone()),
+            Command::UpdateText { before, .. } => self.replace_text(before.clone()),
+            Command::SetPdf { before, .. } => self.set_pdf(before.clone()),
+        }
+    }
+
+    fn add_layer(&mut self) {
+        let id = self.next_layer_id;
+        let index = self.active_layer + 1;
+        let layer = Layer::new(id, LayerProps::named(format!("Layer {}", id + 1)));
+        if self.insert_layer(index, layer.snapshot()) {
+            self.command_stack.push_undo(Command::AddLayer { index, layer });
+        }
+    }
+
+    fn delete_active_layer(&mut self) {
+        if self.layers.len() <= 1 {
+            self.notifications.warning("The last layer can't be deleted");
+            return;
+        }
+        let index = self.active_layer;
+        let layer = self.layers[index].snapshot();
+        if self.remove_layer(layer.id) {
+            self.command_stack.push_undo(Command::RemoveLayer { index, layer });
+        }
+    }
+
+    /// Moves the active layer up (positive) or down (negative) in the stack.
+    fn move_active_layer(&mut self, delta: isize) {
+        let from = self.active_layer;
+        let to = from as isize + delta;
+        if to < 0 || to >= self.layers.len() as isize {
+            return;
+        }
+        let layer = self.layers[from].id;
+        if self.move_layer(layer, to as usize) {
+            self.command_stack.push_undo(Command::MoveLayer { layer, from, to: to as usize });
+        }
+    }
+
+    fn select_layer(&mut self, delta: isize) {
+        let index = (self.active_layer as isize + delta).clamp(0, self.layers.len() as isize - 1);
+        self.active_layer = index as usize;
+    }
+
+    fn update_active_layer(&mut self, update: impl FnOnce(&mut LayerProps)) {
+        let layer = &mut self.layers[self.active_layer];
+        let before = layer.props.clone();
+        update(&mut layer.props);
+        if layer.props != before {
+            self.command_stack.push_undo(Command::SetLayerProps { layer: layer.id, before, after: layer.props.clone() });
+        }
+    }
+
+    fn page_list(&self) -> &[Page] {
+        match &self.pages {
+            PageLayout::Pages(pages) => pages,
+            PageLayout::Infinite => &[],
+        }
+    }
+
+    /// The page in the middle of the screen, or the one closest to it.
+    fn current_page(&self) -> Option<usize> {
+        let center = self.offset + vec2(screen_width(), screen_height()) * (0.5 / self.zoom);
+        page::page_near(self.page_list(), center)
+    }
+
+    /// Switches between the endless canvas and a page list. The page list
+    /// starts out with a page for every A4 cell that already has ink, images
+    /// or text on it.
+    fn toggle_page_mode(&mut self) {
+        let after = match &self.pages {
+            PageLayout::Pages(_) => PageLayout::Infinite,
+            PageLayout::Infinite => {
+                let pdf_pages = self.pdf.iter().flat_map(|pdf| pdf.pages.iter().map(PdfPage::rect));
+                let areas: Vec<Rect> = self.images.iter().map(CanvasImage::rect).chain(self.texts.iter().map(TextBox::rect)).chain(pdf_pages).collect();
+                let mut pages = page::pages_from_ink(self.layers.iter().flat_map(|l| l.strokes.iter()), &areas);
+                if pages.is_empty() {
+                    let (x, y) = page::a4_cell(self.offset);
+                    pages.push(Page::a4(vec2(x as f32 * page::A4_SIZE[0], y as f32 * page::A4_SIZE[1])));
+                }
+                PageLayout::Pages(pages)
+            }
+        };
+        let comm = Command::EditPages {
+            before: self.pages.clone(),
+            after,
+            removed: RemovedContent::default(),
+            moved: MovedContent::default(),
+        };
+        if self.apply_command(&comm) {
+            self.command_stack.push_undo(comm);
+        }
+    }
+
+    /// Replaces the page list with `after`, where `origin[i]` is the old index
+    /// of page `i` (None for new pages). Strokes, images, text boxes and
+    /// imported pdf pages follow their page, the ones on pages missing from
+    /// `origin` are deleted.
+    fn rearrange_pages(&mut self, after: Vec<Page>, origin: &[Option<usize>]) {
+        // the box being typed into may move or go away
+        self.finish_text_edit();
+        let before = self.page_list().to_vec();
+        let deltas: Vec<Option<Vec2>> = (0..before.len())
+            .map(|old| {
+                let new = origin.iter().position(|o| *o == Some(old))?;
+                Some(after[new].top_left() - before[old].top_left())
+            })
+            .collect();
+
+        let mut removed = RemovedContent::default();
+        let mut moved = MovedContent::default();
+        for layer in &self.layers {
+            for stroke in &layer.strokes {
+                let Some(old) = page::page_of_stroke(&before, stroke) else { continue };
+                match deltas[old] {
+                    None => removed.strokes.push((layer.id, stroke.clone())),
+                    Some(delta) if delta != Vec2::ZERO => moved.strokes.push(MovedStroke {
+                        layer: layer.id,
+                        before: stroke.clone(),
+                        after: stroke.translated(delta),
+                    }),
+                    Some(_) => {}
+                }
+            }
+        }
+        for (index, image) in self.images.iter().enumerate() {
+            let Some(old) = page::page_of_rect(&before, image.rect()) else { continue };
+            match deltas[old] {
+                None => removed.images.push((index, image.clone())),
+                Some(delta) if delta != Vec2::ZERO => {
+                    let [x, y, w, h] = image.rect;
+                    moved.images.push((image.id, image.rect, [x + delta.x, y + delta.y, w, h]));
+                }
+                Some(_) => {}
+            }
+        }
+        for (index, text) in self.texts.iter().enumerate() {
+            let Some(old) = page::page_of_rect(&before, text.rect()) else { continue };
+            match deltas[old] {
+                None => removed.texts.push((index, text.clone())),
+                Some(delta) if delta != Vec2::ZERO => {
+                    let pos = [text.pos[0] + delta.x, text.pos[1] + delta.y];
+                    moved.texts.push((text.clone(), TextBox { pos, ..text.clone() }));
+                }
+                Some(_) => {}
+            }
+        }
+        for (at, pdf_page) in self.pdf.iter().flat_map(|pdf| pdf.pages.iter().enumerate()) {
+            let Some(old) = page::page_of_rect(&before, pdf_page.rect()) else { continue };
+            match deltas[old] {
+                None => removed.pdf_pages.push((at, pdf_page.clone())),
+                Some(delta) if delta != Vec2::ZERO => {
+                    let [x, y] = pdf_page.offset;
+                    moved.pdf_pages.push((pdf_page.index, pdf_page.offset, [x + delta.x, y + delta.y]));
+                }
+                Some(_) => {}
+            }
+        }
+
+        let comm = Command::EditPages {
+            before: self.pages.clone(),
+            after: PageLayout::Pages(after),
+            removed,
+            moved,
+        };
+        if self.apply_command(&comm) {
+            self.command_stack.push_undo(comm);
+        }
+    }
+
+    /// Inserts a page like the current one below it, pushing the following
+    /// pages down.
+    fn insert_page(&mut self) {
+        let Some(current) = self.current_page() else { return self.toggle_page_mode() };
+        let mut pages = self.page_list().to_vec();
+        let mut origin: Vec<Option<usize>> = (0..pages.len()).map(Some).collect();
+        let top = pages[current].position[1];
+        pages.insert(current + 1, pages[current].clone());
+        origin.insert(current + 1, None);
+        page::flow_pages(&mut pages, current, top);
+        self.rearrange_pages(pages, &origin);
+    }
+
+    /// Deletes the current page and its strokes, pulling the following pages up.
+    fn delete_page(&mut self) {
+        let Some(current) = self.current_page() else { return };
+        if self.page_list().len() <= 1 {
+            self.notifications.warning("The last page can't be deleted, switch to the endless canvas instead");
+            return;
+        }
+        let mut pages = self.page_list().to_vec();
+        let mut origin: Vec<Option<usize>> = (0..pages.len()).map(Some).collect();
+        let top = pages[current].position[1];
+        pages.remove(current);
+        origin.remove(current);
+        page::flow_pages(&mut pages, current, top);
+        self.rearrange_pages(pages, &origin);
+    }
+
+    /// Swaps the current page with the next (positive) or previous (negative)
+    /// one, the view follows the page.
+    fn move_page(&mut self, delta: isize) {
+        let Some(from) = self.current_page() else { return };
+        let to = from as isize + delta;
+        if to < 0 || to >= self.page_list().len() as isize {
+            return;
+        }
+        let to = to as usize;
+        let mut pages = self.page_list().to_vec();
+        let mut origin: Vec<Option<usize>> = (0..pages.len()).map(Some).collect();
+        let first = from.min(to);
+        let top = pages[first].position[1];
+        pages.swap(from, to);
+        origin.swap(from, to);
+        page::flow_pages(&mut pages, first, top);
+        let shift = pages[to].top_left() - self.page_list()[from].top_left();
+        self.rearrange_pages(pages, &origin);
+        self.offset += shift;
+    }
+
+    /// Changes a property of the current page, the pages below are reflowed
+    /// in case its height changed.
+    fn update_current_page(&mut self, update: impl FnOnce(&mut Page)) {
+        let Some(current) = self.current_page() else { return };
+        let mut pages = self.page_list().to_vec();
+        let origin: Vec<Option<usize>> = (0..pages.len()).map(Some).collect();
+        update(&mut pages[current]);
+        let top = pages[current].position[1];
+        page::flow_pages(&mut pages, current, top);
+        self.rearrange_pages(pages, &origin);
+    }
+
+    fn undo(&mut self) {
+        self.finish_text_edit();
+        if let Some(comm) = self.command_stack.pop_undo() {
+            if self.revert_command(&comm) {
+                self.command_stack.push_redo(comm);
             }
         }
     }
     
     
     fn redo(&mut self) {
+        self.finish_text_edit();
         if let Some(comm) = self.command_stack.pop_redo() {
-            match comm {
-                Command::AddStroke(stroke) => {
-                    self.strokes.push(stroke.clone());
-                    self.stroke_cache.push(None);
-                    self.command_stack.push_undo(Command::AddStroke(stroke));
-                }
-                Command::RemoveStroke(stroke) => {
-                    if let Some(idx) = self.strokes.iter().position(|s| *s == stroke) {
-                        self.strokes.remove(idx);
-                        self.stroke_cache.remove(idx);
-                        self.command_stack.push_undo(Command::RemoveStroke(stroke));
+            if self.apply_command(&comm) {
+                self.command_stack.push_redone(comm);
+            }
+        }
+    }
+
+    /// Returns whether the session was recovered.
+    fn offer_recovery(&mut self, session: CrashedSession) -> bool {
+        let base = session.document.as_deref().map_or_else(|| "an empty canvas".to_string(), file_name);
+        let answer = MessageDialog::new()
+            .set_level(MessageLevel::Warning)
+            .set_title("Recover unsaved changes?")
+            .set_description(format!(
+                "The last session did not shut down cleanly. Replay its {} unsaved change(s) onto {}?",
+                session.entries.len(), base
+            ))
+            .set_buttons(MessageButtons::YesNo)
+            .show();
+        if answer == MessageDialogResult::Yes {
+            self.recover(session);
+            true
+        } else {
+            session.discard();
+            false
+        }
+    }
+
+    fn recover(&mut self, mut session: CrashedSession) {
+        if let Some(path) = &session.document {
+            match persistence::load_document(path) {
+                Ok((data, report)) => {
+                    self.apply_canvas_data(data);
+                    self.report_load(path, &report);
+                    if !persistence::is_foreign(path) {
+                        self.document_path = Some(path.clone());
                     }
                 }
+                Err(e) => self.notifications.error(format!("Could not open {}, replaying onto an empty canvas: {}", file_name(path), e)),
             }
         }
+        if let Some(journal) = self.command_stack.journal_mut() {
+            journal.reset(session.document.as_deref());
+        }
+
+        // replayed commands are journaled again, so the recovery survives another crash
+        let total = session.entries.len();
+        let mut stack = std::mem::take(&mut self.command_stack);
+        let replayed = session.replay(&mut stack, |comm, apply| {
+            if apply { self.apply_command(comm) } else { self.revert_command(comm) }
+        });
+        self.command_stack = stack;
+        session.discard();
+
+        if replayed == total {
+            self.notifications.info(format!("Recovered {} change(s)", replayed));
+        } else {
+            self.notifications.warning(format!("Recovered {} of {} change(s), the rest no longer applied", replayed, total));
+        }
     }
     
     
@@ -254,32 +1360,43 @@ impl InfiniteCanvas {
         let offset_changed = self.offset != self.last_offset;
         let zoom_changed = (self.zoom - self.last_zoom).abs() > f32::EPSILON;
 
-        clear_background(WHITE);
-
-        let a4_w = 595.0;
-        let a4_h = 842.0;
-
         let visible_left = self.offset.x;
         let visible_top = self.offset.y;
         let visible_right = self.offset.x + screen_w/self.zoom;
         let visible_bottom = self.offset.y + screen_h/self.zoom;
 
-        let start_x = (visible_left/a4_w).floor() as i32 - 1;
-        let end_x = (visible_right/a4_w).ceil() as i32 + 1;
-        let start_y = (visible_top/a4_h).floor() as i32 - 1;
-        let end_y = (visible_bottom/a4_h).ceil() as i32 + 1;
-
-        for x in start_x..=end_x {
-            for y in start_y..=end_y {
-                let page_topleft_world = vec2(x as f32 * a4_w, y as f32 * a4_h);
-                let px0 = (page_topleft_world.x - self.offset.x)*self.zoom;
-                let py0 = (page_topleft_world.y - self.offset.y)*self.zoom;
-                let pw = a4_w * self.zoom;
-                let ph = a4_h * self.zoom;
-
-                draw_rectangle_lines(px0, py0, pw, ph, 1.0, Color::new(0.0,0.0,0.0,0.5));
+        match &self.pages {
+            PageLayout::Infinite => {
+                clear_background(WHITE);
+
+                let [a4_w, a4_h] = page::A4_SIZE;
+                let start_x = (visible_left/a4_w).floor() as i32 - 1;
+                let end_x = (visible_right/a4_w).ceil() as i32 + 1;
+                let start_y = (visible_top/a4_h).floor() as i32 - 1;
+                let end_y = (visible_bottom/a4_h).ceil() as i32 + 1;
+
+                for x in start_x..=end_x {
+                    for y in start_y..=end_y {
+                        let page_topleft_world = vec2(x as f32 * a4_w, y as f32 * a4_h);
+                        let px0 = (page_topleft_world.x - self.offset.x)*self.zoom;
+                        let py0 = (page_topleft_world.y - self.offset.y)*self.zoom;
+                        let pw = a4_w * self.zoom;
+                        let ph = a4_h * self.zoom;
+
+                        draw_rectangle_lines(px0, py0, pw, ph, 1.0, Color::new(0.0,0.0,0.0,0.5));
+                    }
+                }
+            }
+            PageLayout::Pages(pages) => {
+                clear_background(Color::new(0.82, 0.82, 0.84, 1.0));
+                let visible = Rect::new(visible_left, visible_top, visible_right - visible_left, visible_bottom - visible_top);
+                for page in pages.iter().filter(|p| p.rect().overlaps(&visible)) {
+                    page::draw_page(page, self.offset, self.zoom);
+                }
             }
         }
+        self.draw_pdf(Rect::new(visible_left, visible_top, visible_right - visible_left, visible_bottom - visible_top));
+        self.draw_images(Rect::new(visible_left, visible_top, visible_right - visible_left, visible_bottom - visible_top));
 
         let start_total = Instant::now();
 
@@ -288,47 +1405,69 @@ impl InfiniteCanvas {
         let mut drawing_time = Duration::ZERO;
         let mut mesh_count = 0;
     
-        for (i, stroke) in self.strokes.iter().enumerate() {
-            // First check the broad visibility
-            if is_stroke_visible(stroke, self.offset, self.zoom, screen_w * 5.0, screen_h * 5.0) {
-                // Only build submeshes if not cached:
-                if self.stroke_cache[i].is_none() {
-                    let meshing_start = Instant::now();
-                    let submeshes = stroke_to_world_submeshes(&stroke.points, 800);
-                    self.stroke_cache[i] = Some(submeshes);
-                    let meshing_end = Instant::now();
-                    meshing_time += meshing_end - meshing_start;
-                }
+        for (index, layer) in self.layers.iter_mut().enumerate() {
+            if !layer.props.visible {
+                continue;
+            }
+            // translucent layers are drawn offscreen and composited once
+            let translucent = layer.props.opacity < 1.0;
+            if translucent {
+                self.layer_target.begin();
+            }
+            for (i, stroke) in layer.strokes.iter().enumerate() {
+                // First check the broad visibility
+                if is_stroke_visible(stroke, self.offset, self.zoom, screen_w * 5.0, screen_h * 5.0) {
+                    // Only build submeshes if not cached:
+                    if layer.stroke_cache[i].is_none() {
+                        let meshing_start = Instant::now();
+                        let submeshes = stroke_to_world_submeshes(&stroke.points, &stroke.style, 800);
+                        layer.stroke_cache[i] = Some(submeshes);
+                        let meshing_end = Instant::now();
+                        meshing_time += meshing_end - meshing_start;
+                    }
     
-                // If we have submeshes in the cache:
-                if let Some(ref submeshes) = self.stroke_cache[i] {
-                    // Now check finer visibility (screen-w sized)
-                    if is_stroke_visible(stroke, self.offset, self.zoom, screen_w, screen_h) {
-                        // For each submesh, transform + draw
-                        for mesh in submeshes.iter() {
-                            mesh_count += 1;
-                            // --- Transform timing ---
-                            let transform_start = Instant::now();
-                            let mut screen_mesh = transform_mesh_absolute(
-                                mesh,
-                                self.offset,
-                                self.zoom,
-                                vec2(0.0, 0.0),
-                            );
-                            let transform_end = Instant::now();
-                            transforming_time += transform_end - transform_start;
+                    // If we have submeshes in the cache:
+                    if let Some(ref submeshes) = layer.stroke_cache[i] {
+                        // Now check finer visibility (screen-w sized)
+                        if is_stroke_visible(stroke, self.offset, self.zoom, screen_w, screen_h) {
+                            // For each submesh, transform + draw
+                            for mesh in submeshes.iter() {
+                                mesh_count += 1;
+                                // --- Transform timing ---
+                                let transform_start = Instant::now();
+                                let screen_mesh = transform_mesh_absolute(
+                                    mesh,
+                                    self.offset,
+                                    self.zoom,
+                                    vec2(0.0, 0.0),
+                                );
+                                let transform_end = Instant::now();
+                                transforming_time += transform_end - transform_start;
     
-                            // --- Drawing timing ---
-                            let drawing_start = Instant::now();
-                            draw_mesh(&mut screen_mesh);
-                            let drawing_end = Instant::now();
-                            drawing_time += drawing_end - drawing_start;
+                                // --- Drawing timing ---
+                                let drawing_start = Instant::now();
+                                draw_mesh(&screen_mesh);
+                                let drawing_end = Instant::now();
+                                drawing_time += drawing_end - drawing_start;
+                            }
                         }
                     }
+                } else {
+                    // If not visible at all, clear the cache
+                    layer.stroke_cache[i] = None;
                 }
-            } else {
-                // If not visible at all, clear the cache
-                self.stroke_cache[i] = None;
+            }
+
+            // the stroke being drawn goes into the layer it will end up in
+            if index == self.active_layer {
+                if let Some(stroke) = &self.current_stroke {
+                    for mesh in stroke_to_world_submeshes(&stroke.points, &stroke.style, 800) {
+                        draw_mesh(&transform_mesh_absolute(&mesh, self.offset, self.zoom, vec2(0.0, 0.0)));
+                    }
+                }
+            }
+            if translucent {
+                self.layer_target.composite(layer.props.opacity);
             }
         }
     
@@ -342,289 +1481,276 @@ impl InfiniteCanvas {
         );
 
 
-        if let Some(stroke) = &self.current_stroke {
-            for i in 0..stroke.points.len() {
-                let (pos, radius) = stroke.points[i];
-                let sx = (pos.x - self.offset.x) * self.zoom;
-                let sy = (pos.y - self.offset.y) * self.zoom;
-                draw_circle(sx, sy, radius * self.zoom, BLACK);
-
-                if i + 1 < stroke.points.len() {
-                    let (npos, nr) = stroke.points[i+1];
-                    let nsx = (npos.x - self.offset.x)*self.zoom;
-                    let nsy = (npos.y - self.offset.y)*self.zoom;
-                    draw_filled_trapezoid(vec2(sx,sy), radius*self.zoom, vec2(nsx,nsy), nr*self.zoom);
-                }
-            }
-        }
+        self.draw_texts(Rect::new(visible_left, visible_top, visible_right - visible_left, visible_bottom - visible_top));
 
         if offset_changed || zoom_changed {
             self.last_offset = self.offset;
             self.last_zoom = self.zoom;
         }
-    }
-}
-
 
-// kp
-const CAP_SEGMENTS: usize = 8; 
-fn draw_cap(
-    vertices: &mut Vec<Vertex>,
-    indices: &mut Vec<u16>,
-    start_left: Vec2,
-    start_right: Vec2,
-    color: [u8;4],
-    normal: [f32;4],
-) {
-    let center = (start_left + start_right)*0.5;
-    let cap_radius = start_left.distance(start_right)*0.5;
-
-    let angle_left = (start_left - center).angle_between(vec2(1.0,0.0));
-    let angle_right = (start_right - center).angle_between(vec2(1.0,0.0));
-
-    let mut a0 = angle_left;
-    let mut a1 = angle_right;
-    if a1 < a0 {
-        a1 += std::f32::consts::TAU;
-    }
-
-    let arc = a1 - a0;
-
-    if arc > std::f32::consts::PI {
-        let temp = a0;
-        a0 = a1;
-        a1 = temp + std::f32::consts::TAU; 
-        let arc2 = a1 - a0;
-        if arc2 > std::f32::consts::PI {
-            a1 = a0 + std::f32::consts::PI;
-        }
-    } else {
-        a1 = a0 + std::f32::consts::PI;
-    }
-
-    let first_cap_index = vertices.len() as u16;
-    vertices.push(Vertex {
-        position: Vec3::new(center.x, center.y, 0.0),
-        uv: Vec2::new(0.0,0.0),
-        color,
-        normal: normal.into(),
-    });
-
-    for j in 0..=CAP_SEGMENTS {
-        let t = j as f32 / CAP_SEGMENTS as f32;
-        let angle = a0 + t*(a1 - a0);
-        let vx = center.x + angle.cos()*cap_radius;
-        let vy = center.y + angle.sin()*cap_radius;
-        vertices.push(Vertex {
-            position: Vec3::new(vx,vy,0.0),
-            uv: Vec2::new(0.0,0.0),
-            color,
-            normal: normal.into(),
-        });
-    }
-
-    for j in 0..CAP_SEGMENTS {
-        let center_i = first_cap_index;
-        let v1 = first_cap_index + 1 + j as u16;
-        let v2 = first_cap_index + 2 + j as u16;
-        indices.push(center_i);
-        indices.push(v1);
-        indices.push(v2);
+        draw_text(self.title(), 10.0, 22.0, 22.0, DARKGRAY);
+        layer::draw_layer_panel(&self.layers, self.active_layer);
+        if self.recent_menu_open {
+            self.recent_files.draw_menu();
+        }
+        self.notifications.draw();
     }
 }
 
-fn build_stroke_mesh_chunk(
-    points: &[(Vec2, f32)],
-    draw_start_cap: bool,
-    draw_end_cap: bool,
-) -> Mesh {
-    if points.len() < 2 {
-        return Mesh {
-            vertices: Vec::new(),
-            indices:  Vec::new(),
-            texture:  None,
-        };
-    }
 
-    let n = points.len();
-    let mut vertices = Vec::with_capacity(n * 2);
-    let mut indices  = Vec::with_capacity((n - 1) * 6);
+// stroke width while drawing with the mouse in --no-stylus sessions
+const MOUSE_WIDTH: f32 = 1.5;
 
-    // direction array
-    let mut directions = Vec::with_capacity(n);
-    for i in 0..n {
-        let dir = if i == n - 1 {
-            // last point
-            let prev = points[i - 1].0;
-            let curr = points[i].0;
-            (curr - prev).normalize()
-        } else {
-            // any other point
-            let curr = points[i].0;
-            let nxt  = points[i + 1].0;
-            (nxt - curr).normalize()
-        };
-        directions.push(dir);
+fn main() {
+    let args=match cli::parse(std::env::args().skip(1)) {
+        Ok(args)=>args,
+        Err(e)=>{
+            eprintln!("error: {}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    if args.help {
+        println!("{}", cli::USAGE);
+        return;
     }
-
-    let color  = Color::new(0.0, 0.0, 0.0, 1.0);
-    let c      = color_u8(color);
-    let normal = [0.0, 0.0, 1.0, 0.0];
-
-    // 2 vertices per stroke point
-    for i in 0..n {
-        let (pos, radius) = points[i];
-        let dir  = directions[i];
-        let perp = vec2(-dir.y, dir.x);
-
-        let left_pos  = pos + perp * radius;
-        let right_pos = pos - perp * radius;
-
-        vertices.push(Vertex {
-            position: Vec3::new(left_pos.x, left_pos.y, 0.0),
-            uv:       Vec2::new(0.0, 0.0),
-            color:    c,
-            normal:   normal.into(),
-        });
-
-        vertices.push(Vertex {
-            position: Vec3::new(right_pos.x, right_pos.y, 0.0),
-            uv:       Vec2::new(0.0, 0.0),
-            color:    c,
-            normal:   normal.into(),
-        });
+    // an explicitly passed config has to work, a broken default one only warns
+    // and isn't overwritten
+    let mut startup_warning=None;
+    let mut config_path=Some(args.config.clone().unwrap_or_else(config::default_path));
+    let config=match &args.config {
+        Some(path)=>Config::load(path, true).unwrap_or_else(|e| {
+            eprintln!("error: could not read config {}", e);
+            std::process::exit(2);
+        }),
+        None=>Config::load(&config::default_path(), false).unwrap_or_else(|e| {
+            startup_warning=Some(format!("Ignoring broken config {}", e));
+            config_path=None;
+            Config::default()
+        }),
+    };
+    if let (Some(out), Some(document))=(&args.export, &args.document) {
+        if let Some(warning)=&startup_warning {
+            eprintln!("warning: {}", warning);
+        }
+        let render=raster::RenderOptions {
+            dpi: args.dpi.unwrap_or(raster::RenderOptions::default().dpi),
+            region: args.region.map(|[x, y, w, h]| Rect::new(x, y, w, h)),
+            pdf_renderer: config.pdf_renderer.clone(),
+        };
+        std::process::exit(export_headless(document, out, &render, args.annotated));
     }
 
-    // indices 2 triangles per segment
-    for i in 0..(n - 1) {
-        let i0 = (i * 2) as u16;
-        let i1 = (i * 2 + 1) as u16;
-        let i2 = ((i + 1) * 2) as u16;
-        let i3 = ((i + 1) * 2 + 1) as u16;
-
-        indices.push(i0); indices.push(i1); indices.push(i2);
-        indices.push(i2); indices.push(i1); indices.push(i3);
-    }
+    let window_title=match &args.document {
+        Some(path)=>format!("{} - Drawing App", file_name(path)),
+        None=>"Drawing App".to_string(),
+    };
+    let conf=Conf {
+        window_title,
+        ..Default::default()
+    };
+    macroquad::Window::from_config(conf, run(args, config, config_path, startup_warning));
+}
 
-    if draw_start_cap {
-        // draw cap at the first segment start
-        let start_left  = vertices[0].position.truncate();
-        let start_right = vertices[1].position.truncate();
-        draw_cap(&mut vertices, &mut indices, start_left, start_right, c, normal);
-    }
-    if draw_end_cap {
-        // draw cap at the final segment end
-        let end_left_i  = 2 * (n - 1);
-        let end_right_i = 2 * (n - 1) + 1;
-        let end_left  = vertices[end_left_i as usize].position.truncate();
-        let end_right = vertices[end_right_i as usize].position.truncate();
-        draw_cap(&mut vertices, &mut indices, end_left, end_right, c, normal);
-    }
+// starts reading a stylus, with its axis ranges and the first matching profile
+fn start_stylus(device: evdev::Device, profiles: &[TabletProfile], sender: &mpsc::Sender<StylusEvent>, notifications: &mut Notifications) -> Tablet {
+    let tablet=Tablet::new(&device, profiles);
+    notifications.info(format!("Using stylus {} with the {} profile", tablet.id, tablet.profile.name));
+    read_input(device, sender.clone());
+    tablet
+}
 
-    Mesh {
-        vertices,
-        indices,
-        texture: None,
+// converts a document without a window, returns the exit code
+fn export_headless(document: &Path, out: &Path, render: &raster::RenderOptions, annotated: bool) -> i32 {
+    let data=match persistence::load_document(document) {
+        Ok((data, report))=>{
+            if !report.is_clean() {
+                eprintln!("warning: {}: {}", document.display(), report);
+            }
+            data
+        }
+        Err(e)=>{
+            eprintln!("error: could not open {}: {}", document.display(), e);
+            return 1;
+        }
+    };
+    let result=if annotated {export::export_annotated(&data, out)} else {export::export_document(&data, out, render)};
+    match result {
+        Ok(summary)=>{
+            println!("Exported {} to {}", summary, out.display());
+            0
+        }
+        Err(e)=>{
+            eprintln!("error: could not export to {}: {}", out.display(), e);
+            1
+        }
     }
 }
 
-// ? kp was hier abgeht
-pub fn stroke_to_world_submeshes(
-    points: &[(Vec2, f32)],
-    max_chunk_points: usize
-) -> Vec<Mesh> {
-    if points.len() < 2 {
-        return Vec::new();
+async fn run(args: Args, mut config: Config, config_path: Option<PathBuf>, startup_warning: Option<String>) {
+    let (sender,receiver)=mpsc::channel();
+    let mut canvas=InfiniteCanvas::new();
+    canvas.default_author=config.author.clone();
+    canvas.pdf_renderer=config.pdf_renderer.clone();
+    if let Some(warning)=startup_warning {
+        canvas.notifications.warning(warning);
     }
 
-    let mut result = Vec::new();
-    let n = points.len();
-    let mut start = 0;
-
-    while start < n {
-        let mut end = (start + max_chunk_points).min(n - 1);
-        let is_last_chunk = end == n - 1;
-
-        if !is_last_chunk {
-            end += 1;
+    // without a stylus the mouse draws, several tablets are chosen from in a picker
+    let mut stylus_enabled=false;
+    let mut stylus_choices: Vec<Candidate>=Vec::new();
+    let mut tablet: Option<Tablet>=None;
+    let explicit_device=args.device.clone().or_else(|| Some(config.stylus_device.clone()).filter(|d| !d.is_empty()));
+    if !args.no_stylus {
+        match explicit_device {
+            Some(path)=>match evdev::Device::open(&path) {
+                Ok(device)=>{
+                    tablet=Some(start_stylus(device, &config.tablet_profiles, &sender, &mut canvas.notifications));
+                    stylus_enabled=true;
+                }
+                Err(e)=>canvas.notifications.error(format!("Could not open stylus {}: {}, drawing with the mouse", path, e)),
+            },
+            None=>match stylus_device::discover(StylusId::load_remembered().as_ref()) {
+                Discovery::Found(candidate)=>{
+                    tablet=Some(start_stylus(candidate.device, &config.tablet_profiles, &sender, &mut canvas.notifications));
+                    stylus_enabled=true;
+                }
+                Discovery::Choose(candidates)=>stylus_choices=candidates,
+                Discovery::NotFound=>canvas.notifications.warning("No readable stylus in /dev/input, drawing with the mouse"),
+            },
         }
-
-        let sub_points = &points[start..=end];
-
-        let draw_start_cap = start == 0;
-        let draw_end_cap   = end == n - 1;
-
-        let mesh = build_stroke_mesh_chunk(sub_points, draw_start_cap, draw_end_cap);
-        result.push(mesh);
-
-        if !is_last_chunk {
-            start = end - 1;
+    }
+    let crashed=journal::find_crashed();
+    match Journal::create() {
+        Ok(journal)=>canvas.command_stack.set_journal(journal),
+        Err(e)=>canvas.notifications.warning(format!("Crash recovery is off, could not create the journal: {}", e)),
+    }
+    let recovered=match crashed {
+        Some(session)=>canvas.offer_recovery(session),
+        None=>false,
+    };
+    if let Some(path)=args.document {
+        if recovered {
+            canvas.notifications.warning(format!("Showing the recovered session instead of {}", file_name(&path)));
+        } else if path.exists() {
+            canvas.open_path(path);
+        } else if persistence::is_foreign(&path) {
+            canvas.notifications.error(format!("Could not import {}: file not found", file_name(&path)));
         } else {
-            // done
-            start = end + 1;
+            canvas.notifications.info(format!("New document {}", file_name(&path)));
+            canvas.document_path=Some(path);
         }
     }
+    prevent_quit();
 
-    result
-}
-
-
-#[macroquad::main("Drawing App")]
-async fn main() {
-    let (sender,receiver)=mpsc::channel();
-    let stylus_device_path="/dev/input/event15".to_string();
-    read_input(stylus_device_path, sender);
-
-    let mut canvas=InfiniteCanvas::new();
-    let pressure_max=60000.0;
     let double_click_threshold=Duration::from_millis(300);
 
+    let mut stylus_pos: Option<Vec2>=None;
+    let mut pen_distance: Option<f32>=None; // 0 to 1 while hovering, if the tablet reports it
+    let mut last_wheel: Option<i32>=None;
+    let mut curve_editor: Option<CurveEditor>=None;
+
     loop {
-        let screen_pos=vec2(mouse_position().0, mouse_position().1);
+        let window_pos=miniquad::window::get_window_position();
+        let window_pos=vec2(window_pos.0 as f32, window_pos.1 as f32);
+        // the pen only moves the live pressure while the curve editor is open
+        let editing_curve=curve_editor.is_some();
 
+        let mut wheel_scroll=0.0;
         while let Ok(event)=receiver.try_recv() {
             match event {
-                StylusEvent::Pressure{value}=>{
-                    let pressure = value as f32 / pressure_max;
-                    let threshold = 0.7;
-                    let minWidth = 0.0;
-                    let midWidth = 1.5;
-                    let maxWidth = 3.5;
-                    let exponentLow = 1.2;
-                    let exponentHigh = 1.6;
-                    let width;
-                    if pressure < threshold {
-                        // lower segment: gentler slope
-                        let fraction = pressure / threshold;
-                        width = minWidth + (midWidth - minWidth) * fraction.powf(exponentLow);
-                    } else {
-                        // upper segment: steeper slope
-                        let fraction = (pressure - threshold) / (1.0 - threshold);
-                        width = midWidth + (maxWidth - midWidth) * fraction.powf(exponentHigh);
+                StylusEvent::Sample(sample)=>{
+                    let Some(tablet)=&tablet else { continue };
+                    canvas.current_force=tablet.pressure(sample.pressure);
+                    let curve=tablet.profile.curve.as_ref().unwrap_or(&config.pressure_curve);
+                    canvas.current_pressure=curve.width(canvas.current_force);
+                    canvas.current_tilt=tablet.tilt_degrees(sample.tilt);
+
+                    // every report becomes a point, not just the last one of the frame
+                    if let Some([x,y])=tablet.position {
+                        let normalized=vec2(x.normalize(sample.pos[0]), y.normalize(sample.pos[1]));
+                        let size=vec2(x.length(), y.length());
+                        let pos=config.tablet_mapping.to_window(normalized, size, vec2(screen_width(), screen_height()), window_pos);
+                        stylus_pos=Some(pos);
+                        if !editing_curve {
+                            let age=SystemTime::now().duration_since(sample.time).unwrap_or_default();
+                            canvas.pen_input(pos, Instant::now().checked_sub(age).unwrap_or_else(Instant::now));
+                        }
                     }
-                    canvas.current_pressure = width ;
                 }
                 StylusEvent::Key{key,value}=>{
-                    if key==evdev::Key::BTN_STYLUS {
-                        if value==1 {
-                            let now=Instant::now();
-                            if !canvas.stylus_btn_1_pressed {
-                                if now.duration_since(canvas.last_btn_1_press)<double_click_threshold {
-                                    canvas.toggle_eraser();
+                    let action=tablet.as_ref().and_then(|t| t.profile.buttons.action(key));
+                    match action {
+                        Some(ButtonAction::Pan)=>{
+                            if value==1 {
+                                let now=Instant::now();
+                                if !canvas.stylus_btn_1_pressed {
+                                    if now.duration_since(canvas.last_btn_1_press)<double_click_threshold {
+                                        canvas.toggle_eraser();
+                                    }
+                                    canvas.last_btn_1_press=now;
+                                    canvas.stylus_btn_1_pressed=true;
+                                    canvas.last_stylus_screen_pos=None;
                                 }
-                                canvas.last_btn_1_press=now;
-                                canvas.stylus_btn_1_pressed=true;
-                                canvas.last_stylus_screen_pos=Some(screen_pos);
+                            } else {
+                                canvas.stylus_btn_1_pressed=false;
+                                canvas.last_stylus_screen_pos=None;
                             }
-                        } else {
-                            canvas.stylus_btn_1_pressed=false;
-                            canvas.last_stylus_screen_pos=None;
                         }
+                        Some(ButtonAction::ToggleEraser)=>{
+                            if value==1 {
+                                canvas.toggle_eraser();
+                            }
+                        }
+                        Some(ButtonAction::Nothing)|None=>{}
+                    }
+                }
+                StylusEvent::Proximity(in_range)=>{
+                    if !in_range {
+                        stylus_pos=None;
+                        pen_distance=None;
                     }
                 }
-                _=>{}
+                StylusEvent::Distance(raw)=>{
+                    pen_distance=tablet.as_ref().and_then(|t| t.distance).map(|range| range.normalize(raw).clamp(0.0,1.0));
+                }
+                StylusEvent::Tool(tool)=>{
+                    // turning the pen around erases with its other end
+                    canvas.set_eraser(tool==Tool::Rubber);
+                }
+                StylusEvent::Serial(serial)=>{
+                    canvas.notifications.info(format!("Pen {:08x} in range", serial));
+                }
+                StylusEvent::Wheel(value)=>{
+                    // a wheel or dial zooms like the mouse wheel
+                    if let Some(last)=last_wheel {
+                        wheel_scroll+=(value-last).signum() as f32;
+                    }
+                    last_wheel=Some(value);
+                }
+            }
+        }
+        let screen_pos=stylus_pos.unwrap_or_else(|| vec2(mouse_position().0, mouse_position().1));
+
+        if !stylus_enabled&&!editing_curve {
+            canvas.current_pressure=if is_mouse_button_down(MouseButton::Left) {MOUSE_WIDTH} else {0.0};
+            canvas.current_force=if is_mouse_button_down(MouseButton::Left) {1.0} else {0.0};
+            let panning=is_mouse_button_down(MouseButton::Right)||is_mouse_button_down(MouseButton::Middle);
+            if panning!=canvas.stylus_btn_1_pressed {
+                canvas.stylus_btn_1_pressed=panning;
+                canvas.last_stylus_screen_pos=None;
             }
         }
+        // single key shortcuts are off while typing into a text box
+        let typing=canvas.text_edit.is_some();
+        if !typing&&is_key_pressed(KeyCode::E)&&!is_key_down(KeyCode::LeftControl) {
+            canvas.toggle_eraser();
+        }
+        if !typing&&is_key_pressed(KeyCode::V)&&!is_key_down(KeyCode::LeftControl) {
+            canvas.toggle_select();
+        }
+        if !typing&&is_key_pressed(KeyCode::T)&&!is_key_down(KeyCode::LeftControl) {
+            canvas.toggle_text();
+        }
 
         if canvas.stylus_btn_1_pressed {
             if let Some(last_pos)=canvas.last_stylus_screen_pos {
@@ -637,7 +1763,7 @@ async fn main() {
             }
         }
 
-        let scroll=mouse_wheel().1;
+        let scroll=mouse_wheel().1+wheel_scroll;
         if scroll!=0.0 {
             let factor=if scroll>0.0 {1.1}else{0.9};
             canvas.last_zoom = canvas.zoom;
@@ -645,33 +1771,37 @@ async fn main() {
             canvas.zoom=canvas.zoom.clamp(0.1,10.0);
         }
 
-        if canvas.current_pressure>0.1 {
-            let world_pos=canvas.offset+(screen_pos*(1.0/canvas.zoom));
-            match canvas.tool_mode {
-                ToolMode::Pen=>{
-                    if let Some(stroke)=&mut canvas.current_stroke {
-                        stroke.add_point(world_pos, canvas.current_pressure, canvas.zoom);
-                    } else {
-                        let mut stroke=Stroke::new();
-                        stroke.add_point(world_pos, canvas.current_pressure, canvas.zoom);
-                        canvas.current_stroke=Some(stroke);
-                    }
-                }
-                ToolMode::Eraser=>{
-                    canvas.erase_stroke_at(world_pos);
-                }
+        // tablets with position axes were handled sample by sample above
+        if !editing_curve&&tablet.as_ref().is_none_or(|t| t.position.is_none()) {
+            canvas.pen_input(screen_pos, Instant::now());
+        }
+
+        let ctrl=is_key_down(KeyCode::LeftControl)||is_key_down(KeyCode::RightControl);
+        let shift=is_key_down(KeyCode::LeftShift)||is_key_down(KeyCode::RightShift);
+        canvas.type_text(ctrl);
+        if ctrl&&is_key_pressed(KeyCode::S) {
+            if shift {
+                canvas.save_as();
+            } else {
+                canvas.save();
             }
-        } else if canvas.current_pressure<0.1 {
-            if canvas.tool_mode==ToolMode::Pen {
-                canvas.finalize_stroke();
+        }
+        if ctrl&&is_key_pressed(KeyCode::O) {
+            if shift {
+                canvas.recent_menu_open = !canvas.recent_menu_open;
+            } else {
+                canvas.open();
             }
         }
-
-        if is_key_down(KeyCode::LeftControl)&&is_key_pressed(KeyCode::S) {
-            canvas.save_to_json();
+        if ctrl&&is_key_pressed(KeyCode::E) {
+            if shift {
+                canvas.export_annotated();
+            } else {
+                canvas.export();
+            }
         }
-        if is_key_down(KeyCode::LeftControl)&&is_key_pressed(KeyCode::O) {
-            canvas.load_from_json();
+        if ctrl&&is_key_pressed(KeyCode::I) {
+            canvas.import();
         }
         if is_key_down(KeyCode::LeftControl) && is_key_pressed(KeyCode::Z) {
             canvas.undo();
@@ -680,13 +1810,154 @@ async fn main() {
             canvas.redo();
         }
 
+        if ctrl&&is_key_pressed(KeyCode::L) {
+            if shift {
+                canvas.delete_active_layer();
+            } else {
+                canvas.add_layer();
+            }
+        }
+        if is_key_pressed(KeyCode::PageUp) {
+            if ctrl { canvas.move_active_layer(1); } else { canvas.select_layer(1); }
+        }
+        if is_key_pressed(KeyCode::PageDown) {
+            if ctrl { canvas.move_active_layer(-1); } else { canvas.select_layer(-1); }
+        }
+        if ctrl&&is_key_pressed(KeyCode::P) {
+            if shift {
+                if !typing&&!editing_curve {
+                    let profile=tablet.as_ref().map(|t| &t.profile);
+                    let curve=profile.and_then(|p| p.curve.clone()).unwrap_or_else(|| config.pressure_curve.clone());
+                    curve_editor=Some(CurveEditor::new(curve));
+                }
+            } else {
+                canvas.toggle_page_mode();
+            }
+        }
+        if !typing&&is_key_pressed(KeyCode::Insert) {
+            canvas.insert_page();
+        }
+        if ctrl&&is_key_pressed(KeyCode::Delete) {
+            canvas.delete_page();
+        }
+        if !typing&&!ctrl&&(is_key_pressed(KeyCode::Delete)||is_key_pressed(KeyCode::Backspace)) {
+            canvas.delete_selected_image();
+        }
+        let alt=is_key_down(KeyCode::LeftAlt)||is_key_down(KeyCode::RightAlt);
+        if alt&&is_key_pressed(KeyCode::Up) {
+            canvas.move_page(-1);
+        }
+        if alt&&is_key_pressed(KeyCode::Down) {
+            canvas.move_page(1);
+        }
+        if !typing&&!ctrl&&is_key_pressed(KeyCode::R) {
+            canvas.update_current_page(|page| page.orientation=match page.orientation {
+                Orientation::Portrait=>Orientation::Landscape,
+                Orientation::Landscape=>Orientation::Portrait,
+            });
+        }
+        if !typing&&!ctrl&&is_key_pressed(KeyCode::B) {
+            canvas.update_current_page(|page| page.background.pattern=page.background.pattern.next());
+        }
+        if !typing&&!ctrl&&is_key_pressed(KeyCode::H) {
+            canvas.update_active_layer(|props| props.visible = !props.visible);
+        }
+        if !typing&&!ctrl&&is_key_pressed(KeyCode::K) {
+            canvas.update_active_layer(|props| props.locked = !props.locked);
+        }
+        if !typing&&is_key_pressed(KeyCode::LeftBracket) {
+            canvas.update_active_layer(|props| props.opacity=(props.opacity-0.1).max(0.1));
+        }
+        if !typing&&is_key_pressed(KeyCode::RightBracket) {
+            canvas.update_active_layer(|props| props.opacity=(props.opacity+0.1).min(1.0));
+        }
+
         canvas.draw();
+        if let Some(pos)=stylus_pos {
+            // fades as the pen is lifted away from the tablet
+            let alpha=1.0-0.7*pen_distance.unwrap_or(0.0);
+            draw_circle_lines(pos.x, pos.y, 4.0, 1.0, Color { a: alpha, ..DARKGRAY });
+        }
+        if let Some(editor)=&mut curve_editor {
+            editor.update();
+            editor.draw(canvas.current_force);
+        }
 
-        if is_key_pressed(KeyCode::C) {
-            //canvas.clear();
+        let number_keys=[KeyCode::Key1,KeyCode::Key2,KeyCode::Key3,KeyCode::Key4,KeyCode::Key5,KeyCode::Key6,KeyCode::Key7,KeyCode::Key8,KeyCode::Key9];
+        let picking_stylus=!stylus_choices.is_empty();
+        if picking_stylus {
+            stylus_device::draw_picker(&stylus_choices);
+            if is_key_pressed(KeyCode::Escape) {
+                stylus_choices.clear();
+                canvas.notifications.info("No stylus chosen, drawing with the mouse");
+            }
+            let chosen=number_keys.into_iter().position(is_key_pressed).filter(|i| *i<stylus_choices.len());
+            if let Some(i)=chosen {
+                let candidate=stylus_choices.swap_remove(i);
+                stylus_choices.clear();
+                candidate.id.remember();
+                tablet=Some(start_stylus(candidate.device, &config.tablet_profiles, &sender, &mut canvas.notifications));
+                stylus_enabled=true;
+            }
+        } else if let Some(editor)=&curve_editor {
+            if is_key_pressed(KeyCode::Escape) {
+                curve_editor=None;
+            } else if is_key_pressed(KeyCode::Enter)||is_key_pressed(KeyCode::KpEnter) {
+                let curve=editor.curve.clone();
+                let profile=tablet.as_ref().map_or_else(TabletProfile::default, |t| t.profile.clone());
+                config.set_pressure_curve(&profile, curve.clone());
+                if let Some(t)=tablet.as_mut().filter(|t| t.profile.curve.is_some()) {
+                    t.profile.curve=Some(curve);
+                }
+                match &config_path {
+                    Some(path)=>match config.save(path) {
+                        Ok(())=>canvas.notifications.info(format!("Saved the pressure curve to {}", path.display())),
+                        Err(e)=>canvas.notifications.error(format!("Could not save the pressure curve: {}", e)),
+                    },
+                    None=>canvas.notifications.warning("Using the pressure curve until restart, the broken config isn't overwritten"),
+                }
+                curve_editor=None;
+            }
+        } else if canvas.recent_menu_open {
+            if is_key_pressed(KeyCode::Escape) {
+                canvas.recent_menu_open=false;
+            }
+            for (i,key) in number_keys.into_iter().enumerate() {
+                if is_key_pressed(key) {
+                    canvas.open_recent(i);
+                }
+            }
+        } else if is_key_pressed(KeyCode::Escape) {
+            canvas.selected_image=None;
+        }
+
+        let tool_keys=[(KeyCode::Key1,ToolKind::Pen),(KeyCode::Key2,ToolKind::Marker),(KeyCode::Key3,ToolKind::Highlighter)];
+        for (key,tool) in tool_keys {
+            if !typing&&!picking_stylus&&!editing_curve&&!canvas.recent_menu_open&&is_key_pressed(key) {
+                canvas.current_style=canvas.current_style.with_tool(tool);
+            }
+        }
+        let color_keys=[KeyCode::Key4,KeyCode::Key5,KeyCode::Key6,KeyCode::Key7,KeyCode::Key8,KeyCode::Key9];
+        for (key,color) in color_keys.into_iter().zip(PALETTE) {
+            if !typing&&!picking_stylus&&!editing_curve&&!canvas.recent_menu_open&&is_key_pressed(key) {
+                canvas.current_style=canvas.current_style.with_color(color);
+            }
+        }
+
+        if let Some(e)=canvas.command_stack.journal_mut().and_then(Journal::take_error) {
+            canvas.notifications.warning(format!("Crash recovery stopped working: {}", e));
+        }
+
+        if is_quit_requested()&&canvas.confirm_discard() {
+            break;
         }
 
         next_frame().await;
     }
+
+    // clean shutdown, nothing to recover next time
+    if let Some(journal)=canvas.command_stack.take_journal() {
+        journal.remove();
+    }
 }
 
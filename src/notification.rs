@@ -0,0 +1,81 @@
+use macroquad::prelude::*;
+use std::time::{Duration, Instant};
+
+const VISIBLE_FOR: Duration = Duration::from_secs(6);
+const FONT_SIZE: f32 = 20.0;
+const PADDING: f32 = 8.0;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Level {
+    Info,
+    Warning,
+    Error,
+}
+
+struct Notification {
+    text: String,
+    level: Level,
+    shown_at: Instant,
+}
+
+/// Short messages drawn on top of the canvas, in screen space, that fade out
+/// on their own. Used for anything the user should know about but that
+/// shouldn't interrupt drawing, like failed saves.
+pub struct Notifications {
+    items: Vec<Notification>,
+}
+
+impl Notifications {
+    pub fn new() -> Self {
+        Self { items: Vec::new() }
+    }
+
+    pub fn push(&mut self, level: Level, text: impl Into<String>) {
+        let text = text.into();
+        if level != Level::Info {
+            eprintln!("{}", text);
+        }
+        self.items.push(Notification { text, level, shown_at: Instant::now() });
+    }
+
+    pub fn info(&mut self, text: impl Into<String>) {
+        self.push(Level::Info, text);
+    }
+
+    pub fn warning(&mut self, text: impl Into<String>) {
+        self.push(Level::Warning, text);
+    }
+
+    pub fn error(&mut self, text: impl Into<String>) {
+        self.push(Level::Error, text);
+    }
+
+    pub fn draw(&mut self) {
+        self.items.retain(|n| n.shown_at.elapsed() < VISIBLE_FOR);
+
+        let mut y = screen_height() - PADDING;
+        for n in self.items.iter().rev() {
+            let age = n.shown_at.elapsed().as_secs_f32() / VISIBLE_FOR.as_secs_f32();
+            // fade out during the last fifth
+            let alpha = ((1.0 - age) * 5.0).min(1.0);
+            let background = match n.level {
+                Level::Info => Color::new(0.15, 0.15, 0.15, 0.85 * alpha),
+                Level::Warning => Color::new(0.75, 0.45, 0.0, 0.9 * alpha),
+                Level::Error => Color::new(0.7, 0.1, 0.1, 0.9 * alpha),
+            };
+
+            let size = measure_text(&n.text, None, FONT_SIZE as u16, 1.0);
+            let h = size.height + 2.0 * PADDING;
+            y -= h;
+            draw_rectangle(PADDING, y, size.width + 2.0 * PADDING, h, background);
+            draw_text(&n.text, 2.0 * PADDING, y + PADDING + size.offset_y, FONT_SIZE, Color::new(1.0, 1.0, 1.0, alpha));
+            y -= PADDING;
+        }
+    }
+}
+
+impl Default for Notifications {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,63 @@
+use macroquad::prelude::*;
+use std::f32::consts::PI;
+
+use crate::stroke_style::{CapStyle, StrokeStyle};
+
+const CAP_SEGMENTS: usize = 8;
+
+// Vector exporters fill a stroke's outline instead of drawing its triangle
+// mesh. The outline uses the same left/right offsets as
+// `build_stroke_mesh_chunk`. Strokes are stored after `catmull_rom_spline`
+// already ran in `finalize_stroke`, so the points are used as they are.
+
+/// Closed polygon around a stroke: the left side from start to end, the end
+/// cap, the right side back to the start and the start cap. Empty for strokes
+/// with less than two points, which don't produce a mesh either.
+pub fn stroke_outline(points: &[(Vec2, f32)], style: &StrokeStyle) -> Vec<Vec2> {
+    let n = points.len();
+    if n < 2 {
+        return Vec::new();
+    }
+
+    let directions: Vec<Vec2> = (0..n)
+        .map(|i| {
+            if i == n - 1 {
+                (points[i].0 - points[i - 1].0).normalize_or_zero()
+            } else {
+                (points[i + 1].0 - points[i].0).normalize_or_zero()
+            }
+        })
+        .collect();
+    let radius = |i: usize| points[i].1 * style.width;
+    let perp = |d: Vec2| vec2(-d.y, d.x);
+
+    let mut outline = Vec::with_capacity(2 * n + 2 * (CAP_SEGMENTS + 1));
+    for i in 0..n {
+        outline.push(points[i].0 + perp(directions[i]) * radius(i));
+    }
+    push_cap(&mut outline, points[n - 1].0, directions[n - 1], radius(n - 1), style.cap);
+    for i in (0..n).rev() {
+        outline.push(points[i].0 - perp(directions[i]) * radius(i));
+    }
+    push_cap(&mut outline, points[0].0, -directions[0], radius(0), style.cap);
+    outline
+}
+
+// goes around `center` from the left side over `outward` to the right side,
+// left and right as seen when walking in the `outward` direction
+fn push_cap(outline: &mut Vec<Vec2>, center: Vec2, outward: Vec2, radius: f32, cap: CapStyle) {
+    let side = vec2(-outward.y, outward.x);
+    match cap {
+        CapStyle::Round => {
+            for j in 1..CAP_SEGMENTS {
+                let angle = PI * j as f32 / CAP_SEGMENTS as f32;
+                outline.push(center + (side * angle.cos() + outward * angle.sin()) * radius);
+            }
+        }
+        CapStyle::Square => {
+            outline.push(center + (side + outward) * radius);
+            outline.push(center + (outward - side) * radius);
+        }
+        CapStyle::Butt => {}
+    }
+}
@@ -0,0 +1,275 @@
+use macroquad::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use crate::utility::stroke_bounding_box;
+use crate::Stroke;
+
+/// A4 at 72 dpi, in world units.
+pub const A4_SIZE: [f32;2] = [595.0, 842.0];
+
+/// Vertical space between pages that are laid out below each other.
+pub const PAGE_GAP: f32 = 40.0;
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Orientation {
+    Portrait,
+    Landscape,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Pattern {
+    Blank,
+    Ruled { spacing: f32 },
+    Grid { spacing: f32 },
+    Dots { spacing: f32 },
+}
+
+impl Pattern {
+    /// Next pattern for cycling through them with a key.
+    pub fn next(self) -> Pattern {
+        match self {
+            Pattern::Blank => Pattern::Ruled { spacing: 24.0 },
+            Pattern::Ruled { .. } => Pattern::Grid { spacing: 20.0 },
+            Pattern::Grid { .. } => Pattern::Dots { spacing: 20.0 },
+            Pattern::Dots { .. } => Pattern::Blank,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Background {
+    pub color: [f32;4],
+    pub pattern: Pattern,
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Self { color: [1.0, 1.0, 1.0, 1.0], pattern: Pattern::Blank }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Page {
+    pub position: [f32;2], // top left, world coords
+    pub size: [f32;2],     // width and height in portrait orientation
+    pub orientation: Orientation,
+    pub background: Background,
+}
+
+impl Page {
+    pub fn a4(position: Vec2) -> Self {
+        Self {
+            position: [position.x, position.y],
+            size: A4_SIZE,
+            orientation: Orientation::Portrait,
+            background: Background::default(),
+        }
+    }
+
+    pub fn top_left(&self) -> Vec2 {
+        vec2(self.position[0], self.position[1])
+    }
+
+    /// Width and height with the orientation applied.
+    pub fn extent(&self) -> Vec2 {
+        match self.orientation {
+            Orientation::Portrait => vec2(self.size[0], self.size[1]),
+            Orientation::Landscape => vec2(self.size[1], self.size[0]),
+        }
+    }
+
+    pub fn rect(&self) -> Rect {
+        let extent = self.extent();
+        Rect::new(self.position[0], self.position[1], extent.x, extent.y)
+    }
+}
+
+/// Either the original endless canvas with a repeating A4 grid, or an explicit
+/// list of pages.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum PageLayout {
+    Infinite,
+    Pages(Vec<Page>),
+}
+
+fn stroke_center(stroke: &Stroke) -> Vec2 {
+    let (min_x, max_x, min_y, max_y) = stroke_bounding_box(&stroke.points);
+    vec2((min_x + max_x) * 0.5, (min_y + max_y) * 0.5)
+}
+
+/// Index of the page a stroke belongs to, decided by the center of its bounding box.
+pub fn page_of_stroke(pages: &[Page], stroke: &Stroke) -> Option<usize> {
+    let center = stroke_center(stroke);
+    pages.iter().position(|p| p.rect().contains(center))
+}
+
+/// Index of the page an image or other area belongs to, decided by its center.
+pub fn page_of_rect(pages: &[Page], r: Rect) -> Option<usize> {
+    pages.iter().position(|p| p.rect().contains(r.center()))
+}
+
+/// Index of the page containing `pos`, or else the closest one.
+pub fn page_near(pages: &[Page], pos: Vec2) -> Option<usize> {
+    let distance = |p: &Page| {
+        let r = p.rect();
+        let dx = (r.x - pos.x).max(pos.x - (r.x + r.w)).max(0.0);
+        let dy = (r.y - pos.y).max(pos.y - (r.y + r.h)).max(0.0);
+        dx * dx + dy * dy
+    };
+    (0..pages.len()).min_by(|a, b| distance(&pages[*a]).total_cmp(&distance(&pages[*b])))
+}
+
+/// Stacks `pages[from..]` below each other, starting at `top`. Pages keep
+/// their horizontal position.
+pub fn flow_pages(pages: &mut [Page], from: usize, top: f32) {
+    let mut y = top;
+    for page in pages.iter_mut().skip(from) {
+        page.position[1] = y;
+        y += page.extent().y + PAGE_GAP;
+    }
+}
+
+/// A4 cell (as used by the infinite grid) that a world position falls into.
+pub fn a4_cell(pos: Vec2) -> (i32, i32) {
+    ((pos.x / A4_SIZE[0]).floor() as i32, (pos.y / A4_SIZE[1]).floor() as i32)
+}
+
+/// One page for every A4 cell of the infinite grid that contains ink or
+/// part of one of `areas` (images and text boxes), in reading order.
+pub fn pages_from_ink<'a>(strokes: impl Iterator<Item = &'a Stroke>, areas: &[Rect]) -> Vec<Page> {
+    let mut cells: Vec<(i32, i32)> = Vec::new();
+    for stroke in strokes {
+        for (pos, _) in &stroke.points {
+            cells.push(a4_cell(*pos));
+        }
+    }
+    for r in areas {
+        let (x0, y0) = a4_cell(vec2(r.x, r.y));
+        let (x1, y1) = a4_cell(vec2(r.x + r.w, r.y + r.h));
+        for y in y0..=y1 {
+            cells.extend((x0..=x1).map(|x| (x, y)));
+        }
+    }
+    // sort rows first, then columns
+    cells.sort_by_key(|(x, y)| (*y, *x));
+    cells.dedup();
+    cells.into_iter()
+        .map(|(x, y)| Page::a4(vec2(x as f32 * A4_SIZE[0], y as f32 * A4_SIZE[1])))
+        .collect()
+}
+
+fn to_screen(pos: Vec2, offset: Vec2, zoom: f32) -> Vec2 {
+    (pos - offset) * zoom
+}
+
+pub fn draw_page(page: &Page, offset: Vec2, zoom: f32) {
+    let r = page.rect();
+    let tl = to_screen(vec2(r.x, r.y), offset, zoom);
+    let (w, h) = (r.w * zoom, r.h * zoom);
+    let bg = page.background.color;
+    draw_rectangle(tl.x, tl.y, w, h, Color::new(bg[0], bg[1], bg[2], bg[3]));
+
+    let line_color = Color::new(0.55, 0.65, 0.8, 0.6);
+    let (spacing, horizontal, vertical, dots) = match page.background.pattern {
+        Pattern::Blank => (0.0, false, false, false),
+        Pattern::Ruled { spacing } => (spacing, true, false, false),
+        Pattern::Grid { spacing } => (spacing, true, true, false),
+        Pattern::Dots { spacing } => (spacing, false, false, true),
+    };
+    // patterns turn into noise when zoomed far out
+    if spacing * zoom >= 4.0 {
+        let step = spacing * zoom;
+        if horizontal {
+            let mut y = tl.y + step;
+            while y < tl.y + h {
+                draw_line(tl.x, y, tl.x + w, y, 1.0, line_color);
+                y += step;
+            }
+        }
+        if vertical {
+            let mut x = tl.x + step;
+            while x < tl.x + w {
+                draw_line(x, tl.y, x, tl.y + h, 1.0, line_color);
+                x += step;
+            }
+        }
+        if dots {
+            let mut y = tl.y + step;
+            while y < tl.y + h {
+                let mut x = tl.x + step;
+                while x < tl.x + w {
+                    draw_circle(x, y, 1.2, line_color);
+                    x += step;
+                }
+                y += step;
+            }
+        }
+    }
+
+    draw_rectangle_lines(tl.x, tl.y, w, h, 1.0, Color::new(0.0, 0.0, 0.0, 0.5));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stroke(points: &[Vec2]) -> Stroke {
+        let mut stroke = Stroke::new(Default::default());
+        stroke.points = points.iter().map(|p| (*p, 1.0)).collect();
+        stroke
+    }
+
+    fn two_pages() -> Vec<Page> {
+        let landscape = Page { orientation: Orientation::Landscape, ..Page::a4(vec2(0.0, 0.0)) };
+        let mut pages = vec![Page::a4(vec2(0.0, 0.0)), landscape];
+        flow_pages(&mut pages, 0, 0.0);
+        pages
+    }
+
+    #[test]
+    fn page_rects() {
+        let pages = two_pages();
+        assert_eq!(pages[0].rect(), Rect::new(0.0, 0.0, 595.0, 842.0));
+        // landscape swaps width and height, the second page starts below the gap
+        assert_eq!(pages[1].extent(), vec2(842.0, 595.0));
+        assert_eq!(pages[1].rect(), Rect::new(0.0, 842.0 + PAGE_GAP, 842.0, 595.0));
+
+        let mut pages = pages;
+        pages.swap(0, 1);
+        flow_pages(&mut pages, 1, 1000.0);
+        assert_eq!(pages[0].top_left(), vec2(0.0, 842.0 + PAGE_GAP));
+        assert_eq!(pages[1].top_left(), vec2(0.0, 1000.0));
+    }
+
+    #[test]
+    fn page_of_a_point() {
+        let pages = two_pages();
+        let second_top = 842.0 + PAGE_GAP;
+        assert_eq!(page_of_stroke(&pages, &stroke(&[vec2(10.0, 10.0), vec2(100.0, 50.0)])), Some(0));
+        // wider than the first page, only on the landscape one
+        assert_eq!(page_of_stroke(&pages, &stroke(&[vec2(700.0, second_top + 10.0)])), Some(1));
+        // the center decides, not the first point
+        assert_eq!(page_of_stroke(&pages, &stroke(&[vec2(10.0, 800.0), vec2(10.0, second_top + 300.0)])), Some(1));
+        assert_eq!(page_of_stroke(&pages, &stroke(&[vec2(10.0, 850.0)])), None);
+        assert_eq!(page_of_stroke(&pages, &stroke(&[vec2(700.0, 10.0)])), None);
+
+        assert_eq!(page_of_rect(&pages, Rect::new(500.0, 800.0, 20.0, 20.0)), Some(0));
+        assert_eq!(page_of_rect(&pages, Rect::new(500.0, 830.0, 20.0, 100.0)), None);
+
+        assert_eq!(page_near(&pages, vec2(300.0, 400.0)), Some(0));
+        assert_eq!(page_near(&pages, vec2(300.0, 850.0)), Some(0));
+        assert_eq!(page_near(&pages, vec2(300.0, 875.0)), Some(1));
+        assert_eq!(page_near(&pages, vec2(-50.0, 5000.0)), Some(1));
+        assert_eq!(page_near(&pages, vec2(800.0, 100.0)), Some(0));
+        assert_eq!(page_near(&[], vec2(0.0, 0.0)), None);
+    }
+
+    #[test]
+    fn pages_cover_the_ink() {
+        assert_eq!(a4_cell(vec2(-1.0, 843.0)), (-1, 1));
+        let ink = [stroke(&[vec2(10.0, 10.0), vec2(600.0, 20.0)]), stroke(&[vec2(10.0, 900.0)])];
+        let pages = pages_from_ink(ink.iter(), &[Rect::new(-10.0, 10.0, 5.0, 5.0)]);
+        let corners: Vec<Vec2> = pages.iter().map(Page::top_left).collect();
+        assert_eq!(corners, [vec2(-595.0, 0.0), vec2(0.0, 0.0), vec2(595.0, 0.0), vec2(0.0, 842.0)]);
+    }
+}
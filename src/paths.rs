@@ -0,0 +1,23 @@
+use std::env;
+use std::path::PathBuf;
+
+const APP_DIR: &str = "lignum_draw";
+
+fn xdg_dir(var: &str, fallback: &str) -> PathBuf {
+    let base = env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(fallback)))
+        .unwrap_or_else(env::temp_dir);
+    base.join(APP_DIR)
+}
+
+/// Per-user state that isn't a document, like crash recovery journals.
+pub fn state_dir() -> PathBuf {
+    xdg_dir("XDG_STATE_HOME", ".local/state")
+}
+
+/// User settings, see `XDG_CONFIG_HOME`.
+pub fn config_dir() -> PathBuf {
+    xdg_dir("XDG_CONFIG_HOME", ".config")
+}
@@ -0,0 +1,264 @@
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::canvas_image;
+use crate::export;
+use crate::file_format::CanvasData;
+use crate::page::{self, Background, Orientation, Page, Pattern};
+use crate::pdf_export;
+use crate::raster::Image;
+
+// A PDF imported for annotation. The original file is embedded in the
+// document and its pages are shown behind the ink, one per A4 cell of the
+// endless canvas, scaled to fit. The pages are rasterized by an external
+// renderer (pdftoppm from poppler by default) whenever they are needed, the
+// ink never becomes part of them.
+//
+// The annotated export writes the ink as a pdf of its own with one page per
+// imported page, and then draws each of its pages as a form over the
+// matching original page. The original content stays untouched.
+
+pub const DEFAULT_RENDERER: &str = "pdftoppm";
+/// Resolution of the page images shown on screen.
+pub const SCREEN_DPI: f32 = 100.0;
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct PdfPage {
+    pub index: u32,     // page number in the pdf, from 0
+    pub cell: [i32;2],  // A4 cell it is shown on
+    pub size: [f32;2],  // size in points as displayed, with the page rotation applied
+    #[serde(default)]
+    pub offset: [f32;2], // moved away from its cell along with the page it lies on
+}
+
+impl PdfPage {
+    /// World rectangle of the page, centered in its cell.
+    pub fn rect(&self) -> Rect {
+        let [a4_w, a4_h] = page::A4_SIZE;
+        let scale = (a4_w / self.size[0]).min(a4_h / self.size[1]);
+        let (w, h) = (self.size[0] * scale, self.size[1] * scale);
+        let x = self.cell[0] as f32 * a4_w + self.offset[0];
+        let y = self.cell[1] as f32 * a4_h + self.offset[1];
+        Rect::new(x + (a4_w - w) * 0.5, y + (a4_h - h) * 0.5, w, h)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct PdfBackground {
+    pub name: String, // file it was imported from, for messages
+    #[serde(serialize_with = "canvas_image::to_base64", deserialize_with = "canvas_image::from_base64")]
+    pub pdf: Vec<u8>,
+    pub pages: Vec<PdfPage>,
+}
+
+pub fn is_pdf(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("pdf"))
+}
+
+// page attributes that can be inherited from the page tree
+fn inherited<'a>(doc: &'a Document, page: ObjectId, key: &[u8]) -> Option<&'a Object> {
+    let mut node = doc.get_dictionary(page).ok()?;
+    for _ in 0..64 {
+        if let Ok(value) = node.get(key) {
+            return doc.dereference(value).ok().map(|(_, v)| v);
+        }
+        node = doc.get_dictionary(node.get(b"Parent").ok()?.as_reference().ok()?).ok()?;
+    }
+    None
+}
+
+// the visible box of a page and its rotation in degrees, clockwise
+fn page_geometry(doc: &Document, page: ObjectId) -> ([f32; 4], i64) {
+    let rect = |key: &[u8]| {
+        let values: Vec<f32> = inherited(doc, page, key)?.as_array().ok()?
+            .iter().map(|v| v.as_float().ok()).collect::<Option<_>>()?;
+        match values[..] {
+            [x0, y0, x1, y1] if x1 != x0 && y1 != y0 => Some([x0.min(x1), y0.min(y1), x0.max(x1), y0.max(y1)]),
+            _ => None,
+        }
+    };
+    let visible = rect(b"CropBox").or_else(|| rect(b"MediaBox")).unwrap_or([0.0, 0.0, 612.0, 792.0]);
+    let rotate = inherited(doc, page, b"Rotate").and_then(|r| r.as_i64().ok()).unwrap_or(0).rem_euclid(360);
+    (visible, rotate - rotate % 90)
+}
+
+/// Reads the page sizes of `pdf` and places its pages on the A4 cells
+/// below `first_cell`.
+pub fn import(name: &str, pdf: Vec<u8>, first_cell: (i32, i32)) -> Result<PdfBackground, String> {
+    let doc = Document::load_mem(&pdf).map_err(|e| e.to_string())?;
+    let mut pages = Vec::new();
+    for (i, id) in doc.get_pages().into_values().enumerate() {
+        let ([x0, y0, x1, y1], rotate) = page_geometry(&doc, id);
+        let (w, h) = if rotate % 180 == 0 { (x1 - x0, y1 - y0) } else { (y1 - y0, x1 - x0) };
+        pages.push(PdfPage { index: i as u32, cell: [first_cell.0, first_cell.1 + i as i32], size: [w, h], offset: [0.0, 0.0] });
+    }
+    if pages.is_empty() {
+        return Err("the pdf has no pages".to_string());
+    }
+    Ok(PdfBackground { name: name.to_string(), pdf, pages })
+}
+
+/// Rasterizes the pages `first..=last` (counted from 0) of `pdf` with
+/// `renderer`, which is called like pdftoppm:
+/// `renderer -png -r DPI -f FIRST -l LAST input.pdf prefix`, writing one
+/// `prefix-N.png` per page. Returns the images with their page index.
+pub fn render_pages(renderer: &str, pdf: &[u8], dpi: f32, first: u32, last: u32) -> Result<Vec<(u32, Image)>, String> {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.subsec_nanos());
+    let dir = std::env::temp_dir().join(format!("lignum_draw-pdf-{}-{}", std::process::id(), nanos));
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let result = render_into(renderer, pdf, dpi, (first, last), &dir);
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
+
+fn render_into(renderer: &str, pdf: &[u8], dpi: f32, (first, last): (u32, u32), dir: &Path) -> Result<Vec<(u32, Image)>, String> {
+    let input = dir.join("input.pdf");
+    fs::write(&input, pdf).map_err(|e| e.to_string())?;
+    let output = Command::new(renderer)
+        .arg("-png")
+        .args(["-r", &format!("{}", dpi.round().max(1.0))])
+        .args(["-f", &(first + 1).to_string(), "-l", &(last + 1).to_string()])
+        .arg(&input)
+        .arg(dir.join("page"))
+        .output()
+        .map_err(|e| format!("could not run {}: {}, install poppler or set pdf_renderer in the config", renderer, e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("{} failed: {}", renderer, stderr.trim()));
+    }
+
+    // page-1.png, or page-01.png and so on for longer documents
+    let mut files: Vec<(u32, std::path::PathBuf)> = fs::read_dir(dir).map_err(|e| e.to_string())?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let number: u32 = path.file_stem()?.to_str()?.strip_prefix("page-")?.parse().ok()?;
+            Some((number.checked_sub(1)?, path))
+        })
+        .collect();
+    if files.is_empty() {
+        return Err(format!("{} rendered no pages", renderer));
+    }
+    files.sort();
+    files.into_iter()
+        .map(|(index, path)| {
+            let png = fs::read(&path).map_err(|e| e.to_string())?;
+            Ok((index, canvas_image::decode_png(&png)?))
+        })
+        .collect()
+}
+
+// maps the form space of an overlay page (its visible size scaled by `scale`)
+// onto a page with the given visible box and rotation
+fn form_matrix([x0, y0, x1, y1]: [f32; 4], rotate: i64, scale: f32) -> [f32; 6] {
+    let (w, h) = (x1 - x0, y1 - y0);
+    match rotate {
+        90 => [0.0, scale, -scale, 0.0, x0 + w, y0],
+        180 => [-scale, 0.0, 0.0, -scale, x0 + w, y0 + h],
+        270 => [0.0, -scale, scale, 0.0, x0, y0 + h],
+        _ => [scale, 0.0, 0.0, scale, x0, y0],
+    }
+}
+
+// the page's resources as a dictionary of its own, so adding to it doesn't
+// change pages that share or inherit the original
+fn own_resources(doc: &Document, page: ObjectId) -> Dictionary {
+    let mut resources = inherited(doc, page, b"Resources").and_then(|r| r.as_dict().ok()).cloned().unwrap_or_default();
+    let xobjects = resources.get(b"XObject").ok()
+        .and_then(|x| doc.dereference(x).ok())
+        .and_then(|(_, x)| x.as_dict().ok())
+        .cloned()
+        .unwrap_or_default();
+    resources.set("XObject", xobjects);
+    resources
+}
+
+fn page_contents(doc: &Document, page: ObjectId) -> Vec<Object> {
+    let Ok(contents) = doc.get_dictionary(page).and_then(|p| p.get(b"Contents")) else { return Vec::new() };
+    match contents {
+        Object::Reference(id) => match doc.get_object(*id) {
+            Ok(Object::Array(streams)) => streams.clone(),
+            _ => vec![contents.clone()],
+        },
+        Object::Array(streams) => streams.clone(),
+        _ => Vec::new(),
+    }
+}
+
+/// The imported pdf with the visible strokes, images and text drawn over the
+/// pages they were written on. Returns the pdf and the number of pages.
+pub fn write_annotated(data: &CanvasData) -> io::Result<(Vec<u8>, usize)> {
+    let Some(background) = &data.pdf else {
+        return Err(io::Error::other("there is no imported pdf to annotate"));
+    };
+    let mut doc = Document::load_mem(&background.pdf).map_err(io::Error::other)?;
+    let page_ids = doc.get_pages();
+
+    // transparent overlay pages covering the imported pages on the canvas
+    let overlay_pages: Vec<Page> = background.pages.iter()
+        .map(|p| {
+            let r = p.rect();
+            Page {
+                position: [r.x, r.y],
+                size: [r.w, r.h],
+                orientation: Orientation::Portrait,
+                background: Background { color: [1.0, 1.0, 1.0, 0.0], pattern: Pattern::Blank },
+            }
+        })
+        .collect();
+    let strokes = export::visible_strokes(data);
+    let overlay = pdf_export::write_pdf(&overlay_pages, &strokes, &data.images, &data.texts, &data.meta)?;
+    let mut overlay = Document::load_mem(&overlay).map_err(io::Error::other)?;
+    overlay.renumber_objects_with(doc.max_id + 1);
+    let mut forms = Vec::new();
+    for (page, overlay_id) in background.pages.iter().zip(overlay.get_pages().into_values()) {
+        let overlay_page = overlay.get_dictionary(overlay_id).map_err(io::Error::other)?;
+        let resources = overlay_page.get(b"Resources").map_err(io::Error::other)?.clone();
+        let content = overlay_page.get(b"Contents").and_then(Object::as_reference).map_err(io::Error::other)?;
+        forms.push((page, resources, content));
+    }
+    doc.max_id = doc.max_id.max(overlay.max_id);
+    doc.objects.extend(overlay.objects);
+
+    let mut annotated = 0;
+    for (k, (page, resources, content)) in forms.into_iter().enumerate() {
+        let Some(&page_id) = page_ids.get(&(page.index + 1)) else { continue };
+        let (visible, rotate) = page_geometry(&doc, page_id);
+        let r = page.rect();
+        let scale = if rotate % 180 == 0 { visible[2] - visible[0] } else { visible[3] - visible[1] } / r.w;
+
+        // the overlay page's content becomes a form over the whole page
+        let Ok(Object::Stream(form)) = doc.get_object_mut(content) else { continue };
+        form.dict.set("Type", "XObject");
+        form.dict.set("Subtype", "Form");
+        form.dict.set("BBox", vec![0.into(), 0.into(), r.w.into(), r.h.into()]);
+        form.dict.set("Matrix", form_matrix(visible, rotate, scale).iter().map(|v| Object::Real(*v)).collect::<Vec<_>>());
+        form.dict.set("Resources", resources);
+
+        let name = format!("LignumInk{}", k);
+        let mut page_resources = own_resources(&doc, page_id);
+        if let Ok(Object::Dictionary(xobjects)) = page_resources.get_mut(b"XObject") {
+            xobjects.set(name.as_bytes(), Object::Reference(content));
+        }
+        // the original content is wrapped in q/Q, so whatever state it
+        // leaves behind doesn't move the ink
+        let mut contents = vec![Object::Reference(doc.add_object(Stream::new(Dictionary::new(), b"q\n".to_vec())))];
+        contents.extend(page_contents(&doc, page_id));
+        let ink = format!("\nQ q /{} Do Q\n", name).into_bytes();
+        contents.push(Object::Reference(doc.add_object(Stream::new(Dictionary::new(), ink))));
+        let page_dict = doc.get_dictionary_mut(page_id).map_err(io::Error::other)?;
+        page_dict.set("Resources", page_resources);
+        page_dict.set("Contents", contents);
+        annotated += 1;
+    }
+    // drops the overlay's own catalog, page tree and info
+    doc.prune_objects();
+    let mut out = Vec::new();
+    doc.save_to(&mut out)?;
+    Ok((out, annotated))
+}
@@ -0,0 +1,396 @@
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+use crate::canvas_image::{self, CanvasImage};
+use crate::file_format::DocumentMeta;
+use crate::outline::stroke_outline;
+use crate::page::{Page, Pattern};
+use crate::raster::Image;
+use crate::text_box::{self, TextBox};
+use crate::timestamp;
+use crate::utility::stroke_bounding_box;
+use crate::Stroke;
+
+// A minimal PDF 1.4 writer. World units are PDF points (A4 is 595x842 in
+// both), so every page only needs a y flip and a translation to its corner.
+// Strokes are filled outlines, translucent ones get an ExtGState with their
+// alpha.
+//
+// Object numbers: 1 catalog, 2 page tree, then a page and its content
+// stream for every page, the document info, an rgb image with its alpha
+// mask for every embedded picture, and last the embedded font if there is
+// any text. Text is set in the font the canvas uses, so lines break at the
+// same places.
+
+const PATTERN_RGB: [f32; 3] = [0.75, 0.8, 0.9];
+
+struct PdfWriter {
+    out: Vec<u8>,
+    offsets: Vec<usize>,
+}
+
+impl PdfWriter {
+    fn new(object_count: usize) -> Self {
+        let mut out = Vec::new();
+        // the binary comment line marks the file as binary for transfer tools
+        out.extend_from_slice(b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n");
+        Self { out, offsets: vec![0; object_count] }
+    }
+
+    fn object(&mut self, id: usize, body: &[u8]) {
+        self.offsets[id - 1] = self.out.len();
+        self.out.extend_from_slice(format!("{} 0 obj\n", id).as_bytes());
+        self.out.extend_from_slice(body);
+        self.out.extend_from_slice(b"\nendobj\n");
+    }
+
+    fn stream(&mut self, id: usize, content: &[u8]) -> io::Result<()> {
+        self.stream_with(id, "", content)
+    }
+
+    /// A compressed stream whose dictionary also has `entries`.
+    fn stream_with(&mut self, id: usize, entries: &str, content: &[u8]) -> io::Result<()> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(content)?;
+        let data = encoder.finish()?;
+        let mut body = format!("<< {}/Length {} /Filter /FlateDecode >>\nstream\n", entries, data.len()).into_bytes();
+        body.extend_from_slice(&data);
+        body.extend_from_slice(b"\nendstream");
+        self.object(id, &body);
+        Ok(())
+    }
+
+    fn finish(mut self, info: usize) -> Vec<u8> {
+        let xref = self.out.len();
+        let mut table = format!("xref\n0 {}\n0000000000 65535 f \n", self.offsets.len() + 1);
+        for offset in &self.offsets {
+            let _ = writeln!(table, "{:010} 00000 n ", offset);
+        }
+        let _ = write!(
+            table,
+            "trailer\n<< /Size {} /Root 1 0 R /Info {} 0 R >>\nstartxref\n{}\n%%EOF\n",
+            self.offsets.len() + 1,
+            info,
+            xref
+        );
+        self.out.extend_from_slice(table.as_bytes());
+        self.out
+    }
+}
+
+// compact number formatting, pdf readers don't need more than 1/100 pt
+fn num(v: f32) -> String {
+    let s = format!("{:.2}", v);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    if s == "-0" { "0".to_string() } else { s.to_string() }
+}
+
+// utf-16 with a byte order mark, so titles aren't limited to latin-1
+fn text_string(text: &str) -> String {
+    let mut hex = String::from("<FEFF");
+    for unit in text.encode_utf16() {
+        let _ = write!(hex, "{:04X}", unit);
+    }
+    hex.push('>');
+    hex
+}
+
+fn date(millis: u64) -> String {
+    let (y, mo, d, h, mi, s) = timestamp::utc_fields(millis);
+    format!("(D:{:04}{:02}{:02}{:02}{:02}{:02}Z)", y, mo, d, h, mi, s)
+}
+
+fn info_dict(meta: &DocumentMeta) -> String {
+    let mut dict = String::from("<< /Producer (lignum_draw)");
+    if !meta.title.is_empty() {
+        let _ = write!(dict, " /Title {}", text_string(&meta.title));
+    }
+    if !meta.author.is_empty() {
+        let _ = write!(dict, " /Author {}", text_string(&meta.author));
+    }
+    if let Some(created) = meta.created {
+        let _ = write!(dict, " /CreationDate {}", date(created));
+    }
+    if let Some(modified) = meta.modified {
+        let _ = write!(dict, " /ModDate {}", date(modified));
+    }
+    dict.push_str(" >>");
+    dict
+}
+
+fn overlaps_page(stroke: &Stroke, page: &Page) -> bool {
+    let (min_x, max_x, min_y, max_y) = stroke_bounding_box(&stroke.points);
+    let margin = stroke.points.iter().map(|(_, r)| *r).fold(0.0, f32::max) * stroke.style.width;
+    let r = page.rect();
+    max_x + margin >= r.x && min_x - margin <= r.x + r.w && max_y + margin >= r.y && min_y - margin <= r.y + r.h
+}
+
+fn alpha_key(alpha: f32) -> u8 {
+    (alpha.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+fn background_ops(page: &Page, content: &mut String) {
+    let r = page.rect();
+    let [red, green, blue, alpha] = page.background.color;
+    // transparent pages are overlays, annotated pdfs draw them over other content
+    if alpha > 0.0 {
+        let _ = writeln!(content, "{} {} {} rg {} {} {} {} re f", num(red), num(green), num(blue), num(r.x), num(r.y), num(r.w), num(r.h));
+    }
+
+    let [pr, pg, pb] = PATTERN_RGB;
+    let _ = writeln!(content, "{} {} {} RG 0.5 w", num(pr), num(pg), num(pb));
+    let lines = |content: &mut String, spacing: f32, horizontal: bool, vertical: bool| {
+        let mut y = r.y + spacing;
+        while horizontal && y < r.y + r.h {
+            let _ = writeln!(content, "{} {} m {} {} l S", num(r.x), num(y), num(r.x + r.w), num(y));
+            y += spacing;
+        }
+        let mut x = r.x + spacing;
+        while vertical && x < r.x + r.w {
+            let _ = writeln!(content, "{} {} m {} {} l S", num(x), num(r.y), num(x), num(r.y + r.h));
+            x += spacing;
+        }
+    };
+    match page.background.pattern {
+        Pattern::Blank => {}
+        Pattern::Ruled { spacing } if spacing > 0.0 => lines(content, spacing, true, false),
+        Pattern::Grid { spacing } if spacing > 0.0 => lines(content, spacing, true, true),
+        Pattern::Dots { spacing } if spacing > 0.0 => {
+            // zero length lines with round caps are dots
+            content.push_str("1 J 1.6 w\n");
+            let mut y = r.y + spacing;
+            while y < r.y + r.h {
+                let mut x = r.x + spacing;
+                while x < r.x + r.w {
+                    let _ = writeln!(content, "{} {} m {} {} l S", num(x), num(y), num(x), num(y));
+                    x += spacing;
+                }
+                y += spacing;
+            }
+        }
+        _ => {}
+    }
+}
+
+// switches the fill alpha through the ExtGState with that alpha, added on first use
+fn select_alpha(content: &mut String, alphas: &mut Vec<u8>, current: &mut u8, alpha: f32) {
+    let alpha = alpha_key(alpha);
+    if alpha != *current {
+        let index = alphas.iter().position(|a| *a == alpha).unwrap_or_else(|| {
+            alphas.push(alpha);
+            alphas.len() - 1
+        });
+        let _ = writeln!(content, "/A{} gs", index);
+        *current = alpha;
+    }
+}
+
+// characters 0x80 to 0x9f of WinAnsiEncoding (windows-1252), the other codes
+// above 0x7f are latin-1
+const WIN_ANSI_HIGH: [(u8, char); 27] = [
+    (0x80, '€'), (0x82, '‚'), (0x83, 'ƒ'), (0x84, '„'), (0x85, '…'), (0x86, '†'), (0x87, '‡'),
+    (0x88, 'ˆ'), (0x89, '‰'), (0x8a, 'Š'), (0x8b, '‹'), (0x8c, 'Œ'), (0x8e, 'Ž'), (0x91, '‘'),
+    (0x92, '’'), (0x93, '“'), (0x94, '”'), (0x95, '•'), (0x96, '–'), (0x97, '—'), (0x98, '˜'),
+    (0x99, '™'), (0x9a, 'š'), (0x9b, '›'), (0x9c, 'œ'), (0x9e, 'ž'), (0x9f, 'Ÿ'),
+];
+
+/// Code of `c` in the font's WinAnsiEncoding, if it has one.
+fn win_ansi(c: char) -> Option<u8> {
+    match c {
+        ' '..='~' | '\u{a0}'..='\u{ff}' => Some(c as u8),
+        _ => WIN_ANSI_HIGH.iter().find(|(_, ch)| *ch == c).map(|(code, _)| *code),
+    }
+}
+
+/// The character WinAnsiEncoding puts at `code`.
+fn win_ansi_char(code: u8) -> Option<char> {
+    match code {
+        0x20..=0x7e | 0xa0..=0xff => Some(code as char),
+        _ => WIN_ANSI_HIGH.iter().find(|(c, _)| *c == code).map(|(_, ch)| *ch),
+    }
+}
+
+/// Number of characters in the laid out text of `texts` that the pdf font
+/// can't encode, they are written as '?'.
+pub fn unencodable_chars<'a>(texts: impl IntoIterator<Item = &'a TextBox>) -> usize {
+    texts.into_iter()
+        .flat_map(|t| t.layout())
+        .map(|line| line.text.chars().filter(|c| win_ansi(*c).is_none()).count())
+        .sum()
+}
+
+fn pdf_string(text: &str) -> String {
+    let mut out = String::from("(");
+    for c in text.chars() {
+        match win_ansi(c).unwrap_or(b'?') {
+            code @ (b'(' | b')' | b'\\') => {
+                out.push('\\');
+                out.push(code as char);
+            }
+            code @ 0x20..=0x7e => out.push(code as char),
+            // octal escapes keep the content stream ascii
+            code => {
+                let _ = write!(out, "\\{:03o}", code);
+            }
+        }
+    }
+    out.push(')');
+    out
+}
+
+fn page_content(page: &Page, strokes: &[Stroke], images: &[(usize, &CanvasImage)], texts: &[&TextBox], alphas: &mut Vec<u8>) -> String {
+    let r = page.rect();
+    let mut content = String::new();
+    // clip to the page, then flip y so the rest can use world coordinates
+    let _ = writeln!(content, "q 0 0 {} {} re W n", num(r.w), num(r.h));
+    let _ = writeln!(content, "1 0 0 -1 {} {} cm", num(-r.x), num(r.y + r.h));
+    background_ops(page, &mut content);
+    for (k, image) in images {
+        // the image space is a unit square with its first row at the top
+        let r = image.rect();
+        let _ = writeln!(content, "q {} 0 0 {} {} {} cm /Im{} Do Q", num(r.w), num(-r.h), num(r.x), num(r.y + r.h), k);
+    }
+
+    let mut current_alpha = 255;
+    for stroke in strokes.iter().filter(|s| overlaps_page(s, page)) {
+        let outline = stroke_outline(&stroke.points, &stroke.style);
+        let Some(first) = outline.first() else { continue };
+
+        let [red, green, blue, alpha] = stroke.style.color;
+        select_alpha(&mut content, alphas, &mut current_alpha, alpha);
+        let _ = writeln!(content, "{} {} {} rg", num(red), num(green), num(blue));
+        let _ = writeln!(content, "{} {} m", num(first.x), num(first.y));
+        for p in &outline[1..] {
+            let _ = writeln!(content, "{} {} l", num(p.x), num(p.y));
+        }
+        content.push_str("h f\n");
+    }
+    for text in texts {
+        let [red, green, blue, alpha] = text.color;
+        select_alpha(&mut content, alphas, &mut current_alpha, alpha);
+        let _ = writeln!(content, "{} {} {} rg BT /F1 {} Tf", num(red), num(green), num(blue), num(text.font_size));
+        for line in text.layout() {
+            // undo the page flip, glyphs would be upside down otherwise
+            let _ = writeln!(content, "1 0 0 -1 {} {} Tm {} Tj", num(line.baseline.x), num(line.baseline.y), pdf_string(&line.text));
+        }
+        content.push_str("ET\n");
+    }
+    content.push_str("Q\n");
+    content
+}
+
+fn font_objects(pdf: &mut PdfWriter, font: usize) -> io::Result<()> {
+    let metrics = |c: char| text_box::font().metrics(c, 1000.0);
+    let widths: Vec<String> = (32..=255u8)
+        .map(|code| win_ansi_char(code).map_or("0".to_string(), |c| num(metrics(c).advance_width)))
+        .collect();
+    pdf.object(font, format!(
+        "<< /Type /Font /Subtype /TrueType /BaseFont /{} /FirstChar 32 /LastChar 255 /Widths [{}] /FontDescriptor {} 0 R /Encoding /WinAnsiEncoding >>",
+        text_box::FONT_NAME, widths.join(" "), font + 1
+    ).as_bytes());
+    let ascent = text_box::ascent(1000.0);
+    let descent = text_box::font().horizontal_line_metrics(1000.0).map_or(-200.0, |m| m.descent);
+    // flags: fixed pitch, nonsymbolic
+    pdf.object(font + 1, format!(
+        "<< /Type /FontDescriptor /FontName /{} /Flags 33 /FontBBox [0 {} {} {}] /ItalicAngle 0 /Ascent {} /Descent {} /CapHeight {} /StemV 80 /FontFile2 {} 0 R >>",
+        text_box::FONT_NAME, num(descent), num(metrics('M').advance_width), num(ascent), num(ascent), num(descent), num(ascent), font + 2
+    ).as_bytes());
+    pdf.stream_with(font + 2, &format!("/Length1 {} ", text_box::FONT_BYTES.len()), text_box::FONT_BYTES)
+}
+
+/// Renders every page with the images, strokes and text overlapping it
+/// into a PDF document. Images that can't be decoded are left out.
+pub fn write_pdf(pages: &[Page], strokes: &[Stroke], images: &[CanvasImage], texts: &[TextBox], meta: &DocumentMeta) -> io::Result<Vec<u8>> {
+    let info = 3 + 2 * pages.len();
+    let decoded: Vec<(&CanvasImage, Image)> = images.iter()
+        .filter(|image| pages.iter().any(|p| p.rect().overlaps(&image.rect())))
+        .filter_map(|image| Some((image, canvas_image::decode_png(&image.png).ok()?)))
+        .collect();
+    let texts: Vec<&TextBox> = texts.iter().filter(|t| pages.iter().any(|p| p.rect().overlaps(&t.rect()))).collect();
+    let font = info + 1 + 2 * decoded.len();
+    let mut pdf = PdfWriter::new(font - 1 + if texts.is_empty() { 0 } else { 3 });
+    let page_id = |i: usize| 3 + 2 * i;
+    let image_id = |k: usize| info + 1 + 2 * k;
+
+    pdf.object(1, b"<< /Type /Catalog /Pages 2 0 R >>");
+    let kids: Vec<String> = (0..pages.len()).map(|i| format!("{} 0 R", page_id(i))).collect();
+    pdf.object(2, format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids.join(" "), pages.len()).as_bytes());
+
+    for (i, page) in pages.iter().enumerate() {
+        let mut alphas = vec![255];
+        let on_page: Vec<(usize, &CanvasImage)> = decoded.iter().enumerate()
+            .filter(|(_, (image, _))| page.rect().overlaps(&image.rect()))
+            .map(|(k, (image, _))| (k, *image))
+            .collect();
+        let page_texts: Vec<&TextBox> = texts.iter().copied().filter(|t| page.rect().overlaps(&t.rect())).collect();
+        let content = page_content(page, strokes, &on_page, &page_texts, &mut alphas);
+
+        let states: String = alphas.iter().enumerate()
+            .map(|(k, a)| format!("/A{} << /ca {} /CA {} >> ", k, num(*a as f32 / 255.0), num(*a as f32 / 255.0)))
+            .collect();
+        let xobjects: String = on_page.iter().map(|(k, _)| format!("/Im{} {} 0 R ", k, image_id(*k))).collect();
+        let fonts = if page_texts.is_empty() { String::new() } else { format!("/F1 {} 0 R ", font) };
+        let extent = page.extent();
+        let dict = format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Resources << /ExtGState << {}>> /XObject << {}>> /Font << {}>> >> /Contents {} 0 R >>",
+            num(extent.x), num(extent.y), states, xobjects, fonts, page_id(i) + 1
+        );
+        pdf.object(page_id(i), dict.as_bytes());
+        pdf.stream(page_id(i) + 1, content.as_bytes())?;
+    }
+    pdf.object(info, info_dict(meta).as_bytes());
+    for (k, (_, image)) in decoded.iter().enumerate() {
+        let size = format!("/Width {} /Height {} /BitsPerComponent 8", image.width, image.height);
+        let rgb: Vec<u8> = image.pixels.chunks_exact(4).flat_map(|p| [p[0], p[1], p[2]]).collect();
+        let alpha: Vec<u8> = image.pixels.chunks_exact(4).map(|p| p[3]).collect();
+        let entries = format!("/Type /XObject /Subtype /Image {} /ColorSpace /DeviceRGB /SMask {} 0 R ", size, image_id(k) + 1);
+        pdf.stream_with(image_id(k), &entries, &rgb)?;
+        let entries = format!("/Type /XObject /Subtype /Image {} /ColorSpace /DeviceGray ", size);
+        pdf.stream_with(image_id(k) + 1, &entries, &alpha)?;
+    }
+    if !texts.is_empty() {
+        font_objects(&mut pdf, font)?;
+    }
+    Ok(pdf.finish(info))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use macroquad::math::vec2;
+
+    #[test]
+    fn strings_are_win_ansi() {
+        assert_eq!(pdf_string("a(b)\\"), "(a\\(b\\)\\\\)");
+        assert_eq!(pdf_string("Grüße €5 – “ok”"), "(Gr\\374\\337e \\2005 \\226 \\223ok\\224)");
+        assert_eq!(pdf_string("λ日"), "(??)");
+    }
+
+    #[test]
+    fn every_code_maps_back() {
+        for code in 32..=255u8 {
+            if let Some(c) = win_ansi_char(code) {
+                assert_eq!(win_ansi(c), Some(code), "{:?}", c);
+            }
+        }
+    }
+
+    #[test]
+    fn counts_lost_characters() {
+        let mut text = TextBox::new(1, vec2(0.0, 0.0), 12.0, [0.0, 0.0, 0.0, 1.0]);
+        text.text = "Grüße\nλx日".to_string();
+        assert_eq!(unencodable_chars([&text]), 2);
+    }
+
+    #[test]
+    fn exported_text_keeps_its_accents() {
+        let mut text = TextBox::new(1, vec2(50.0, 50.0), 12.0, [0.0, 0.0, 0.0, 1.0]);
+        text.text = "Café à 5€".to_string();
+        let page = Page::a4(vec2(0.0, 0.0));
+        let pdf = write_pdf(&[page], &[], &[], &[text], &DocumentMeta::default()).unwrap();
+        let doc = lopdf::Document::load_mem(&pdf).unwrap();
+        assert_eq!(doc.extract_text(&[1]).unwrap().trim(), "Café à 5€");
+    }
+}
@@ -0,0 +1,204 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::file_format::{self, CanvasData};
+use crate::inkml_format;
+use crate::lignum_format;
+use crate::rnote_import;
+use crate::xopp_format;
+
+#[derive(Debug)]
+pub enum PersistenceError {
+    Io(io::Error),
+    Parse(String),
+    UnsupportedVersion { found: u32, supported: u32 },
+}
+
+impl fmt::Display for PersistenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PersistenceError::Io(e) => write!(f, "I/O error: {}", e),
+            PersistenceError::Parse(msg) => write!(f, "file is damaged or not a canvas: {}", msg),
+            PersistenceError::UnsupportedVersion { found, supported } => write!(
+                f,
+                "file was written by a newer version (format {}, this build reads up to {})",
+                found, supported
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PersistenceError {}
+
+impl From<io::Error> for PersistenceError {
+    fn from(e: io::Error) -> Self {
+        PersistenceError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for PersistenceError {
+    fn from(e: serde_json::Error) -> Self {
+        PersistenceError::Parse(e.to_string())
+    }
+}
+
+pub struct SkippedStroke {
+    pub index: usize,
+    pub reason: String,
+}
+
+/// Everything that was wrong with a document that could still be loaded.
+#[derive(Default)]
+pub struct LoadReport {
+    pub skipped: Vec<SkippedStroke>,
+    pub truncated: bool,
+    /// Elements of imported files that have no equivalent here, with their count.
+    pub unsupported: Vec<(String, usize)>,
+}
+
+impl LoadReport {
+    pub fn skip(&mut self, index: usize, reason: impl Into<String>) {
+        self.skipped.push(SkippedStroke { index, reason: reason.into() });
+    }
+
+    pub fn unsupported(&mut self, kind: &str) {
+        match self.unsupported.iter_mut().find(|(k, _)| k == kind) {
+            Some((_, count)) => *count += 1,
+            None => self.unsupported.push((kind.to_string(), 1)),
+        }
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.skipped.is_empty() && !self.truncated && self.unsupported.is_empty()
+    }
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.truncated {
+            write!(f, "file is truncated, ")?;
+        }
+        write!(f, "skipped {} damaged stroke(s)", self.skipped.len())?;
+        if !self.unsupported.is_empty() {
+            let kinds: Vec<String> = self.unsupported.iter().map(|(kind, n)| format!("{} {}", n, kind)).collect();
+            write!(f, ", left out unsupported elements ({})", kinds.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+fn is_json_path(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "json")
+}
+
+/// Files of other apps that can be opened but are never saved over.
+pub fn is_foreign(path: &Path) -> bool {
+    rnote_import::is_rnote(path) || xopp_format::is_xopp(path) || inkml_format::is_inkml(path)
+}
+
+/// Writes the document next to `path` first and renames it over the old file
+/// afterwards, so a failing write (e.g. a full disk) never destroys the
+/// previous version.
+pub fn save_document(path: &Path, data: &mut CanvasData) -> Result<(), PersistenceError> {
+    let bytes = if is_json_path(path) {
+        file_format::canvas_to_json(data).map_err(io::Error::from)?.into_bytes()
+    } else {
+        lignum_format::canvas_to_lignum(data)?
+    };
+
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let result = File::create(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(&bytes)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    Ok(result?)
+}
+
+pub fn load_document(path: &Path) -> Result<(CanvasData, LoadReport), PersistenceError> {
+    let mut contents = Vec::new();
+    File::open(path)?.read_to_end(&mut contents)?;
+
+    if rnote_import::is_rnote(path) {
+        return rnote_import::canvas_from_rnote(&contents);
+    }
+    if xopp_format::is_xopp(path) {
+        return xopp_format::canvas_from_xopp(&contents);
+    }
+    if inkml_format::is_inkml(path) {
+        return inkml_format::canvas_from_inkml(&contents);
+    }
+    // binary files are recognized by their magic, everything else is json
+    if lignum_format::is_lignum(&contents) {
+        lignum_format::canvas_from_lignum(&contents)
+    } else {
+        let text = String::from_utf8(contents).map_err(|e| PersistenceError::Parse(e.to_string()))?;
+        file_format::canvas_from_json(&text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_format::{self, InkSample, StrokeData, FORMAT_VERSION};
+
+    fn document() -> CanvasData {
+        let mut data = file_format::tests::expected(FORMAT_VERSION);
+        // smoothed strokes have arbitrary floats, not just round numbers
+        let points: Vec<_> = (0..200)
+            .map(|i| {
+                let t = i as f32 * 0.173;
+                ([t * 3.1 + t.sin() * 4.7, t.cos() * 6.9], 1.5 + (t * 0.37).sin())
+            })
+            .collect();
+        let samples = points.iter().enumerate()
+            .map(|(i, (pos, width))| InkSample {
+                pos: *pos,
+                width: *width,
+                pressure: width / 3.0,
+                tilt: [i as f32 * 0.25 - 20.0, 7.0],
+                time: i as f32 * 4.1,
+            })
+            .collect();
+        data.layers[1].strokes.push(StrokeData {
+            points,
+            style: Default::default(),
+            samples,
+            created: Some(1760000001234),
+        });
+        data
+    }
+
+    fn round_trip(name: &str) {
+        let path = std::env::temp_dir().join(format!("lignum_round_trip_{}_{}", std::process::id(), name));
+        let mut data = document();
+        save_document(&path, &mut data).unwrap();
+        let loaded = load_document(&path);
+        let _ = fs::remove_file(&path);
+
+        let (loaded, report) = loaded.unwrap();
+        assert!(report.is_clean(), "{}", report);
+        assert_eq!(serde_json::to_value(&loaded).unwrap(), serde_json::to_value(document()).unwrap());
+        // saving must not change the document it was given
+        assert_eq!(serde_json::to_value(&data).unwrap(), serde_json::to_value(document()).unwrap());
+    }
+
+    #[test]
+    fn lignum_round_trip() {
+        round_trip("doc.lignum");
+    }
+
+    #[test]
+    fn json_round_trip() {
+        round_trip("doc.json");
+    }
+}
@@ -0,0 +1,250 @@
+// Compact encoding of stroke point streams for the binary .lignum format.
+//
+// Every stream stores x, y and width as separate channels. A channel is
+// quantized to a fixed point grid of 2^-shift, where shift is the smallest
+// value at which every sample of the channel is an exact multiple of the grid,
+// so decoding gives back the very same f32 bits. Consecutive samples are then
+// delta encoded as zigzag varints. Channels that can't be represented this way
+// (nan, inf, -0.0, huge dynamic range) fall back to deltas of the raw bits.
+//
+// Raw input samples use the same channels plus pressure, tilt and time.
+// Their timestamps only grow, so the deltas stay small as well.
+
+use crate::file_format::InkSample;
+
+pub type Point = ([f32;2], f32);
+
+const MODE_QUANTIZED: u8 = 0;
+const MODE_RAW_BITS: u8 = 1;
+
+// keeps |q| well inside the range where f64 holds integers exactly
+const MAX_QUANTIZED: f64 = (1u64 << 52) as f64;
+
+pub fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+pub fn read_varint(bytes: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        let byte = *bytes.get(*pos)?;
+        *pos += 1;
+        if shift >= 64 {
+            return None;
+        }
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+    }
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+/// Smallest shift so that `value * 2^shift` is an integer.
+fn required_shift(value: f32) -> u32 {
+    if value == 0.0 {
+        return 0;
+    }
+    let bits = value.to_bits();
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = if exponent == 0 { bits & 0x7f_ffff } else { (bits & 0x7f_ffff) | 0x80_0000 };
+    // value = mantissa * 2^(exponent - 150), subnormals use exponent 1
+    let exponent = exponent.max(1) - 150 + mantissa.trailing_zeros() as i32;
+    (-exponent).max(0) as u32
+}
+
+fn quantize(channel: &[f32]) -> Option<(u32, Vec<i64>)> {
+    if channel.iter().any(|v| !v.is_finite() || (*v == 0.0 && v.is_sign_negative())) {
+        return None;
+    }
+    let shift = channel.iter().map(|v| required_shift(*v)).max().unwrap_or(0);
+    if shift > 1000 {
+        return None;
+    }
+    let scale = 2f64.powi(shift as i32);
+    let mut quantized = Vec::with_capacity(channel.len());
+    for v in channel {
+        let q = *v as f64 * scale;
+        if q.abs() >= MAX_QUANTIZED {
+            return None;
+        }
+        quantized.push(q as i64);
+    }
+    Some((shift, quantized))
+}
+
+fn encode_channel(channel: &[f32], out: &mut Vec<u8>) {
+    match quantize(channel) {
+        Some((shift, quantized)) => {
+            out.push(MODE_QUANTIZED);
+            write_varint(out, shift as u64);
+            let mut prev = 0i64;
+            for q in quantized {
+                write_varint(out, zigzag(q - prev));
+                prev = q;
+            }
+        }
+        None => {
+            out.push(MODE_RAW_BITS);
+            let mut prev = 0i64;
+            for v in channel {
+                let bits = v.to_bits() as i64;
+                write_varint(out, zigzag(bits - prev));
+                prev = bits;
+            }
+        }
+    }
+}
+
+fn decode_channel(bytes: &[u8], pos: &mut usize, len: usize) -> Option<Vec<f32>> {
+    let mode = *bytes.get(*pos)?;
+    *pos += 1;
+    let mut channel = Vec::with_capacity(len);
+    match mode {
+        MODE_QUANTIZED => {
+            let shift = read_varint(bytes, pos)?;
+            if shift > 1000 {
+                return None;
+            }
+            let scale = 2f64.powi(shift as i32);
+            let mut prev = 0i64;
+            for _ in 0..len {
+                prev = prev.wrapping_add(unzigzag(read_varint(bytes, pos)?));
+                channel.push((prev as f64 / scale) as f32);
+            }
+        }
+        MODE_RAW_BITS => {
+            let mut prev = 0i64;
+            for _ in 0..len {
+                prev = prev.wrapping_add(unzigzag(read_varint(bytes, pos)?));
+                channel.push(f32::from_bits(prev as u32));
+            }
+        }
+        _ => return None,
+    }
+    Some(channel)
+}
+
+pub fn encode_points(points: &[Point], out: &mut Vec<u8>) {
+    write_varint(out, points.len() as u64);
+    let xs: Vec<f32> = points.iter().map(|(p, _)| p[0]).collect();
+    let ys: Vec<f32> = points.iter().map(|(p, _)| p[1]).collect();
+    let ws: Vec<f32> = points.iter().map(|(_, w)| *w).collect();
+    encode_channel(&xs, out);
+    encode_channel(&ys, out);
+    encode_channel(&ws, out);
+}
+
+pub fn decode_points(bytes: &[u8], pos: &mut usize) -> Option<Vec<Point>> {
+    let len = read_varint(bytes, pos)? as usize;
+    // every sample takes at least one byte per channel
+    if len > bytes.len().saturating_sub(*pos) {
+        return None;
+    }
+    let xs = decode_channel(bytes, pos, len)?;
+    let ys = decode_channel(bytes, pos, len)?;
+    let ws = decode_channel(bytes, pos, len)?;
+    Some(xs.into_iter().zip(ys).zip(ws).map(|((x, y), w)| ([x, y], w)).collect())
+}
+
+pub fn encode_samples(samples: &[InkSample], out: &mut Vec<u8>) {
+    write_varint(out, samples.len() as u64);
+    let channels: [fn(&InkSample) -> f32; 7] = [
+        |s| s.pos[0],
+        |s| s.pos[1],
+        |s| s.width,
+        |s| s.pressure,
+        |s| s.tilt[0],
+        |s| s.tilt[1],
+        |s| s.time,
+    ];
+    for channel in channels {
+        let values: Vec<f32> = samples.iter().map(channel).collect();
+        encode_channel(&values, out);
+    }
+}
+
+pub fn decode_samples(bytes: &[u8], pos: &mut usize) -> Option<Vec<InkSample>> {
+    let len = read_varint(bytes, pos)? as usize;
+    if len > bytes.len().saturating_sub(*pos) {
+        return None;
+    }
+    let mut channels = Vec::with_capacity(7);
+    for _ in 0..7 {
+        channels.push(decode_channel(bytes, pos, len)?);
+    }
+    Some((0..len).map(|i| InkSample {
+        pos: [channels[0][i], channels[1][i]],
+        width: channels[2][i],
+        pressure: channels[3][i],
+        tilt: [channels[4][i], channels[5][i]],
+        time: channels[6][i],
+    }).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn points_round_trip_bit_exact() {
+        let points = vec![
+            ([0.1, -7.25], 1.5),
+            ([1e30, 3.0e-20], 0.0),
+            ([f32::NAN, -0.0], f32::INFINITY),
+            ([123.456, 1.0 / 3.0], 0.75),
+        ];
+        let mut bytes = Vec::new();
+        encode_points(&points, &mut bytes);
+        let mut pos = 0;
+        let decoded = decode_points(&bytes, &mut pos).unwrap();
+        assert_eq!(pos, bytes.len());
+        let bits = |p: &[Point]| p.iter().map(|(a, w)| [a[0].to_bits(), a[1].to_bits(), w.to_bits()]).collect::<Vec<_>>();
+        assert_eq!(bits(&decoded), bits(&points));
+    }
+
+    #[test]
+    fn samples_round_trip() {
+        let samples: Vec<InkSample> = (0..50)
+            .map(|i| {
+                let t = i as f32 * 0.31;
+                InkSample {
+                    pos: [t.sin() * 40.0, t * 2.7],
+                    width: 1.0 + t.cos(),
+                    pressure: (i as f32 / 49.0).powf(1.7),
+                    tilt: [-30.0 + i as f32, 12.0],
+                    time: i as f32 * 7.3,
+                }
+            })
+            .collect();
+        let mut bytes = Vec::new();
+        encode_samples(&samples, &mut bytes);
+        encode_samples(&[], &mut bytes);
+        let mut pos = 0;
+        assert_eq!(decode_samples(&bytes, &mut pos).unwrap(), samples);
+        assert_eq!(decode_samples(&bytes, &mut pos).unwrap(), Vec::new());
+        assert_eq!(pos, bytes.len());
+    }
+
+    #[test]
+    fn truncated_streams_are_rejected() {
+        let mut bytes = Vec::new();
+        encode_points(&[([1.5, 2.5], 1.0); 20], &mut bytes);
+        for len in 0..bytes.len() {
+            assert!(decode_points(&bytes[..len], &mut 0).is_none());
+        }
+    }
+}
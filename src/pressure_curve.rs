@@ -0,0 +1,364 @@
+use macroquad::prelude::*;
+use serde::{Deserialize, Deserializer, Serialize};
+
+// Pressure to stroke width. The curve goes through control points, pressure
+// on x and the fraction of the width range on y, both from 0 to 1, and is
+// interpolated with monotone cubic segments (pchip), so it is smooth and
+// never overshoots between points: more pressure never gets a thinner line.
+
+/// Screen pixels around a control point that grab it in the editor.
+const GRAB_RADIUS: f32 = 10.0;
+/// Closest two control points can get on the pressure axis.
+const MIN_GAP: f32 = 0.01;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Preset {
+    /// Wide lines from light pressure.
+    Soft,
+    Linear,
+    /// Needs pressing to get wide, the curve the S640 was tuned with.
+    Firm,
+}
+
+impl Preset {
+    pub const ALL: [Preset; 3] = [Preset::Soft, Preset::Linear, Preset::Firm];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::Soft => "Soft",
+            Preset::Linear => "Linear",
+            Preset::Firm => "Firm",
+        }
+    }
+
+    fn points(self) -> Vec<[f32; 2]> {
+        match self {
+            Preset::Soft => vec![[0.0, 0.0], [0.3, 0.55], [0.7, 0.9], [1.0, 1.0]],
+            Preset::Linear => vec![[0.0, 0.0], [1.0, 1.0]],
+            Preset::Firm => vec![[0.0, 0.0], [0.35, 0.19], [0.7, 0.43], [0.85, 0.62], [1.0, 1.0]],
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PressureCurve {
+    /// `[pressure, fraction]` pairs, ordered by pressure.
+    #[serde(deserialize_with = "sorted_points")]
+    pub points: Vec<[f32; 2]>,
+    /// Width without pressure.
+    pub min_width: f32,
+    /// Width at full pressure.
+    pub max_width: f32,
+}
+
+impl Default for PressureCurve {
+    fn default() -> Self {
+        Self::from_preset(Preset::Firm)
+    }
+}
+
+// hand written configs don't have to keep the points in order
+fn sorted_points<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<[f32; 2]>, D::Error> {
+    let mut points: Vec<[f32; 2]> = Vec::deserialize(deserializer)?;
+    for p in &mut points {
+        *p = [p[0].clamp(0.0, 1.0), p[1].clamp(0.0, 1.0)];
+    }
+    points.sort_by(|a, b| a[0].total_cmp(&b[0]));
+    Ok(points)
+}
+
+impl PressureCurve {
+    pub fn from_preset(preset: Preset) -> Self {
+        Self { points: preset.points(), min_width: 0.0, max_width: 3.5 }
+    }
+
+    /// The preset with exactly these control points, if any.
+    pub fn preset(&self) -> Option<Preset> {
+        Preset::ALL.into_iter().find(|p| p.points() == self.points)
+    }
+
+    pub fn set_preset(&mut self, preset: Preset) {
+        self.points = preset.points();
+    }
+
+    /// Stroke width for a pressure from 0 to 1.
+    pub fn width(&self, pressure: f32) -> f32 {
+        self.min_width + (self.max_width - self.min_width) * self.fraction(pressure)
+    }
+
+    /// Height of the curve at `pressure`, from 0 to 1.
+    pub fn fraction(&self, pressure: f32) -> f32 {
+        let p = &self.points;
+        if p.len() < 2 {
+            return p.first().map_or(pressure, |only| only[1]).clamp(0.0, 1.0);
+        }
+        let x = pressure.clamp(p[0][0], p[p.len() - 1][0]);
+        let i = p.windows(2).position(|w| x <= w[1][0]).unwrap_or(p.len() - 2);
+        let (a, b) = (p[i], p[i + 1]);
+        let h = b[0] - a[0];
+        if h <= f32::EPSILON {
+            return b[1];
+        }
+        let t = (x - a[0]) / h;
+        let (t2, t3) = (t * t, t * t * t);
+        let y = (2.0 * t3 - 3.0 * t2 + 1.0) * a[1]
+            + (t3 - 2.0 * t2 + t) * h * self.tangent(i)
+            + (-2.0 * t3 + 3.0 * t2) * b[1]
+            + (t3 - t2) * h * self.tangent(i + 1);
+        y.clamp(0.0, 1.0)
+    }
+
+    fn secant(&self, i: usize) -> f32 {
+        let (a, b) = (self.points[i], self.points[i + 1]);
+        if b[0] - a[0] <= f32::EPSILON { 0.0 } else { (b[1] - a[1]) / (b[0] - a[0]) }
+    }
+
+    // one sided at the ends, a weighted harmonic mean of the neighbouring
+    // secants inside, zero at peaks and valleys
+    fn tangent(&self, i: usize) -> f32 {
+        let last = self.points.len() - 1;
+        if i == 0 {
+            return self.secant(0);
+        }
+        if i == last {
+            return self.secant(last - 1);
+        }
+        let (before, after) = (self.secant(i - 1), self.secant(i));
+        if before * after <= 0.0 {
+            return 0.0;
+        }
+        let h_before = self.points[i][0] - self.points[i - 1][0];
+        let h_after = self.points[i + 1][0] - self.points[i][0];
+        let (w1, w2) = (2.0 * h_after + h_before, h_after + 2.0 * h_before);
+        (w1 + w2) / (w1 / before + w2 / after)
+    }
+
+    /// Adds a control point between the first and the last one, returns
+    /// its index.
+    pub fn insert(&mut self, point: [f32; 2]) -> Option<usize> {
+        let i = self.points.iter().position(|p| p[0] > point[0])?;
+        if i == 0 || point[0] - self.points[i - 1][0] < MIN_GAP || self.points[i][0] - point[0] < MIN_GAP {
+            return None;
+        }
+        self.points.insert(i, [point[0], point[1].clamp(0.0, 1.0)]);
+        Some(i)
+    }
+
+    /// Moves a control point, staying between its neighbours. The first and
+    /// the last point only move up and down.
+    pub fn move_point(&mut self, i: usize, to: [f32; 2]) {
+        let last = self.points.len() - 1;
+        let x = if i == 0 || i == last {
+            self.points[i][0]
+        } else {
+            to[0].clamp(self.points[i - 1][0] + MIN_GAP, self.points[i + 1][0] - MIN_GAP)
+        };
+        self.points[i] = [x, to[1].clamp(0.0, 1.0)];
+    }
+
+    /// Removes a control point, the first and the last one stay.
+    pub fn remove(&mut self, i: usize) {
+        if i > 0 && i + 1 < self.points.len() {
+            self.points.remove(i);
+        }
+    }
+}
+
+/// Edits a copy of a curve in a panel in the middle of the screen, with the
+/// mouse and keyboard. The caller decides what happens on Enter and Escape.
+pub struct CurveEditor {
+    pub curve: PressureCurve,
+    dragging: Option<usize>,
+}
+
+impl CurveEditor {
+    pub fn new(curve: PressureCurve) -> Self {
+        Self { curve, dragging: None }
+    }
+
+    fn panel() -> Rect {
+        let w = 560.0f32.min(screen_width() - 40.0);
+        let h = 500.0f32.min(screen_height() - 40.0);
+        Rect::new((screen_width() - w) / 2.0, (screen_height() - h) / 2.0, w, h)
+    }
+
+    // the square the curve is plotted in, pressure to the right and width up
+    fn plot() -> Rect {
+        let panel = Self::panel();
+        let size = (panel.w - 80.0).min(panel.h - 150.0).max(50.0);
+        Rect::new(panel.x + (panel.w - size) / 2.0, panel.y + 50.0, size, size)
+    }
+
+    fn to_screen(plot: Rect, point: [f32; 2]) -> Vec2 {
+        vec2(plot.x + point[0] * plot.w, plot.y + (1.0 - point[1]) * plot.h)
+    }
+
+    fn from_screen(plot: Rect, pos: Vec2) -> [f32; 2] {
+        [((pos.x - plot.x) / plot.w).clamp(0.0, 1.0), (1.0 - (pos.y - plot.y) / plot.h).clamp(0.0, 1.0)]
+    }
+
+    fn point_at(&self, plot: Rect, pos: Vec2) -> Option<usize> {
+        self.curve.points.iter().position(|p| Self::to_screen(plot, *p).distance(pos) <= GRAB_RADIUS)
+    }
+
+    /// Dragging moves control points, a click on the plot adds one and a
+    /// right click removes one. `1` to `3` pick a preset, `Up` / `Down`
+    /// change the widest width.
+    pub fn update(&mut self) {
+        let plot = Self::plot();
+        let mouse = Vec2::from(mouse_position());
+        if is_mouse_button_pressed(MouseButton::Left) {
+            self.dragging = self.point_at(plot, mouse);
+            if self.dragging.is_none() && plot.contains(mouse) {
+                self.dragging = self.curve.insert(Self::from_screen(plot, mouse));
+            }
+        }
+        if !is_mouse_button_down(MouseButton::Left) {
+            self.dragging = None;
+        }
+        if let Some(i) = self.dragging {
+            self.curve.move_point(i, Self::from_screen(plot, mouse));
+        }
+        if is_mouse_button_pressed(MouseButton::Right) {
+            if let Some(i) = self.point_at(plot, mouse) {
+                self.curve.remove(i);
+                self.dragging = None;
+            }
+        }
+
+        let preset_keys = [KeyCode::Key1, KeyCode::Key2, KeyCode::Key3];
+        for (key, preset) in preset_keys.into_iter().zip(Preset::ALL) {
+            if is_key_pressed(key) {
+                self.curve.set_preset(preset);
+                self.dragging = None;
+            }
+        }
+        if is_key_pressed(KeyCode::Up) {
+            self.curve.max_width += 0.25;
+        }
+        if is_key_pressed(KeyCode::Down) {
+            self.curve.max_width = (self.curve.max_width - 0.25).max(self.curve.min_width + 0.25);
+        }
+    }
+
+    /// Draws the panel with the live `pressure` of the pen marked on the curve.
+    pub fn draw(&self, pressure: f32) {
+        let panel = Self::panel();
+        let plot = Self::plot();
+        draw_rectangle(panel.x, panel.y, panel.w, panel.h, Color::new(0.97, 0.97, 0.97, 0.97));
+        draw_rectangle_lines(panel.x, panel.y, panel.w, panel.h, 2.0, DARKGRAY);
+        let name = self.curve.preset().map_or("Custom", Preset::name);
+        draw_text(format!("Pressure curve: {}", name), panel.x + 12.0, panel.y + 30.0, 24.0, BLACK);
+
+        for k in 0..=4 {
+            let f = k as f32 / 4.0;
+            let grid = Color::new(0.0, 0.0, 0.0, 0.12);
+            draw_line(plot.x + f * plot.w, plot.y, plot.x + f * plot.w, plot.y + plot.h, 1.0, grid);
+            draw_line(plot.x, plot.y + f * plot.h, plot.x + plot.w, plot.y + f * plot.h, 1.0, grid);
+        }
+        draw_line(plot.x, plot.y + plot.h, plot.x + plot.w, plot.y, 1.0, Color::new(0.0, 0.0, 0.0, 0.25));
+        draw_rectangle_lines(plot.x, plot.y, plot.w, plot.h, 1.0, DARKGRAY);
+
+        let steps = 100;
+        let curve_point = |x: f32| Self::to_screen(plot, [x, self.curve.fraction(x)]);
+        for k in 0..steps {
+            let (a, b) = (curve_point(k as f32 / steps as f32), curve_point((k + 1) as f32 / steps as f32));
+            draw_line(a.x, a.y, b.x, b.y, 2.0, DARKBLUE);
+        }
+        for (i, p) in self.curve.points.iter().enumerate() {
+            let pos = Self::to_screen(plot, *p);
+            let color = if self.dragging == Some(i) { ORANGE } else { DARKBLUE };
+            draw_circle(pos.x, pos.y, 5.0, color);
+        }
+
+        let pressure = pressure.clamp(0.0, 1.0);
+        let live = curve_point(pressure);
+        draw_line(live.x, plot.y, live.x, plot.y + plot.h, 1.0, RED);
+        draw_circle(live.x, live.y, 6.0, RED);
+
+        let y = plot.y + plot.h + 30.0;
+        let values = format!(
+            "pressure {:.2}   width {:.2}   (from {:.2} to {:.2})",
+            pressure, self.curve.width(pressure), self.curve.min_width, self.curve.max_width
+        );
+        draw_text(&values, panel.x + 12.0, y, 20.0, BLACK);
+        let help = [
+            "Drag points to move them, click to add one, right click to remove it",
+            "1 Soft  2 Linear  3 Firm   Up / Down: widest width   Enter saves, Esc cancels",
+        ];
+        for (k, line) in help.iter().enumerate() {
+            draw_text(line, panel.x + 12.0, y + 26.0 * (k + 1) as f32, 18.0, DARKGRAY);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curve(points: &[[f32; 2]]) -> PressureCurve {
+        PressureCurve { points: points.to_vec(), ..PressureCurve::default() }
+    }
+
+    fn assert_ordered(curve: &PressureCurve) {
+        for w in curve.points.windows(2) {
+            assert!(w[1][0] - w[0][0] >= MIN_GAP - 1e-6, "{:?} too close", curve.points);
+        }
+    }
+
+    #[test]
+    fn fraction_is_monotone_through_the_points() {
+        let mut curves: Vec<PressureCurve> = Preset::ALL.into_iter().map(PressureCurve::from_preset).collect();
+        curves.push(curve(&[[0.0, 0.1], [0.2, 0.15], [0.25, 0.8], [0.9, 0.81], [1.0, 1.0]]));
+        curves.push(curve(&[[0.0, 0.0], [0.4, 0.5], [0.6, 0.5], [1.0, 1.0]]));
+        for c in &curves {
+            for p in &c.points {
+                assert!((c.fraction(p[0]) - p[1]).abs() < 1e-6, "{:?} misses {:?}", c.points, p);
+            }
+            let mut last = c.fraction(0.0);
+            for k in 1..=1000 {
+                let y = c.fraction(k as f32 / 1000.0);
+                assert!(y >= last - 1e-6, "{:?} falls at {}", c.points, k);
+                last = y;
+            }
+            // pressure outside the points keeps the value at the ends
+            assert_eq!(c.fraction(-1.0), c.fraction(0.0));
+            assert_eq!(c.fraction(2.0), c.fraction(1.0));
+        }
+        // equal neighbours give a flat stretch without a bump
+        let plateau = &curves[curves.len() - 1];
+        assert!((plateau.fraction(0.5) - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn editing_keeps_the_points_apart() {
+        let mut c = PressureCurve::from_preset(Preset::Linear);
+        assert_eq!(c.insert([0.5, 0.3]), Some(1));
+        assert_eq!(c.insert([0.25, 1.5]), Some(1));
+        assert_eq!(c.points, [[0.0, 0.0], [0.25, 1.0], [0.5, 0.3], [1.0, 1.0]]);
+        // too close to a neighbour, or outside the first and last point
+        assert_eq!(c.insert([0.505, 0.5]), None);
+        assert_eq!(c.insert([0.0, 0.5]), None);
+        assert_eq!(c.insert([1.0, 0.5]), None);
+        assert_eq!(c.insert([1.5, 0.5]), None);
+        assert_eq!(c.points.len(), 4);
+
+        c.move_point(1, [0.9, -0.5]);
+        assert_eq!(c.points[1], [0.5 - MIN_GAP, 0.0]);
+        c.move_point(2, [0.0, 0.7]);
+        assert_eq!(c.points[2], [c.points[1][0] + MIN_GAP, 0.7]);
+        c.move_point(0, [0.3, 0.2]);
+        c.move_point(3, [0.3, 0.9]);
+        assert_eq!((c.points[0], c.points[3]), ([0.0, 0.2], [1.0, 0.9]));
+        assert_ordered(&c);
+
+        c.remove(0);
+        c.remove(3);
+        c.remove(7);
+        assert_eq!(c.points.len(), 4);
+        c.remove(1);
+        assert_eq!(c.points.len(), 3);
+        assert_ordered(&c);
+    }
+}
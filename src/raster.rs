@@ -0,0 +1,402 @@
+use macroquad::prelude::*;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use crate::canvas_image;
+use crate::export;
+use crate::file_format::CanvasData;
+use crate::page::{self, Page, PageLayout, Pattern};
+use crate::pdf_background::{self, PdfPage};
+use crate::stroke_to_world_submeshes;
+use crate::text_box::{self, TextBox};
+
+// Software rendering for machines without a gpu. Strokes go through the same
+// meshing as on screen and their triangles are filled on the cpu. Drawing at
+// twice the resolution and averaging down smooths the edges without the
+// seams that per triangle coverage would leave between neighbouring
+// triangles.
+
+const SUPERSAMPLE: usize = 2;
+/// Largest image side the rasterizer agrees to produce, in pixels.
+pub const MAX_SIDE: u32 = 16384;
+
+const DESK_COLOR: [u8; 4] = [209, 209, 214, 255];
+const GRID_COLOR: [u8; 4] = [0, 0, 0, 128];
+const PATTERN_COLOR: [u8; 4] = [140, 166, 204, 153];
+
+pub struct RenderOptions {
+    /// Pixels per inch, world units are points (1/72 inch).
+    pub dpi: f32,
+    /// World rectangle to render, by default all pages with ink.
+    pub region: Option<Rect>,
+    /// Program that rasterizes the pages of an imported pdf.
+    pub pdf_renderer: String,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self { dpi: 96.0, region: None, pdf_renderer: pdf_background::DEFAULT_RENDERER.to_string() }
+    }
+}
+
+/// Straight (not premultiplied) rgba8 pixels.
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<[u8; 4]>,
+    origin: Vec2,
+    scale: f32, // supersampled pixels per world unit
+}
+
+impl Canvas {
+    fn to_pixel(&self, world: Vec2) -> Vec2 {
+        (world - self.origin) * self.scale
+    }
+
+    fn blend(&mut self, x: usize, y: usize, color: [u8; 4]) {
+        let dst = &mut self.pixels[y * self.width + x];
+        let sa = color[3] as f32 / 255.0;
+        let da = dst[3] as f32 / 255.0;
+        let out_a = sa + da * (1.0 - sa);
+        if out_a <= 0.0 {
+            return;
+        }
+        for c in 0..3 {
+            let mixed = (color[c] as f32 * sa + dst[c] as f32 * da * (1.0 - sa)) / out_a;
+            dst[c] = mixed.round() as u8;
+        }
+        dst[3] = (out_a * 255.0).round() as u8;
+    }
+
+    // world space rectangle, snapped to whole pixels
+    fn fill_rect(&mut self, min: Vec2, max: Vec2, color: [u8; 4]) {
+        let a = self.to_pixel(min);
+        let b = self.to_pixel(max);
+        let x0 = a.x.round().clamp(0.0, self.width as f32) as usize;
+        let x1 = b.x.round().clamp(0.0, self.width as f32) as usize;
+        let y0 = a.y.round().clamp(0.0, self.height as f32) as usize;
+        let y1 = b.y.round().clamp(0.0, self.height as f32) as usize;
+        for y in y0..y1 {
+            for x in x0..x1 {
+                self.blend(x, y, color);
+            }
+        }
+    }
+
+    // axis aligned line `px` output pixels wide
+    fn line(&mut self, from: Vec2, to: Vec2, px: f32, color: [u8; 4]) {
+        let half = px * SUPERSAMPLE as f32 / self.scale * 0.5;
+        let min = from.min(to) - Vec2::splat(half);
+        let max = from.max(to) + Vec2::splat(half);
+        self.fill_rect(min, max, color);
+    }
+
+    // nearest neighbour sampling, images are rarely scaled much on a page
+    fn draw_image(&mut self, rect: Rect, image: &Image) {
+        let a = self.to_pixel(vec2(rect.x, rect.y));
+        let b = self.to_pixel(vec2(rect.x + rect.w, rect.y + rect.h));
+        if b.x <= a.x || b.y <= a.y || image.width == 0 || image.height == 0 {
+            return;
+        }
+        let x0 = a.x.round().clamp(0.0, self.width as f32) as usize;
+        let x1 = b.x.round().clamp(0.0, self.width as f32) as usize;
+        let y0 = a.y.round().clamp(0.0, self.height as f32) as usize;
+        let y1 = b.y.round().clamp(0.0, self.height as f32) as usize;
+        let (sx, sy) = (image.width as f32 / (b.x - a.x), image.height as f32 / (b.y - a.y));
+        for y in y0..y1 {
+            let iy = (((y as f32 + 0.5 - a.y) * sy) as usize).min(image.height as usize - 1);
+            for x in x0..x1 {
+                let ix = (((x as f32 + 0.5 - a.x) * sx) as usize).min(image.width as usize - 1);
+                let i = (iy * image.width as usize + ix) * 4;
+                let p = &image.pixels[i..i + 4];
+                self.blend(x, y, [p[0], p[1], p[2], p[3]]);
+            }
+        }
+    }
+
+    fn draw_text(&mut self, text: &TextBox) {
+        let px = text.font_size * self.scale;
+        let color = text.color.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+        for line in text.layout() {
+            let mut pen = self.to_pixel(line.baseline);
+            for c in line.text.chars() {
+                let (m, coverage) = text_box::font().rasterize(c, px);
+                // the bitmap's bottom left corner is at (xmin, ymin) from the pen
+                let left = pen.x.round() as i64 + m.xmin as i64;
+                let top = pen.y.round() as i64 - m.height as i64 - m.ymin as i64;
+                for (i, a) in coverage.iter().enumerate().filter(|(_, a)| **a > 0) {
+                    let (x, y) = (left + (i % m.width) as i64, top + (i / m.width) as i64);
+                    if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
+                        let alpha = (color[3] as u32 * *a as u32 / 255) as u8;
+                        self.blend(x as usize, y as usize, [color[0], color[1], color[2], alpha]);
+                    }
+                }
+                pen.x += m.advance_width;
+            }
+        }
+    }
+
+    fn fill_triangle(&mut self, a: Vec2, b: Vec2, c: Vec2, color: [u8; 4]) {
+        let (a, b, c) = (self.to_pixel(a), self.to_pixel(b), self.to_pixel(c));
+        let area = (b - a).perp_dot(c - a);
+        if area == 0.0 || !area.is_finite() {
+            return;
+        }
+        // same winding for all triangles, so inside is always w > 0
+        let (b, c) = if area < 0.0 { (c, b) } else { (b, c) };
+
+        let x0 = a.x.min(b.x).min(c.x).floor().max(0.0) as usize;
+        let y0 = a.y.min(b.y).min(c.y).floor().max(0.0) as usize;
+        let x1 = (a.x.max(b.x).max(c.x).ceil().max(0.0) as usize).min(self.width);
+        let y1 = (a.y.max(b.y).max(c.y).ceil().max(0.0) as usize).min(self.height);
+
+        // top-left rule, so a sample on an edge shared by two triangles is
+        // filled exactly once
+        let edge = |p: Vec2, q: Vec2| {
+            let d = q - p;
+            let top_left = (d.y == 0.0 && d.x > 0.0) || d.y < 0.0;
+            (p, d, top_left)
+        };
+        let edges = [edge(a, b), edge(b, c), edge(c, a)];
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let sample = vec2(x as f32 + 0.5, y as f32 + 0.5);
+                let inside = edges.iter().all(|(p, d, top_left)| {
+                    let w = d.perp_dot(sample - *p);
+                    w > 0.0 || (w == 0.0 && *top_left)
+                });
+                if inside {
+                    self.blend(x, y, color);
+                }
+            }
+        }
+    }
+
+    fn downsample(self) -> Image {
+        let width = self.width / SUPERSAMPLE;
+        let height = self.height / SUPERSAMPLE;
+        let mut pixels = Vec::with_capacity(width * height * 4);
+        let samples = (SUPERSAMPLE * SUPERSAMPLE) as f32;
+        for y in 0..height {
+            for x in 0..width {
+                // average premultiplied values, then unpremultiply
+                let mut sum = [0.0f32; 4];
+                for sy in 0..SUPERSAMPLE {
+                    for sx in 0..SUPERSAMPLE {
+                        let p = self.pixels[(y * SUPERSAMPLE + sy) * self.width + x * SUPERSAMPLE + sx];
+                        let a = p[3] as f32;
+                        for c in 0..3 {
+                            sum[c] += p[c] as f32 * a;
+                        }
+                        sum[3] += a;
+                    }
+                }
+                let alpha = sum[3] / samples;
+                for c in sum.iter().take(3) {
+                    pixels.push(if sum[3] > 0.0 { (c / sum[3]).round() as u8 } else { 0 });
+                }
+                pixels.push(alpha.round() as u8);
+            }
+        }
+        Image { width: width as u32, height: height as u32, pixels }
+    }
+}
+
+fn draw_pattern(canvas: &mut Canvas, page: &Page) {
+    let r = page.rect();
+    let (spacing, horizontal, vertical, dots) = match page.background.pattern {
+        Pattern::Blank => return,
+        Pattern::Ruled { spacing } => (spacing, true, false, false),
+        Pattern::Grid { spacing } => (spacing, true, true, false),
+        Pattern::Dots { spacing } => (spacing, false, false, true),
+    };
+    // like on screen, patterns finer than a few pixels are left out
+    if spacing * canvas.scale / (SUPERSAMPLE as f32) < 4.0 {
+        return;
+    }
+    let mut y = r.y + spacing;
+    while y < r.y + r.h {
+        if horizontal {
+            canvas.line(vec2(r.x, y), vec2(r.x + r.w, y), 1.0, PATTERN_COLOR);
+        }
+        let mut x = r.x + spacing;
+        while dots && x < r.x + r.w {
+            canvas.line(vec2(x, y), vec2(x, y), 2.0, PATTERN_COLOR);
+            x += spacing;
+        }
+        y += spacing;
+    }
+    let mut x = r.x + spacing;
+    while vertical && x < r.x + r.w {
+        canvas.line(vec2(x, r.y), vec2(x, r.y + r.h), 1.0, PATTERN_COLOR);
+        x += spacing;
+    }
+}
+
+fn draw_background(canvas: &mut Canvas, layout: &PageLayout, region: Rect) {
+    match layout {
+        PageLayout::Infinite => {
+            canvas.pixels.fill([255, 255, 255, 255]);
+            let [a4_w, a4_h] = page::A4_SIZE;
+            let (x0, x1) = ((region.x / a4_w).floor() as i32, ((region.x + region.w) / a4_w).ceil() as i32);
+            let (y0, y1) = ((region.y / a4_h).floor() as i32, ((region.y + region.h) / a4_h).ceil() as i32);
+            for x in x0..=x1 {
+                let x = x as f32 * a4_w;
+                canvas.line(vec2(x, region.y), vec2(x, region.y + region.h), 1.0, GRID_COLOR);
+            }
+            for y in y0..=y1 {
+                let y = y as f32 * a4_h;
+                canvas.line(vec2(region.x, y), vec2(region.x + region.w, y), 1.0, GRID_COLOR);
+            }
+        }
+        PageLayout::Pages(pages) => {
+            canvas.pixels.fill(DESK_COLOR);
+            for page in pages.iter().filter(|p| p.rect().overlaps(&region)) {
+                let r = page.rect();
+                let bg = page.background.color.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+                canvas.fill_rect(vec2(r.x, r.y), vec2(r.x + r.w, r.y + r.h), bg);
+                draw_pattern(canvas, page);
+                let (tl, br) = (vec2(r.x, r.y), vec2(r.x + r.w, r.y + r.h));
+                canvas.line(tl, vec2(br.x, tl.y), 1.0, GRID_COLOR);
+                canvas.line(vec2(tl.x, br.y), br, 1.0, GRID_COLOR);
+                canvas.line(tl, vec2(tl.x, br.y), 1.0, GRID_COLOR);
+                canvas.line(vec2(br.x, tl.y), br, 1.0, GRID_COLOR);
+            }
+        }
+    }
+}
+
+/// Area rendered when no region is given: the bounding box of all export pages.
+pub fn default_region(data: &CanvasData) -> Option<Rect> {
+    let strokes = export::visible_strokes(data);
+    export::export_pages(data, &strokes)
+        .iter()
+        .map(Page::rect)
+        .reduce(|a, b| a.combine_with(b))
+}
+
+/// Renders the visible layers of a document, without needing a window.
+pub fn render(data: &CanvasData, options: &RenderOptions) -> io::Result<Image> {
+    let region = options.region.or_else(|| default_region(data))
+        .ok_or_else(|| io::Error::other("there is nothing to render, the canvas is empty"))?;
+    if !options.dpi.is_finite() || options.dpi <= 0.0 || region.w <= 0.0 || region.h <= 0.0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "dpi and region size have to be positive"));
+    }
+    let scale = options.dpi / 72.0;
+    let width = (region.w * scale).round().max(1.0);
+    let height = (region.h * scale).round().max(1.0);
+    if width > MAX_SIDE as f32 || height > MAX_SIDE as f32 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{}x{} pixels is too large, lower the dpi or pick a smaller region", width, height),
+        ));
+    }
+
+    let (width, height) = (width as usize * SUPERSAMPLE, height as usize * SUPERSAMPLE);
+    let mut canvas = Canvas {
+        width,
+        height,
+        pixels: vec![[0; 4]; width * height],
+        origin: vec2(region.x, region.y),
+        scale: scale * SUPERSAMPLE as f32,
+    };
+    draw_background(&mut canvas, &data.pages, region);
+    if let Some(background) = &data.pdf {
+        let shown: Vec<&PdfPage> = background.pages.iter().filter(|p| p.rect().overlaps(&region)).collect();
+        if let (Some(first), Some(last)) = (shown.iter().map(|p| p.index).min(), shown.iter().map(|p| p.index).max()) {
+            let rendered = pdf_background::render_pages(&options.pdf_renderer, &background.pdf, options.dpi * SUPERSAMPLE as f32, first, last)
+                .map_err(io::Error::other)?;
+            for page in shown {
+                if let Some((_, image)) = rendered.iter().find(|(index, _)| *index == page.index) {
+                    canvas.draw_image(page.rect(), image);
+                }
+            }
+        }
+    }
+    for image in data.images.iter().filter(|i| i.rect().overlaps(&region)) {
+        if let Ok(decoded) = canvas_image::decode_png(&image.png) {
+            canvas.draw_image(image.rect(), &decoded);
+        }
+    }
+
+    for stroke in export::visible_strokes(data) {
+        for mesh in stroke_to_world_submeshes(&stroke.points, &stroke.style, 800) {
+            for tri in mesh.indices.chunks_exact(3) {
+                let v = |i: u16| &mesh.vertices[i as usize];
+                let (a, b, c) = (v(tri[0]), v(tri[1]), v(tri[2]));
+                canvas.fill_triangle(a.position.truncate(), b.position.truncate(), c.position.truncate(), a.color);
+            }
+        }
+    }
+    for text in data.texts.iter().filter(|t| t.rect().overlaps(&region)) {
+        canvas.draw_text(text);
+    }
+    Ok(canvas.downsample())
+}
+
+pub fn write_png(path: &Path, image: &Image) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, image.width, image.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(&image.pixels).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_format::{DocumentMeta, LayerData, StrokeData, FORMAT_VERSION};
+    use crate::layer::LayerProps;
+    use crate::stroke_style::StrokeStyle;
+    use crate::ToolMode;
+
+    fn pixel(image: &Image, x: u32, y: u32) -> [u8; 4] {
+        let i = 4 * (y * image.width + x) as usize;
+        image.pixels[i..i + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn renders_a_stroke_on_a_page() {
+        let style = StrokeStyle { color: [1.0, 0.0, 0.0, 1.0], ..StrokeStyle::default() };
+        let stroke = StrokeData {
+            points: vec![([100.0, 200.0], 2.0), ([400.0, 200.0], 2.0)],
+            style,
+            samples: Vec::new(),
+            created: None,
+        };
+        let data = CanvasData {
+            format_version: FORMAT_VERSION,
+            meta: DocumentMeta::default(),
+            layers: vec![LayerData { id: 0, props: LayerProps::named("Ink".to_string()), strokes: vec![stroke] }],
+            active_layer: 0,
+            pages: PageLayout::Pages(vec![Page::a4(vec2(0.0, 0.0))]),
+            images: Vec::new(),
+            texts: Vec::new(),
+            pdf: None,
+            tool_mode: ToolMode::Pen,
+            offset: [0.0, 0.0],
+            zoom: 1.0,
+        };
+        // two pixels per point
+        let image = render(&data, &RenderOptions { dpi: 144.0, ..Default::default() }).unwrap();
+        assert_eq!((image.width, image.height), (1190, 1684));
+        assert_eq!(image.pixels.len(), 1190 * 1684 * 4);
+
+        for x in [220, 500, 780] {
+            assert_eq!(pixel(&image, x, 400), [255, 0, 0, 255], "on the stroke at x {}", x);
+        }
+        // off the stroke: above, below and past its ends
+        for (x, y) in [(500, 380), (500, 420), (150, 400), (850, 400), (600, 1200)] {
+            assert_eq!(pixel(&image, x, y), [255, 255, 255, 255], "off the stroke at {},{}", x, y);
+        }
+    }
+}
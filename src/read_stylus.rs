@@ -1,38 +1,190 @@
 
-use evdev::{AbsoluteAxisType, Device, InputEventKind};
+use evdev::{AbsoluteAxisType, Device, InputEvent, InputEventKind, Key, MiscType, Synchronization};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::sync::mpsc::Sender;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
+
+/// State of the pen at the end of one evdev report, raw device values.
+#[derive(Clone, Copy, Debug)]
+pub struct StylusSample {
+    pub pos: [i32; 2],
+    pub pressure: i32,
+    pub tilt: [i32; 2],
+    /// When the kernel received the report.
+    pub time: SystemTime,
+}
+
+/// Which end of the pen is used.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Tool {
+    Pen,
+    Rubber,
+}
+
+impl Tool {
+    /// The tool a `BTN_TOOL_*` key stands for, pencils, brushes and
+    /// airbrushes draw like a pen.
+    fn of(key: Key) -> Option<Tool> {
+        match key {
+            Key::BTN_TOOL_PEN | Key::BTN_TOOL_PENCIL | Key::BTN_TOOL_BRUSH | Key::BTN_TOOL_AIRBRUSH => Some(Tool::Pen),
+            Key::BTN_TOOL_RUBBER => Some(Tool::Rubber),
+            _ => None,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub enum StylusEvent {
-    Absolute { axis: evdev::AbsoluteAxisType, value: i32 },
-    Tilt { axis: evdev::AbsoluteAxisType, value: i32 },
-    Pressure {value: i32 },
-    Key { key: evdev::Key, value: i32 },
+    /// Sent for every report that moved the pen or changed its pressure or tilt.
+    Sample(StylusSample),
+    Key { key: Key, value: i32 },
+    /// The pen came into range of the tablet (hovering or touching) or left it.
+    Proximity(bool),
+    /// Raw height of the hovering pen above the tablet.
+    Distance(i32),
+    /// The pen was turned around, sent before it comes into range with the other end.
+    Tool(Tool),
+    /// Serial number of the pen, sent when a different one comes into range.
+    Serial(u32),
+    /// Raw position of the wheel on airbrushes and some tablets.
+    Wheel(i32),
 }
 
-pub fn read_input(device_path: String, sender: Sender<StylusEvent>) {
-    thread::spawn(move || {
-        let mut device = Device::open(device_path).expect("Could not open device");
+/// Range of an absolute axis as the device reports it, `resolution` is in
+/// units per millimeter and 0 if the device doesn't say.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct AxisRange {
+    pub min: i32,
+    pub max: i32,
+    #[serde(default)]
+    pub resolution: i32,
+}
+
+impl AxisRange {
+    pub fn read(device: &Device, axis: AbsoluteAxisType) -> Option<AxisRange> {
+        if !device.supported_absolute_axes()?.contains(axis) {
+            return None;
+        }
+        let info = device.get_abs_state().ok()?[axis.0 as usize];
+        (info.maximum > info.minimum).then_some(AxisRange { min: info.minimum, max: info.maximum, resolution: info.resolution })
+    }
+
+    /// `value` as a fraction of the range, 0 at `min` and 1 at `max`.
+    pub fn normalize(&self, value: i32) -> f32 {
+        (value - self.min) as f32 / (self.max - self.min) as f32
+    }
 
+    /// Length of the axis in millimeters, or in device units without a resolution.
+    pub fn length(&self) -> f32 {
+        (self.max - self.min) as f32 / self.resolution.max(1) as f32
+    }
+}
+
+/// Turns evdev events into stylus events. Axis values are collected until
+/// the end of a report, anything that isn't a stylus event is logged once and
+/// skipped, so tablets with more axes or buttons than the ones used here work.
+pub struct Decoder {
+    // axis values carry over between reports, a report only has the axes that changed
+    sample: StylusSample,
+    changed: bool,
+    tool: Tool,
+    serial: u32,
+    ignored: HashSet<(u16, u16)>, // event type and code
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self {
+            sample: StylusSample { pos: [0; 2], pressure: 0, tilt: [0; 2], time: SystemTime::now() },
+            changed: false,
+            tool: Tool::Pen,
+            serial: 0,
+            ignored: HashSet::new(),
+        }
+    }
+
+    /// Adds the stylus events `event` results in to `out`.
+    pub fn decode(&mut self, event: InputEvent, out: &mut Vec<StylusEvent>) {
+        match event.kind() {
+            InputEventKind::AbsAxis(axis) => match axis {
+                AbsoluteAxisType::ABS_DISTANCE => out.push(StylusEvent::Distance(event.value())),
+                AbsoluteAxisType::ABS_WHEEL => out.push(StylusEvent::Wheel(event.value())),
+                _ => match self.sample_value(axis) {
+                    Some(value) => {
+                        *value = event.value();
+                        self.changed = true;
+                    }
+                    None => self.ignore(&event),
+                },
+            },
+            InputEventKind::Key(key) => match Tool::of(key) {
+                Some(tool) if event.value() == 1 => {
+                    if tool != self.tool {
+                        self.tool = tool;
+                        out.push(StylusEvent::Tool(tool));
+                    }
+                    out.push(StylusEvent::Proximity(true));
+                }
+                // only the end in use leaving takes the pen out of range
+                Some(tool) if event.value() == 0 && tool == self.tool => out.push(StylusEvent::Proximity(false)),
+                Some(_) => {}
+                None => out.push(StylusEvent::Key { key, value: event.value() }),
+            },
+            InputEventKind::Misc(MiscType::MSC_SERIAL) => {
+                let serial = event.value() as u32;
+                if serial != 0 && serial != self.serial {
+                    self.serial = serial;
+                    out.push(StylusEvent::Serial(serial));
+                }
+            }
+            InputEventKind::Synchronization(Synchronization::SYN_REPORT) if self.changed => {
+                self.changed = false;
+                self.sample.time = event.timestamp();
+                out.push(StylusEvent::Sample(self.sample));
+            }
+            InputEventKind::Synchronization(_) => {}
+            _ => self.ignore(&event),
+        }
+    }
+
+    // where a sample keeps the value of an axis
+    fn sample_value(&mut self, axis: AbsoluteAxisType) -> Option<&mut i32> {
+        match axis {
+            AbsoluteAxisType::ABS_X => Some(&mut self.sample.pos[0]),
+            AbsoluteAxisType::ABS_Y => Some(&mut self.sample.pos[1]),
+            AbsoluteAxisType::ABS_TILT_X => Some(&mut self.sample.tilt[0]),
+            AbsoluteAxisType::ABS_TILT_Y => Some(&mut self.sample.tilt[1]),
+            AbsoluteAxisType::ABS_PRESSURE => Some(&mut self.sample.pressure),
+            _ => None,
+        }
+    }
+
+    fn ignore(&mut self, event: &InputEvent) {
+        if self.ignored.insert((event.event_type().0, event.code())) {
+            eprintln!("Ignoring {:?} events from the stylus", event.kind());
+        }
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn read_input(mut device: Device, sender: Sender<StylusEvent>) {
+    thread::spawn(move || {
+        let mut decoder = Decoder::new();
+        let mut decoded = Vec::new();
         loop {
             match device.fetch_events() {
                 Ok(events) => {
                     for event in events {
-                        let stylus_event = match event.kind() {
-                            InputEventKind::AbsAxis(axis) => {
-                                match axis {
-                                    AbsoluteAxisType::ABS_X | AbsoluteAxisType::ABS_Y => StylusEvent::Absolute { axis, value: event.value() },
-                                    AbsoluteAxisType::ABS_TILT_X | AbsoluteAxisType::ABS_TILT_Y => StylusEvent::Tilt { axis, value: event.value() },
-                                    AbsoluteAxisType::ABS_PRESSURE => StylusEvent::Pressure { value: event.value() },
-                                    _ => panic!("Unhandled event in read stylus: {:?}", event)
-                                }
-                            },
-                            InputEventKind::Key(key) => StylusEvent::Key { key, value: event.value() },
-                            InputEventKind::Synchronization(_) => continue,
-                            _ => panic!("Unknown Event: {:?}", event),
-                        };
+                        decoder.decode(event, &mut decoded);
+                    }
+                    for stylus_event in decoded.drain(..) {
                         if sender.send(stylus_event).is_err() {
                             // Empfänger wurde geschlossen
                             return;
@@ -48,3 +200,138 @@ pub fn read_input(device_path: String, sender: Sender<StylusEvent>) {
     });
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use evdev::EventType;
+
+    // shorthands for recorded events
+    fn abs(axis: AbsoluteAxisType, value: i32) -> InputEvent {
+        InputEvent::new(EventType::ABSOLUTE, axis.0, value)
+    }
+
+    fn key(key: Key, value: i32) -> InputEvent {
+        InputEvent::new(EventType::KEY, key.code(), value)
+    }
+
+    fn syn() -> InputEvent {
+        InputEvent::new(EventType::SYNCHRONIZATION, Synchronization::SYN_REPORT.0, 0)
+    }
+
+    fn decode(decoder: &mut Decoder, events: &[InputEvent]) -> Vec<StylusEvent> {
+        let mut out = Vec::new();
+        for event in events {
+            decoder.decode(*event, &mut out);
+        }
+        out
+    }
+
+    #[test]
+    fn hover_and_proximity() {
+        let mut decoder = Decoder::new();
+        let out = decode(&mut decoder, &[
+            key(Key::BTN_TOOL_PEN, 1),
+            abs(AbsoluteAxisType::ABS_X, 1200),
+            abs(AbsoluteAxisType::ABS_Y, 800),
+            abs(AbsoluteAxisType::ABS_DISTANCE, 30),
+            syn(),
+            abs(AbsoluteAxisType::ABS_DISTANCE, 12),
+            syn(),
+            key(Key::BTN_TOOL_PEN, 0),
+            syn(),
+        ]);
+        assert!(matches!(out[0], StylusEvent::Proximity(true)));
+        assert!(matches!(out[1], StylusEvent::Distance(30)));
+        assert!(matches!(out[2], StylusEvent::Sample(s) if s.pos == [1200, 800] && s.pressure == 0));
+        // distance alone doesn't make a new sample
+        assert!(matches!(out[3], StylusEvent::Distance(12)));
+        assert!(matches!(out[4], StylusEvent::Proximity(false)));
+        assert_eq!(out.len(), 5);
+    }
+
+    #[test]
+    fn axes_carry_over_between_reports() {
+        let mut decoder = Decoder::new();
+        let out = decode(&mut decoder, &[
+            abs(AbsoluteAxisType::ABS_X, 10),
+            abs(AbsoluteAxisType::ABS_Y, 20),
+            abs(AbsoluteAxisType::ABS_PRESSURE, 300),
+            abs(AbsoluteAxisType::ABS_TILT_X, -15),
+            syn(),
+            abs(AbsoluteAxisType::ABS_PRESSURE, 350),
+            syn(),
+        ]);
+        assert_eq!(out.len(), 2);
+        assert!(matches!(out[1], StylusEvent::Sample(s) if s.pos == [10, 20] && s.pressure == 350 && s.tilt == [-15, 0]));
+    }
+
+    #[test]
+    fn tool_change() {
+        let mut decoder = Decoder::new();
+        let out = decode(&mut decoder, &[
+            key(Key::BTN_TOOL_PEN, 1),
+            syn(),
+            key(Key::BTN_TOOL_PEN, 0),
+            syn(),
+            key(Key::BTN_TOOL_RUBBER, 1),
+            syn(),
+            // a stale release of the other end keeps the pen in range
+            key(Key::BTN_TOOL_PEN, 0),
+            key(Key::BTN_TOOL_PENCIL, 1),
+            syn(),
+        ]);
+        assert!(matches!(out[0], StylusEvent::Proximity(true)));
+        assert!(matches!(out[1], StylusEvent::Proximity(false)));
+        assert!(matches!(out[2], StylusEvent::Tool(Tool::Rubber)));
+        assert!(matches!(out[3], StylusEvent::Proximity(true)));
+        assert!(matches!(out[4], StylusEvent::Tool(Tool::Pen)));
+        assert!(matches!(out[5], StylusEvent::Proximity(true)));
+        assert_eq!(out.len(), 6);
+    }
+
+    #[test]
+    fn serial_is_reported_when_it_changes() {
+        let mut decoder = Decoder::new();
+        let serial = |value| InputEvent::new(EventType::MISC, MiscType::MSC_SERIAL.0, value);
+        let out = decode(&mut decoder, &[serial(0), serial(0x1234), syn(), serial(0x1234), syn(), serial(0x99), syn()]);
+        assert!(matches!(out[0], StylusEvent::Serial(0x1234)));
+        assert!(matches!(out[1], StylusEvent::Serial(0x99)));
+        assert_eq!(out.len(), 2);
+    }
+
+    #[test]
+    fn wheel_and_buttons() {
+        let mut decoder = Decoder::new();
+        let out = decode(&mut decoder, &[
+            abs(AbsoluteAxisType::ABS_WHEEL, 512),
+            key(Key::BTN_STYLUS, 1),
+            syn(),
+            key(Key::BTN_STYLUS, 0),
+            syn(),
+        ]);
+        assert!(matches!(out[0], StylusEvent::Wheel(512)));
+        assert!(matches!(out[1], StylusEvent::Key { key: Key::BTN_STYLUS, value: 1 }));
+        assert!(matches!(out[2], StylusEvent::Key { key: Key::BTN_STYLUS, value: 0 }));
+        assert_eq!(out.len(), 3);
+    }
+
+    #[test]
+    fn unknown_events_are_skipped_and_logged_once() {
+        let mut decoder = Decoder::new();
+        let unknown = [
+            abs(AbsoluteAxisType::ABS_MT_POSITION_X, 5),
+            InputEvent::new(EventType::ABSOLUTE, 0x3e, 1),
+            InputEvent::new(EventType::MISC, MiscType::MSC_SCAN.0, 0x90001),
+            InputEvent::new(EventType::RELATIVE, 0x08, -1),
+            InputEvent::new(EventType(0x1e), 0xffff, i32::MIN),
+        ];
+        for _ in 0..3 {
+            assert!(decode(&mut decoder, &unknown).is_empty());
+        }
+        assert_eq!(decoder.ignored.len(), unknown.len());
+
+        // decoding goes on normally afterwards
+        let out = decode(&mut decoder, &[abs(AbsoluteAxisType::ABS_X, 7), syn()]);
+        assert!(matches!(out[..], [StylusEvent::Sample(s)] if s.pos == [7, 0]));
+    }
+}
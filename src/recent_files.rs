@@ -0,0 +1,75 @@
+use macroquad::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::paths;
+use crate::utility::file_name;
+
+const MAX_RECENT: usize = 9;
+
+fn recent_path() -> PathBuf {
+    paths::state_dir().join("recent.json")
+}
+
+/// Recently opened or saved documents, newest first, persisted across sessions.
+pub struct RecentFiles {
+    paths: Vec<PathBuf>,
+}
+
+impl RecentFiles {
+    pub fn load() -> Self {
+        let paths = fs::read_to_string(recent_path())
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        Self { paths }
+    }
+
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    pub fn add(&mut self, path: &Path) {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.paths.retain(|p| *p != path);
+        self.paths.insert(0, path);
+        self.paths.truncate(MAX_RECENT);
+        self.save();
+    }
+
+    pub fn remove(&mut self, path: &Path) {
+        self.paths.retain(|p| p != path);
+        self.save();
+    }
+
+    fn save(&self) {
+        let result = fs::create_dir_all(paths::state_dir())
+            .and_then(|_| fs::write(recent_path(), serde_json::to_vec_pretty(&self.paths)?));
+        if let Err(e) = result {
+            eprintln!("Could not store recent files: {}", e);
+        }
+    }
+
+    /// Numbered list in the middle of the screen, entries are picked with the number keys.
+    pub fn draw_menu(&self) {
+        let font_size = 22.0;
+        let line_h = 30.0;
+        let w = 560.0f32.min(screen_width() - 40.0);
+        let h = line_h * (self.paths.len().max(1) + 2) as f32;
+        let x = (screen_width() - w) / 2.0;
+        let y = (screen_height() - h) / 2.0;
+
+        draw_rectangle(x, y, w, h, Color::new(0.97, 0.97, 0.97, 0.97));
+        draw_rectangle_lines(x, y, w, h, 2.0, DARKGRAY);
+        draw_text("Recent files  (1-9 to open, Esc to close)", x + 12.0, y + line_h * 0.8, font_size, DARKGRAY);
+
+        if self.paths.is_empty() {
+            draw_text("No recent files", x + 12.0, y + line_h * 1.8, font_size, GRAY);
+        }
+        for (i, path) in self.paths.iter().enumerate() {
+            let dir = path.parent().map(|p| p.display().to_string()).unwrap_or_default();
+            let line = format!("{}  {}   {}", i + 1, file_name(path), dir);
+            draw_text(&line, x + 12.0, y + line_h * (i as f32 + 1.8), font_size, BLACK);
+        }
+    }
+}
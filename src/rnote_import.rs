@@ -0,0 +1,326 @@
+use flate2::read::GzDecoder;
+use serde_json::Value;
+use std::io::Read;
+
+use crate::file_format::{CanvasData, DocumentMeta, LayerData, StrokeData, FORMAT_VERSION};
+use crate::layer::LayerProps;
+use crate::page::{Background, Orientation, Page, PageLayout, Pattern};
+use crate::persistence::{LoadReport, PersistenceError};
+use crate::stroke_style::StrokeStyle;
+use crate::ToolMode;
+
+// Rnote saves gzipped json: {"version": "0.x.y", "data": <engine snapshot>}.
+// The snapshot has a "document" (size, page format, background, layout) and
+// "stroke_components", a slot map serialized as a list of {"value": stroke}
+// entries. Only brush strokes are converted, every other kind of element is
+// listed in the report.
+//
+// Rnote measures in pixels at the format's dpi (96 by default), the canvas
+// uses points, so everything is scaled by 72 / dpi.
+
+const CURVE_SEGMENTS: usize = 6;
+
+pub fn is_rnote(path: &std::path::Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("rnote"))
+}
+
+fn invalid(msg: impl Into<String>) -> PersistenceError {
+    PersistenceError::Parse(msg.into())
+}
+
+fn f32_at(value: &Value, key: &str) -> Option<f32> {
+    value.get(key)?.as_f64().map(|v| v as f32)
+}
+
+fn vec2_of(value: &Value) -> Option<[f32; 2]> {
+    let v = value.as_array()?;
+    Some([v.first()?.as_f64()? as f32, v.get(1)?.as_f64()? as f32])
+}
+
+fn color_of(value: Option<&Value>) -> Option<[f32; 4]> {
+    let v = value?;
+    Some([f32_at(v, "r")?, f32_at(v, "g")?, f32_at(v, "b")?, f32_at(v, "a")?])
+}
+
+/// How much of the stroke width a given pressure gets, after rnote's pressure curves.
+fn pressure_factor(curve: &str, pressure: f32) -> f32 {
+    let p = pressure.clamp(0.0, 1.0);
+    match curve {
+        "const" => 1.0,
+        "sqrt" => p.sqrt(),
+        "cbrt" => p.cbrt(),
+        "quadr" => p * p,
+        "cubic" => p * p * p,
+        _ => p,
+    }
+}
+
+struct Element {
+    pos: [f32; 2],
+    pressure: f32,
+}
+
+fn element_of(value: &Value) -> Option<Element> {
+    Some(Element { pos: vec2_of(value.get("pos")?)?, pressure: f32_at(value, "pressure").unwrap_or(0.5) })
+}
+
+fn lerp(a: [f32; 2], b: [f32; 2], t: f32) -> [f32; 2] {
+    [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t]
+}
+
+/// Flattens a pen path into (position, pressure) samples.
+fn flatten_pen_path(path: &Value) -> Result<Vec<([f32; 2], f32)>, String> {
+    let start = path.get("start").and_then(element_of)
+        .ok_or("pen path in an old or unknown format")?;
+    let mut points = vec![(start.pos, start.pressure)];
+    let segments = path.get("segments").and_then(Value::as_array).ok_or("pen path without segments")?;
+
+    for segment in segments {
+        let (kind, body) = segment.as_object()
+            .and_then(|o| o.iter().next())
+            .ok_or("malformed path segment")?;
+        let end = body.get("end").and_then(element_of).ok_or("path segment without an end")?;
+        let (from, from_pressure) = *points.last().unwrap();
+        let controls: Vec<[f32; 2]> = match kind.as_str() {
+            "lineto" => Vec::new(),
+            "quadbezto" => vec![body.get("cp").and_then(vec2_of).ok_or("bezier without control point")?],
+            "cubbezto" => vec![
+                body.get("cp1").and_then(vec2_of).ok_or("bezier without control point")?,
+                body.get("cp2").and_then(vec2_of).ok_or("bezier without control point")?,
+            ],
+            other => return Err(format!("unknown path segment '{}'", other)),
+        };
+        if controls.is_empty() {
+            points.push((end.pos, end.pressure));
+            continue;
+        }
+        for j in 1..=CURVE_SEGMENTS {
+            let t = j as f32 / CURVE_SEGMENTS as f32;
+            // de casteljau over the control polygon
+            let mut polygon = vec![from];
+            polygon.extend(controls.iter().copied());
+            polygon.push(end.pos);
+            while polygon.len() > 1 {
+                polygon = polygon.windows(2).map(|w| lerp(w[0], w[1], t)).collect();
+            }
+            points.push((polygon[0], from_pressure + (end.pressure - from_pressure) * t));
+        }
+    }
+    Ok(points)
+}
+
+fn brush_stroke(stroke: &Value, scale: f32) -> Result<StrokeData, String> {
+    let (style_kind, options) = stroke.get("style")
+        .and_then(Value::as_object)
+        .and_then(|o| o.iter().next())
+        .ok_or("brush stroke without a style")?;
+    let width = f32_at(options, "stroke_width").ok_or("brush style without a width")?;
+    let color = color_of(options.get("stroke_color")).unwrap_or([0.0, 0.0, 0.0, 1.0]);
+    // rough and textured strokes have no pressure curve, they keep their width
+    let curve = match style_kind.as_str() {
+        "smooth" => options.get("pressure_curve").and_then(Value::as_str).unwrap_or("linear"),
+        _ => "const",
+    };
+
+    let samples = flatten_pen_path(stroke.get("path").ok_or("brush stroke without a path")?)?;
+    let points = samples.into_iter()
+        .map(|(pos, pressure)| {
+            // thickness is half the drawn width
+            let thickness = width * pressure_factor(curve, pressure) * 0.5 * scale;
+            ([pos[0] * scale, pos[1] * scale], thickness)
+        })
+        .collect();
+    Ok(StrokeData { points, style: StrokeStyle { color, ..StrokeStyle::default() }, samples: Vec::new(), created: None })
+}
+
+fn pattern_of(background: &Value, scale: f32) -> Pattern {
+    let size = background.get("pattern_size").and_then(vec2_of).unwrap_or([32.0, 32.0]);
+    let spacing = size[1].max(1.0) * scale;
+    match background.get("pattern").and_then(Value::as_str).unwrap_or("none") {
+        "lines" => Pattern::Ruled { spacing },
+        "grid" | "isometric_grid" => Pattern::Grid { spacing },
+        "dots" | "isometric_dots" => Pattern::Dots { spacing },
+        _ => Pattern::Blank,
+    }
+}
+
+fn page_layout(document: &Value, scale: f32) -> PageLayout {
+    // newer versions keep the settings in a "config" object
+    let config = document.get("config").unwrap_or(document);
+    let layout = config.get("layout").and_then(Value::as_str).unwrap_or("fixed_size");
+    if layout.contains("infinite") {
+        return PageLayout::Infinite;
+    }
+    let Some(format) = config.get("format") else { return PageLayout::Infinite };
+    let (Some(fw), Some(fh)) = (f32_at(format, "width"), f32_at(format, "height")) else {
+        return PageLayout::Infinite;
+    };
+    if fw <= 0.0 || fh <= 0.0 {
+        return PageLayout::Infinite;
+    }
+
+    let background = config.get("background").cloned().unwrap_or(Value::Null);
+    let background = Background {
+        color: color_of(background.get("color")).unwrap_or([1.0, 1.0, 1.0, 1.0]),
+        pattern: pattern_of(&background, scale),
+    };
+    let (size, orientation) = if fw > fh {
+        ([fh * scale, fw * scale], Orientation::Landscape)
+    } else {
+        ([fw * scale, fh * scale], Orientation::Portrait)
+    };
+
+    let x = f32_at(document, "x").unwrap_or(0.0);
+    let y = f32_at(document, "y").unwrap_or(0.0);
+    let cols = (f32_at(document, "width").unwrap_or(fw) / fw).ceil().max(1.0) as usize;
+    let rows = (f32_at(document, "height").unwrap_or(fh) / fh).ceil().max(1.0) as usize;
+    let mut pages = Vec::new();
+    for row in 0..rows {
+        for col in 0..cols {
+            pages.push(Page {
+                position: [(x + col as f32 * fw) * scale, (y + row as f32 * fh) * scale],
+                size,
+                orientation,
+                background,
+            });
+        }
+    }
+    PageLayout::Pages(pages)
+}
+
+pub fn canvas_from_rnote(bytes: &[u8]) -> Result<(CanvasData, LoadReport), PersistenceError> {
+    let mut text = Vec::new();
+    if bytes.starts_with(&[0x1f, 0x8b]) {
+        GzDecoder::new(bytes).read_to_end(&mut text)?;
+    } else {
+        text.extend_from_slice(bytes);
+    }
+    let root: Value = serde_json::from_slice(&text)?;
+
+    let data = root.get("data").unwrap_or(&root);
+    let snapshot = if data.get("document").is_some() { data } else { data.get("engine_snapshot").unwrap_or(data) };
+    let document = snapshot.get("document")
+        .ok_or_else(|| invalid("not an Rnote document, or written by an unsupported Rnote version"))?;
+    let config = document.get("config").unwrap_or(document);
+    let dpi = config.get("format").and_then(|f| f32_at(f, "dpi")).filter(|d| *d > 0.0).unwrap_or(96.0);
+    let scale = 72.0 / dpi;
+
+    let mut report = LoadReport::default();
+    let mut strokes = Vec::new();
+    let components = match snapshot.get("stroke_components") {
+        Some(Value::Array(items)) => items.iter().collect(),
+        Some(Value::Object(map)) => map.values().collect(),
+        _ => Vec::new(),
+    };
+    for (index, item) in components.into_iter().enumerate() {
+        // free slots of the slot map have no value
+        let stroke = match item.get("value") {
+            Some(Value::Null) => continue,
+            Some(value) => value,
+            None if item.get("version").is_some() => continue,
+            None => item,
+        };
+        let Some((kind, body)) = stroke.as_object().and_then(|o| o.iter().next()) else {
+            report.skip(index, "malformed element");
+            continue;
+        };
+        match kind.as_str() {
+            "brushstroke" => match brush_stroke(body, scale) {
+                Ok(sd) if sd.points.len() >= 2 => strokes.push(sd),
+                Ok(_) => report.skip(index, "brush stroke without length"),
+                Err(reason) => report.skip(index, reason),
+            },
+            "shapestroke" => report.unsupported("shape"),
+            "textstroke" => report.unsupported("text"),
+            "vectorimage" | "bitmapimage" => report.unsupported("image"),
+            other => report.unsupported(other),
+        }
+    }
+
+    let origin = [f32_at(document, "x").unwrap_or(0.0) * scale, f32_at(document, "y").unwrap_or(0.0) * scale];
+    let data = CanvasData {
+        format_version: FORMAT_VERSION,
+        meta: DocumentMeta::default(),
+        layers: vec![LayerData { id: 0, props: LayerProps::named("Layer 1"), strokes }],
+        active_layer: 0,
+        pages: page_layout(document, scale),
+        images: Vec::new(),
+        texts: Vec::new(),
+        pdf: None,
+        tool_mode: ToolMode::Pen,
+        offset: origin,
+        zoom: 1.0,
+    };
+    Ok((data, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const SAMPLE: &[u8] = include_bytes!("../tests/fixtures/sample.rnote");
+
+    fn assert_close(a: [f32; 2], b: [f32; 2]) {
+        assert!((a[0] - b[0]).abs() < 1e-4 && (a[1] - b[1]).abs() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn flattens_lines_and_curves() {
+        let path = json!({
+            "start": {"pos": [0.0, 0.0], "pressure": 0.2},
+            "segments": [
+                {"lineto": {"end": {"pos": [10.0, 0.0], "pressure": 0.4}}},
+                {"cubbezto": {"cp1": [10.0, 10.0], "cp2": [20.0, 10.0], "end": {"pos": [20.0, 0.0], "pressure": 1.0}}},
+            ],
+        });
+        let points = flatten_pen_path(&path).unwrap();
+        assert_eq!(points.len(), 2 + CURVE_SEGMENTS);
+        assert_eq!(points[1], ([10.0, 0.0], 0.4));
+        // halfway along the symmetric curve, pressure interpolated
+        let (mid, pressure) = points[1 + CURVE_SEGMENTS / 2];
+        assert_close(mid, [15.0, 7.5]);
+        assert!((pressure - 0.7).abs() < 1e-6);
+        assert_eq!(*points.last().unwrap(), ([20.0, 0.0], 1.0));
+    }
+
+    #[test]
+    fn rejects_unknown_segments() {
+        let path = json!({"start": {"pos": [0.0, 0.0]}, "segments": [{"arcto": {"end": {"pos": [1.0, 1.0]}}}]});
+        assert!(flatten_pen_path(&path).is_err());
+    }
+
+    #[test]
+    fn imports_sample() {
+        let (data, report) = canvas_from_rnote(SAMPLE).unwrap();
+
+        let strokes = &data.layers[0].strokes;
+        assert_eq!(strokes.len(), 2);
+        // rnote pixels at 96 dpi become points, widths follow the pressure
+        assert_eq!(strokes[0].points, vec![([72.0, 72.0], 0.75), ([144.0, 72.0], 1.5)]);
+        assert_eq!(strokes[0].style.color, [0.9, 0.1, 0.1, 1.0]);
+        // rough strokes keep their width, the curve is flattened
+        assert_eq!(strokes[1].points.len(), 1 + CURVE_SEGMENTS);
+        assert!(strokes[1].points.iter().all(|(_, width)| *width == 0.75));
+        assert_close(strokes[1].points[CURVE_SEGMENTS / 2].0, [54.0, 18.0]);
+        assert_close(strokes[1].points[CURVE_SEGMENTS].0, [72.0, 72.0]);
+
+        let PageLayout::Pages(pages) = &data.pages else { panic!("expected pages") };
+        assert_eq!(pages.len(), 2);
+        assert_close(pages[0].size, [595.275, 841.875]);
+        assert_close(pages[1].position, [0.0, 841.875]);
+        assert_eq!(pages[0].orientation, Orientation::Portrait);
+        assert_eq!(pages[0].background.color, [1.0, 1.0, 0.9, 1.0]);
+        assert_eq!(pages[0].background.pattern, Pattern::Ruled { spacing: 24.0 });
+
+        let unsupported: Vec<(&str, usize)> = report.unsupported.iter().map(|(kind, n)| (kind.as_str(), *n)).collect();
+        assert_eq!(unsupported, [("shape", 1), ("text", 1), ("image", 1)]);
+        let skipped: Vec<usize> = report.skipped.iter().map(|s| s.index).collect();
+        assert_eq!(skipped, [6, 7]);
+    }
+
+    #[test]
+    fn rejects_other_json() {
+        assert!(canvas_from_rnote(br#"{"version": "0.10.0", "data": {}}"#).is_err());
+    }
+}
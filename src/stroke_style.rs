@@ -0,0 +1,71 @@
+use macroquad::color::Color;
+use serde::{Serialize, Deserialize};
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum ToolKind {
+    Pen,
+    Marker,
+    Highlighter,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum CapStyle {
+    Round,
+    Butt,
+    Square,
+}
+
+/// How a stroke is rendered, stored per stroke in the document.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct StrokeStyle {
+    pub color: [f32;4], // rgba
+    pub width: f32,     // multiplier on the pressure dependent thickness
+    pub tool: ToolKind,
+    pub cap: CapStyle,
+}
+
+impl Default for StrokeStyle {
+    fn default() -> Self {
+        Self::for_tool(ToolKind::Pen)
+    }
+}
+
+impl StrokeStyle {
+    pub fn for_tool(tool: ToolKind) -> Self {
+        match tool {
+            ToolKind::Pen => Self { color: [0.0, 0.0, 0.0, 1.0], width: 1.0, tool, cap: CapStyle::Round },
+            ToolKind::Marker => Self { color: [0.0, 0.0, 0.0, 1.0], width: 2.5, tool, cap: CapStyle::Round },
+            ToolKind::Highlighter => Self { color: [1.0, 0.9, 0.0, 0.4], width: 6.0, tool, cap: CapStyle::Butt },
+        }
+    }
+
+    /// Switches to another tool, keeping the current color unless the tool
+    /// needs its own alpha (highlighters are always translucent).
+    pub fn with_tool(self, tool: ToolKind) -> Self {
+        let mut style = Self::for_tool(tool);
+        if tool != ToolKind::Highlighter && self.tool != ToolKind::Highlighter {
+            style.color = self.color;
+        }
+        style
+    }
+
+    pub fn with_color(mut self, color: Color) -> Self {
+        let alpha = self.color[3];
+        self.color = [color.r, color.g, color.b, alpha];
+        self
+    }
+
+    pub fn color(&self) -> Color {
+        Color::new(self.color[0], self.color[1], self.color[2], self.color[3])
+    }
+}
+
+/// Colors reachable with the number keys 4 to 9.
+pub const PALETTE: [Color; 6] = [
+    Color::new(0.0, 0.0, 0.0, 1.0),
+    Color::new(0.85, 0.1, 0.1, 1.0),
+    Color::new(0.1, 0.3, 0.85, 1.0),
+    Color::new(0.1, 0.6, 0.2, 1.0),
+    Color::new(1.0, 0.55, 0.0, 1.0),
+    Color::new(0.55, 0.2, 0.75, 1.0),
+];
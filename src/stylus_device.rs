@@ -0,0 +1,144 @@
+use evdev::{AbsoluteAxisType, Device, Key};
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::paths;
+
+// Finding the tablet among the evdev devices. Event numbers under /dev/input
+// depend on the order devices showed up in, so a tablet picked from several
+// is remembered by its name and usb ids and looked up again on every start.
+
+fn remembered_path() -> PathBuf {
+    paths::state_dir().join("stylus.json")
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct StylusId {
+    pub name: String,
+    pub vendor: u16,
+    pub product: u16,
+}
+
+impl StylusId {
+    pub fn of(device: &Device) -> Self {
+        let id = device.input_id();
+        Self { name: device.name().unwrap_or("unnamed device").to_string(), vendor: id.vendor(), product: id.product() }
+    }
+
+    pub fn load_remembered() -> Option<Self> {
+        let json = fs::read_to_string(remembered_path()).ok()?;
+        serde_json::from_str(&json).ok()
+    }
+
+    pub fn remember(&self) {
+        let result = fs::create_dir_all(paths::state_dir())
+            .and_then(|_| fs::write(remembered_path(), serde_json::to_vec_pretty(self)?));
+        if let Err(e) = result {
+            eprintln!("Could not store the chosen stylus: {}", e);
+        }
+    }
+}
+
+impl fmt::Display for StylusId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({:04x}:{:04x})", self.name, self.vendor, self.product)
+    }
+}
+
+/// An opened device that reports pen input.
+pub struct Candidate {
+    pub path: PathBuf,
+    pub id: StylusId,
+    pub device: Device,
+}
+
+/// Tablets report pressure and tell when the pen comes into range, mice and
+/// touchpads at most one of the two.
+pub fn is_stylus(device: &Device) -> bool {
+    device.supported_absolute_axes().is_some_and(|axes| axes.contains(AbsoluteAxisType::ABS_PRESSURE))
+        && device.supported_keys().is_some_and(|keys| keys.contains(Key::BTN_TOOL_PEN))
+}
+
+// event2 before event10
+fn event_number(path: &Path) -> u32 {
+    path.file_name()
+        .and_then(|name| name.to_str()?.strip_prefix("event")?.parse().ok())
+        .unwrap_or(u32::MAX)
+}
+
+/// Every device under /dev/input that looks like a pen tablet and can be
+/// opened, in event number order.
+pub fn find_styluses() -> Vec<Candidate> {
+    let mut found: Vec<Candidate> = evdev::enumerate()
+        .filter(|(_, device)| is_stylus(device))
+        .map(|(path, device)| Candidate { path, id: StylusId::of(&device), device })
+        .collect();
+    found.sort_by_key(|c| event_number(&c.path));
+    found
+}
+
+pub enum Discovery {
+    Found(Box<Candidate>),
+    /// Several tablets and none of them was chosen before.
+    Choose(Vec<Candidate>),
+    NotFound,
+}
+
+/// Picks the remembered tablet if it is connected, or the only one there is.
+pub fn discover(remembered: Option<&StylusId>) -> Discovery {
+    let mut found = find_styluses();
+    if let Some(i) = found.iter().position(|c| Some(&c.id) == remembered) {
+        return Discovery::Found(Box::new(found.swap_remove(i)));
+    }
+    match found.len() {
+        0 => Discovery::NotFound,
+        1 => Discovery::Found(Box::new(found.remove(0))),
+        _ => Discovery::Choose(found),
+    }
+}
+
+/// Numbered list in the middle of the screen like the recent files menu,
+/// entries are picked with the number keys.
+pub fn draw_picker(candidates: &[Candidate]) {
+    let font_size = 22.0;
+    let line_h = 30.0;
+    let w = 640.0f32.min(screen_width() - 40.0);
+    let h = line_h * (candidates.len() + 2) as f32;
+    let x = (screen_width() - w) / 2.0;
+    let y = (screen_height() - h) / 2.0;
+
+    draw_rectangle(x, y, w, h, Color::new(0.97, 0.97, 0.97, 0.97));
+    draw_rectangle_lines(x, y, w, h, 2.0, DARKGRAY);
+    draw_text("Which stylus?  (1-9 to choose, Esc for the mouse)", x + 12.0, y + line_h * 0.8, font_size, DARKGRAY);
+    for (i, candidate) in candidates.iter().enumerate() {
+        let line = format!("{}  {}   {}", i + 1, candidate.id, candidate.path.display());
+        draw_text(&line, x + 12.0, y + line_h * (i as f32 + 1.8), font_size, BLACK);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn devices_sort_by_event_number() {
+        let mut paths: Vec<PathBuf> = ["/dev/input/event10", "/dev/input/mouse0", "/dev/input/event2", "/dev/input/event0"]
+            .into_iter()
+            .map(PathBuf::from)
+            .collect();
+        paths.sort_by_key(|p| event_number(p));
+        let names: Vec<&str> = paths.iter().map(|p| p.to_str().unwrap()).collect();
+        assert_eq!(names, ["/dev/input/event0", "/dev/input/event2", "/dev/input/event10", "/dev/input/mouse0"]);
+    }
+
+    #[test]
+    fn remembered_id_round_trip() {
+        let id = StylusId { name: "UGEE S640 Pen".to_string(), vendor: 0x28bd, product: 0x0094 };
+        assert_eq!(id.to_string(), "UGEE S640 Pen (28bd:0094)");
+        let json = serde_json::to_string(&id).unwrap();
+        assert_eq!(serde_json::from_str::<StylusId>(&json).unwrap(), id);
+    }
+}
@@ -0,0 +1,506 @@
+use macroquad::prelude::*;
+use std::fmt::Write as _;
+
+use crate::canvas_image;
+use crate::file_format::{CanvasData, StrokeData};
+use crate::outline::stroke_outline;
+use crate::page::PageLayout;
+use crate::persistence::{LoadReport, PersistenceError};
+use crate::stroke_style::StrokeStyle;
+use crate::text_box;
+use crate::utility::stroke_bounding_box;
+use crate::export;
+
+// Every stroke is exported as its filled outline, so the file looks right
+// anywhere. The center line with its per point thickness and the style are
+// kept in data attributes next to it, which makes our own files import
+// losslessly. Paths from other programs are imported along their center
+// line, using the stroke width they were drawn with.
+
+const POINTS_ATTR: &str = "data-lignum-points";
+const STYLE_ATTR: &str = "data-lignum-style";
+const MARGIN: f32 = 20.0;
+// segments used to flatten bezier curves of imported paths
+const CURVE_SEGMENTS: usize = 8;
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn hex_color(color: [f32; 4]) -> String {
+    let c = |v: f32| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+    format!("#{:02x}{:02x}{:02x}", c(color[0]), c(color[1]), c(color[2]))
+}
+
+pub fn canvas_to_svg(data: &CanvasData) -> String {
+    let strokes = export::visible_strokes(data);
+
+    // view box around all ink and pages
+    let mut bounds: Option<Rect> = None;
+    let mut include = |r: Rect| bounds = Some(bounds.map_or(r, |b| b.combine_with(r)));
+    for stroke in &strokes {
+        let (min_x, max_x, min_y, max_y) = stroke_bounding_box(&stroke.points);
+        include(Rect::new(min_x, min_y, max_x - min_x, max_y - min_y));
+    }
+    let pages = match &data.pages {
+        PageLayout::Pages(pages) => pages.as_slice(),
+        PageLayout::Infinite => &[],
+    };
+    for page in pages {
+        include(page.rect());
+    }
+    for image in &data.images {
+        include(image.rect());
+    }
+    for text in &data.texts {
+        include(text.rect());
+    }
+    let b = bounds.unwrap_or(Rect::new(0.0, 0.0, 0.0, 0.0));
+    let (x, y, w, h) = (b.x - MARGIN, b.y - MARGIN, b.w + 2.0 * MARGIN, b.h + 2.0 * MARGIN);
+
+    let mut svg = String::new();
+    let _ = writeln!(svg, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w:.2}" height="{h:.2}" viewBox="{x:.2} {y:.2} {w:.2} {h:.2}">"#
+    );
+    if !data.meta.title.is_empty() {
+        let _ = writeln!(svg, "  <title>{}</title>", escape(&data.meta.title));
+    }
+    for page in pages {
+        let r = page.rect();
+        let _ = writeln!(
+            svg,
+            r##"  <rect x="{:.2}" y="{:.2}" width="{:.2}" height="{:.2}" fill="{}" stroke="#808080" stroke-width="0.5"/>"##,
+            r.x, r.y, r.w, r.h, hex_color(page.background.color)
+        );
+    }
+    for image in &data.images {
+        let r = image.rect();
+        let _ = writeln!(
+            svg,
+            r#"  <image x="{:.2}" y="{:.2}" width="{:.2}" height="{:.2}" preserveAspectRatio="none" href="data:image/png;base64,{}"/>"#,
+            r.x, r.y, r.w, r.h, canvas_image::base64_encode(&image.png)
+        );
+    }
+    for stroke in &strokes {
+        let outline = stroke_outline(&stroke.points, &stroke.style);
+        let Some(first) = outline.first() else { continue };
+        let mut d = format!("M{:.2} {:.2}", first.x, first.y);
+        for p in &outline[1..] {
+            let _ = write!(d, "L{:.2} {:.2}", p.x, p.y);
+        }
+        d.push('Z');
+
+        let points: Vec<String> = stroke.points.iter().map(|(p, th)| format!("{},{},{}", p.x, p.y, th)).collect();
+        let style = serde_json::to_string(&stroke.style).unwrap_or_default();
+        let _ = writeln!(
+            svg,
+            r#"  <path d="{}" fill="{}" fill-opacity="{}" {}="{}" {}="{}"/>"#,
+            d,
+            hex_color(stroke.style.color),
+            stroke.style.color[3],
+            POINTS_ATTR,
+            points.join(" "),
+            STYLE_ATTR,
+            escape(&style),
+        );
+    }
+    for text in &data.texts {
+        let _ = writeln!(
+            svg,
+            r#"  <text font-family="{}, monospace" font-size="{:.2}" fill="{}" fill-opacity="{}" xml:space="preserve">"#,
+            text_box::FONT_NAME, text.font_size, hex_color(text.color), text.color[3]
+        );
+        for line in text.layout() {
+            let _ = writeln!(svg, r#"    <tspan x="{:.2}" y="{:.2}">{}</tspan>"#, line.baseline.x, line.baseline.y, escape(&line.text));
+        }
+        svg.push_str("  </text>\n");
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Affine transform as in svg's `matrix(a b c d e f)`.
+#[derive(Clone, Copy)]
+struct Transform([f32; 6]);
+
+impl Transform {
+    const IDENTITY: Transform = Transform([1.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+
+    fn then(self, inner: Transform) -> Transform {
+        let [a, b, c, d, e, f] = self.0;
+        let [a2, b2, c2, d2, e2, f2] = inner.0;
+        Transform([
+            a * a2 + c * b2,
+            b * a2 + d * b2,
+            a * c2 + c * d2,
+            b * c2 + d * d2,
+            a * e2 + c * f2 + e,
+            b * e2 + d * f2 + f,
+        ])
+    }
+
+    fn apply(&self, p: Vec2) -> Vec2 {
+        let [a, b, c, d, e, f] = self.0;
+        vec2(a * p.x + c * p.y + e, b * p.x + d * p.y + f)
+    }
+
+    // average scale, used for stroke widths
+    fn scale(&self) -> f32 {
+        let [a, b, c, d, _, _] = self.0;
+        ((a * d - b * c).abs()).sqrt()
+    }
+}
+
+fn numbers(text: &str) -> Vec<f32> {
+    let mut out = Vec::new();
+    // scanned by char, so anything non-ascii is skipped instead of split
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let at = |k: usize| chars.get(k).map(|&(_, c)| c);
+    let byte = |k: usize| chars.get(k).map_or(text.len(), |&(i, _)| i);
+    let mut k = 0;
+    while k < chars.len() {
+        let start = k;
+        if matches!(at(k), Some('-' | '+')) {
+            k += 1;
+        }
+        let mut seen_dot = false;
+        while let Some(c) = at(k).filter(|c| c.is_ascii_digit() || (*c == '.' && !seen_dot)) {
+            seen_dot |= c == '.';
+            k += 1;
+        }
+        if matches!(at(k), Some('e' | 'E')) {
+            k += 1;
+            if matches!(at(k), Some('-' | '+')) {
+                k += 1;
+            }
+            while at(k).is_some_and(|c| c.is_ascii_digit()) {
+                k += 1;
+            }
+        }
+        match text[byte(start)..byte(k)].parse() {
+            Ok(v) => out.push(v),
+            Err(_) => k = start + 1,
+        }
+    }
+    out
+}
+
+fn parse_transform(text: &str) -> Transform {
+    let mut result = Transform::IDENTITY;
+    for part in text.split(')') {
+        let Some((name, args)) = part.split_once('(') else { continue };
+        let v = numbers(args);
+        let arg = |i: usize, default: f32| v.get(i).copied().unwrap_or(default);
+        let t = match name.trim().trim_start_matches(',').trim() {
+            "matrix" if v.len() == 6 => Transform([v[0], v[1], v[2], v[3], v[4], v[5]]),
+            "translate" => Transform([1.0, 0.0, 0.0, 1.0, arg(0, 0.0), arg(1, 0.0)]),
+            "scale" => Transform([arg(0, 1.0), 0.0, 0.0, arg(1, arg(0, 1.0)), 0.0, 0.0]),
+            "rotate" => {
+                let (sin, cos) = arg(0, 0.0).to_radians().sin_cos();
+                let (cx, cy) = (arg(1, 0.0), arg(2, 0.0));
+                Transform([1.0, 0.0, 0.0, 1.0, cx, cy])
+                    .then(Transform([cos, sin, -sin, cos, 0.0, 0.0]))
+                    .then(Transform([1.0, 0.0, 0.0, 1.0, -cx, -cy]))
+            }
+            _ => continue,
+        };
+        result = result.then(t);
+    }
+    result
+}
+
+/// Flattens path data into polylines, one per subpath. Elliptical arcs are
+/// replaced by a straight line to their end point.
+fn flatten_path(d: &str) -> Result<Vec<Vec<Vec2>>, String> {
+    let mut subpaths: Vec<Vec<Vec2>> = Vec::new();
+    let mut current: Vec<Vec2> = Vec::new();
+    let mut pos = Vec2::ZERO;
+    let mut start = Vec2::ZERO;
+    let mut last_control: Option<Vec2> = None;
+
+    // split into (command, arguments)
+    let mut commands = Vec::new();
+    let mut cmd_start = None;
+    for (i, ch) in d.char_indices() {
+        if ch.is_ascii_alphabetic() && ch != 'e' && ch != 'E' {
+            if let Some(s) = cmd_start {
+                commands.push((&d[s..s + 1], numbers(&d[s + 1..i])));
+            }
+            cmd_start = Some(i);
+        }
+    }
+    if let Some(s) = cmd_start {
+        commands.push((&d[s..s + 1], numbers(&d[s + 1..])));
+    }
+
+    let cubic = |points: &mut Vec<Vec2>, p0: Vec2, c1: Vec2, c2: Vec2, p3: Vec2| {
+        for j in 1..=CURVE_SEGMENTS {
+            let t = j as f32 / CURVE_SEGMENTS as f32;
+            let u = 1.0 - t;
+            points.push(p0 * u * u * u + c1 * 3.0 * u * u * t + c2 * 3.0 * u * t * t + p3 * t * t * t);
+        }
+    };
+
+    for (cmd, args) in commands {
+        let relative = cmd.chars().all(|c| c.is_ascii_lowercase());
+        let arity = match cmd.to_ascii_uppercase().as_str() {
+            "M" | "L" | "T" => 2,
+            "H" | "V" => 1,
+            "C" => 6,
+            "S" | "Q" => 4,
+            "A" => 7,
+            "Z" => 0,
+            other => return Err(format!("unknown path command '{}'", other)),
+        };
+        if arity == 0 {
+            if current.len() > 1 {
+                current.push(start);
+            }
+            subpaths.push(std::mem::take(&mut current));
+            pos = start;
+            continue;
+        }
+        if args.is_empty() || args.len() % arity != 0 {
+            return Err(format!("wrong number of arguments for '{}'", cmd));
+        }
+        for (k, a) in args.chunks(arity).enumerate() {
+            let base = if relative { pos } else { Vec2::ZERO };
+            let pt = |i: usize| base + vec2(a[i], a[i + 1]);
+            let mut control = None;
+            match cmd.to_ascii_uppercase().as_str() {
+                // further pairs after a moveto are implicit linetos
+                "M" if k == 0 => {
+                    if !current.is_empty() {
+                        subpaths.push(std::mem::take(&mut current));
+                    }
+                    pos = pt(0);
+                    start = pos;
+                    current.push(pos);
+                }
+                "M" | "L" => {
+                    pos = pt(0);
+                    current.push(pos);
+                }
+                "H" => {
+                    pos.x = if relative { pos.x + a[0] } else { a[0] };
+                    current.push(pos);
+                }
+                "V" => {
+                    pos.y = if relative { pos.y + a[0] } else { a[0] };
+                    current.push(pos);
+                }
+                "C" => {
+                    let (c1, c2, end) = (pt(0), pt(2), pt(4));
+                    cubic(&mut current, pos, c1, c2, end);
+                    control = Some(c2);
+                    pos = end;
+                }
+                "S" => {
+                    let c1 = last_control.map_or(pos, |c| pos * 2.0 - c);
+                    let (c2, end) = (pt(0), pt(2));
+                    cubic(&mut current, pos, c1, c2, end);
+                    control = Some(c2);
+                    pos = end;
+                }
+                "Q" | "T" => {
+                    let (q, end) = if arity == 4 {
+                        (pt(0), pt(2))
+                    } else {
+                        (last_control.map_or(pos, |c| pos * 2.0 - c), pt(0))
+                    };
+                    // a quadratic is a cubic with both controls 2/3 of the way to q
+                    let c1 = pos + (q - pos) * (2.0 / 3.0);
+                    let c2 = end + (q - end) * (2.0 / 3.0);
+                    cubic(&mut current, pos, c1, c2, end);
+                    control = Some(q);
+                    pos = end;
+                }
+                "A" => {
+                    pos = base + vec2(a[5], a[6]);
+                    current.push(pos);
+                }
+                _ => unreachable!(),
+            }
+            last_control = control;
+        }
+    }
+    if !current.is_empty() {
+        subpaths.push(current);
+    }
+    Ok(subpaths)
+}
+
+// style="a: b; c: d" wins over presentation attributes
+fn property<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> Option<&'a str> {
+    let from_style = node.attribute("style").and_then(|style| {
+        style.split(';').find_map(|decl| {
+            let (key, value) = decl.split_once(':')?;
+            (key.trim() == name).then(|| value.trim())
+        })
+    });
+    from_style.or_else(|| node.attribute(name))
+}
+
+fn inherited<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.ancestors().filter(|n| n.is_element()).find_map(|n| property(n, name))
+}
+
+/// Css color: hex, rgb() or one of the common names.
+pub fn parse_color(text: &str) -> Option<[f32; 3]> {
+    let text = text.trim();
+    if let Some(hex) = text.strip_prefix('#') {
+        let v = |s: &str| u8::from_str_radix(s, 16).ok().map(|v| v as f32 / 255.0);
+        let short = |i: usize| v(&hex.get(i..i + 1)?.repeat(2));
+        let long = |i: usize| v(hex.get(i..i + 2)?);
+        return match hex.len() {
+            3 => Some([short(0)?, short(1)?, short(2)?]),
+            6 => Some([long(0)?, long(2)?, long(4)?]),
+            _ => None,
+        };
+    }
+    if let Some(args) = text.strip_prefix("rgb(") {
+        let v = numbers(args);
+        return (v.len() == 3).then(|| [v[0] / 255.0, v[1] / 255.0, v[2] / 255.0]);
+    }
+    match text {
+        "black" => Some([0.0, 0.0, 0.0]),
+        "white" => Some([1.0, 1.0, 1.0]),
+        "red" => Some([1.0, 0.0, 0.0]),
+        "green" => Some([0.0, 0.5, 0.0]),
+        "blue" => Some([0.0, 0.0, 1.0]),
+        "yellow" => Some([1.0, 1.0, 0.0]),
+        "orange" => Some([1.0, 0.65, 0.0]),
+        "purple" => Some([0.5, 0.0, 0.5]),
+        "gray" | "grey" => Some([0.5, 0.5, 0.5]),
+        _ => None,
+    }
+}
+
+fn own_stroke(node: roxmltree::Node, transform: Transform) -> Option<Result<StrokeData, String>> {
+    let points = node.attribute(POINTS_ATTR)?;
+    let parsed = (|| {
+        let style: StrokeStyle = serde_json::from_str(node.attribute(STYLE_ATTR).unwrap_or(""))
+            .map_err(|e| format!("bad {}: {}", STYLE_ATTR, e))?;
+        let mut out = Vec::new();
+        for point in points.split_whitespace() {
+            let v: Vec<f32> = point.split(',').filter_map(|v| v.parse().ok()).collect();
+            if v.len() != 3 {
+                return Err(format!("bad point '{}' in {}", point, POINTS_ATTR));
+            }
+            let p = transform.apply(vec2(v[0], v[1]));
+            out.push(([p.x, p.y], v[2] * transform.scale()));
+        }
+        Ok(StrokeData { points: out, style, samples: Vec::new(), created: None })
+    })();
+    Some(parsed)
+}
+
+fn foreign_strokes(node: roxmltree::Node, transform: Transform) -> Result<Vec<StrokeData>, String> {
+    let d = node.attribute("d").ok_or("path without 'd'")?;
+    let stroke_color = inherited(node, "stroke").filter(|s| *s != "none");
+    let color = stroke_color
+        .or_else(|| inherited(node, "fill").filter(|s| *s != "none"))
+        .map_or(Some([0.0, 0.0, 0.0]), parse_color)
+        .unwrap_or([0.0, 0.0, 0.0]);
+    let opacity = ["opacity", if stroke_color.is_some() { "stroke-opacity" } else { "fill-opacity" }]
+        .iter()
+        .filter_map(|name| inherited(node, name).and_then(|v| v.parse::<f32>().ok()))
+        .product::<f32>();
+    let width = if stroke_color.is_some() {
+        inherited(node, "stroke-width").and_then(|w| numbers(w).first().copied()).unwrap_or(1.0)
+    } else {
+        1.0
+    };
+
+    let style = StrokeStyle {
+        color: [color[0], color[1], color[2], opacity.clamp(0.0, 1.0)],
+        ..StrokeStyle::default()
+    };
+    let thickness = width * 0.5 * transform.scale();
+
+    Ok(flatten_path(d)?
+        .into_iter()
+        .filter(|subpath| subpath.len() >= 2)
+        .map(|subpath| StrokeData {
+            points: subpath.into_iter().map(|p| {
+                let p = transform.apply(p);
+                ([p.x, p.y], thickness)
+            }).collect(),
+            style,
+            samples: Vec::new(),
+            created: None,
+        })
+        .collect())
+}
+
+fn node_transform(node: roxmltree::Node) -> Transform {
+    let mut chain: Vec<Transform> = node.ancestors()
+        .filter_map(|n| n.attribute("transform"))
+        .map(parse_transform)
+        .collect();
+    chain.reverse();
+    chain.into_iter().fold(Transform::IDENTITY, Transform::then)
+}
+
+/// Turns every `<path>` of an svg file into strokes. Paths that can't be
+/// read are skipped and listed in the report.
+pub fn strokes_from_svg(text: &str) -> Result<(Vec<StrokeData>, LoadReport), PersistenceError> {
+    let doc = roxmltree::Document::parse(text).map_err(|e| PersistenceError::Parse(e.to_string()))?;
+    let mut report = LoadReport::default();
+    let mut strokes = Vec::new();
+
+    let paths = doc.descendants().filter(|n| n.has_tag_name("path"));
+    for (index, node) in paths.enumerate() {
+        let transform = node_transform(node);
+        let parsed = match own_stroke(node, transform) {
+            Some(stroke) => stroke.map(|s| vec![s]),
+            None => foreign_strokes(node, transform),
+        };
+        match parsed {
+            Ok(parsed) if parsed.is_empty() => report.skip(index, "path has no length"),
+            Ok(parsed) => strokes.extend(parsed),
+            Err(reason) => report.skip(index, reason),
+        }
+    }
+    Ok((strokes, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_skip_non_ascii() {
+        assert_eq!(numbers("1,2.5 -3e1"), vec![1.0, 2.5, -30.0]);
+        assert_eq!(numbers("1é2"), vec![1.0, 2.0]);
+        assert_eq!(numbers("ü-ß.€"), Vec::<f32>::new());
+        assert_eq!(numbers("5€6"), vec![5.0, 6.0]);
+    }
+
+    #[test]
+    fn parse_color_rejects_non_ascii() {
+        assert_eq!(parse_color("#f80"), Some([1.0, 0.53333336, 0.0]));
+        assert_eq!(parse_color("#aé"), None);
+        assert_eq!(parse_color("#ffé0"), None);
+        assert_eq!(parse_color("#€€"), None);
+        assert_eq!(parse_color("rgb(255, ä0, 0)"), Some([1.0, 0.0, 0.0]));
+        assert_eq!(parse_color("rgb(ö)"), None);
+    }
+
+    #[test]
+    fn import_with_non_ascii_data() {
+        let svg = r##"<svg xmlns="http://www.w3.org/2000/svg">
+            <path d="M0 0 L10 ä10" stroke="#aé" transform="translate(5, ü5)"/>
+            <path d="Mö0 0 L10 10" stroke="rgb(0, 0, 255)"/>
+            <path d="M0 0 ß"/>
+        </svg>"##;
+        let (strokes, report) = strokes_from_svg(svg).unwrap();
+        assert_eq!(strokes.len(), 2);
+        assert_eq!(strokes[0].points[1].0, [15.0, 15.0]);
+        assert_eq!(strokes[0].style.color[..3], [0.0, 0.0, 0.0]);
+        assert_eq!(strokes[1].style.color[..3], [0.0, 0.0, 1.0]);
+        assert_eq!(report.skipped.len(), 1);
+    }
+}
@@ -0,0 +1,139 @@
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+
+// Maps the tablet's absolute coordinates onto the window directly, without
+// going through the compositor's pointer. The used part of the tablet's
+// active area is stretched over a target rectangle, optionally cropped to
+// the target's proportions so circles stay round.
+
+/// Where the tablet area ends up on screen.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MapTarget {
+    /// The whole window.
+    Window,
+    /// `[x, y, width, height]` in window pixels.
+    Region { rect: [f32; 4] },
+    /// `[x, y, width, height]` of a monitor on the desktop, in pixels.
+    /// Positions are made relative to the window, which needs a window
+    /// system that reports the window position (X11).
+    Monitor { rect: [f32; 4] },
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TabletMapping {
+    /// Used part of the active area, `[left, top, right, bottom]` as
+    /// fractions of its width and height.
+    pub area: [f32; 4],
+    pub target: MapTarget,
+    /// Crops the used area, around its center, to the target's aspect ratio.
+    pub keep_aspect: bool,
+}
+
+impl Default for TabletMapping {
+    fn default() -> Self {
+        Self { area: [0.0, 0.0, 1.0, 1.0], target: MapTarget::Window, keep_aspect: true }
+    }
+}
+
+impl TabletMapping {
+    /// Target rectangle in window pixels.
+    pub fn target_rect(&self, window_size: Vec2, window_pos: Vec2) -> Rect {
+        match self.target {
+            MapTarget::Window => Rect::new(0.0, 0.0, window_size.x, window_size.y),
+            MapTarget::Region { rect: [x, y, w, h] } => Rect::new(x, y, w, h),
+            MapTarget::Monitor { rect: [x, y, w, h] } => Rect::new(x - window_pos.x, y - window_pos.y, w, h),
+        }
+    }
+
+    /// Used part of the tablet as fractions of its active area, whose
+    /// physical size is `tablet_size`.
+    pub fn used_area(&self, tablet_size: Vec2, target: Rect) -> Rect {
+        let [left, top, right, bottom] = self.area;
+        let mut area = Rect::new(left, top, right - left, bottom - top);
+        if self.keep_aspect && target.w > 0.0 && target.h > 0.0 && area.w > 0.0 && area.h > 0.0 {
+            let physical = vec2(area.w * tablet_size.x, area.h * tablet_size.y);
+            let target_aspect = target.w / target.h;
+            let center = area.center();
+            if physical.x / physical.y > target_aspect {
+                area.w *= target_aspect * physical.y / physical.x;
+            } else {
+                area.h *= physical.x / (target_aspect * physical.y);
+            }
+            area.x = center.x - area.w * 0.5;
+            area.y = center.y - area.h * 0.5;
+        }
+        area
+    }
+
+    /// Window position of a tablet report, `normalized` is the position on
+    /// the active area from 0 to 1 on both axes.
+    pub fn to_window(&self, normalized: Vec2, tablet_size: Vec2, window_size: Vec2, window_pos: Vec2) -> Vec2 {
+        let target = self.target_rect(window_size, window_pos);
+        let area = self.used_area(tablet_size, target);
+        let fraction = (normalized - area.point()) / area.size().max(Vec2::splat(f32::EPSILON));
+        target.point() + fraction * target.size()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: Vec2, b: Vec2) {
+        assert!(a.distance(b) < 1e-3, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn keep_aspect_crops_around_the_center() {
+        let mapping = TabletMapping::default();
+        let square = Rect::new(0.0, 0.0, 800.0, 800.0);
+        // a wide tablet on a square target loses its sides
+        let area = mapping.used_area(vec2(200.0, 100.0), square);
+        assert_near(area.point(), vec2(0.25, 0.0));
+        assert_near(area.size(), vec2(0.5, 1.0));
+        // a square tablet on a wide target loses top and bottom
+        let area = mapping.used_area(vec2(100.0, 100.0), Rect::new(0.0, 0.0, 400.0, 200.0));
+        assert_near(area.point(), vec2(0.0, 0.25));
+        assert_near(area.size(), vec2(1.0, 0.5));
+        // part of a tablet keeps its own center
+        let part = TabletMapping { area: [0.5, 0.0, 1.0, 1.0], ..TabletMapping::default() };
+        let area = part.used_area(vec2(100.0, 100.0), square);
+        assert_near(area.point(), vec2(0.5, 0.25));
+        assert_near(area.size(), vec2(0.5, 0.5));
+
+        let stretched = TabletMapping { keep_aspect: false, ..part };
+        assert_eq!(stretched.used_area(vec2(200.0, 100.0), square), Rect::new(0.5, 0.0, 0.5, 1.0));
+    }
+
+    #[test]
+    fn area_corners_land_on_target_corners() {
+        let tablet = vec2(200.0, 100.0);
+        let window = vec2(800.0, 800.0);
+        let cases = [
+            (TabletMapping::default(), Vec2::ZERO),
+            (TabletMapping { keep_aspect: false, ..Default::default() }, Vec2::ZERO),
+            (TabletMapping { area: [0.1, 0.2, 0.6, 0.9], target: MapTarget::Region { rect: [50.0, 60.0, 300.0, 200.0] }, keep_aspect: true }, Vec2::ZERO),
+            (TabletMapping { target: MapTarget::Monitor { rect: [1920.0, 0.0, 1280.0, 1024.0] }, ..Default::default() }, vec2(2000.0, 100.0)),
+        ];
+        for (mapping, window_pos) in cases {
+            let target = mapping.target_rect(window, window_pos);
+            let area = mapping.used_area(tablet, target);
+            let corners = [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)];
+            for (fx, fy) in corners {
+                let on_tablet = vec2(area.x + fx * area.w, area.y + fy * area.h);
+                let expected = vec2(target.x + fx * target.w, target.y + fy * target.h);
+                assert_near(mapping.to_window(on_tablet, tablet, window, window_pos), expected);
+            }
+        }
+        // the cropped sides of a wide tablet fall outside the window
+        let mapping = TabletMapping::default();
+        assert_near(mapping.to_window(vec2(0.25, 0.0), tablet, window, Vec2::ZERO), vec2(0.0, 0.0));
+        assert_near(mapping.to_window(vec2(0.75, 1.0), tablet, window, Vec2::ZERO), vec2(800.0, 800.0));
+        assert_near(mapping.to_window(vec2(0.0, 0.5), tablet, window, Vec2::ZERO), vec2(-400.0, 400.0));
+        // monitor positions are relative to the window
+        let monitor = TabletMapping { target: MapTarget::Monitor { rect: [1920.0, 0.0, 1280.0, 1024.0] }, ..Default::default() };
+        assert_eq!(monitor.target_rect(window, vec2(2000.0, 100.0)), Rect::new(-80.0, -100.0, 1280.0, 1024.0));
+    }
+}
@@ -0,0 +1,201 @@
+use evdev::{AbsoluteAxisType, Device, Key};
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::pressure_curve::PressureCurve;
+use crate::read_stylus::AxisRange;
+use crate::stylus_device::StylusId;
+
+// How a tablet is read. Axis ranges come from the device itself, a profile
+// matched by name or usb ids fixes what the device gets wrong, sets the
+// buttons and can bring its own pressure curve. Profiles from the config file
+// are tried before the built in ones, devices nothing matches get
+// `TabletProfile::default`.
+
+/// Used when a device doesn't report a pressure range.
+const FALLBACK_PRESSURE: AxisRange = AxisRange { min: 0, max: 8191, resolution: 0 };
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ButtonAction {
+    /// Hold to pan, double click to toggle the eraser.
+    Pan,
+    ToggleEraser,
+    Nothing,
+}
+
+/// What the barrel buttons do.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ButtonMap {
+    /// `BTN_STYLUS`, usually the lower button
+    pub stylus: ButtonAction,
+    /// `BTN_STYLUS2`
+    pub stylus2: ButtonAction,
+}
+
+impl Default for ButtonMap {
+    fn default() -> Self {
+        Self { stylus: ButtonAction::Pan, stylus2: ButtonAction::ToggleEraser }
+    }
+}
+
+impl ButtonMap {
+    /// Action of a key, None for keys that aren't barrel buttons.
+    pub fn action(&self, key: Key) -> Option<ButtonAction> {
+        match key {
+            Key::BTN_STYLUS => Some(self.stylus),
+            Key::BTN_STYLUS2 => Some(self.stylus2),
+            _ => None,
+        }
+    }
+}
+
+/// Which devices a profile applies to, every given field has to match.
+#[derive(Clone, Default, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceMatch {
+    /// Part of the device name, ignoring case.
+    pub name: Option<String>,
+    pub vendor: Option<u16>,
+    pub product: Option<u16>,
+}
+
+impl DeviceMatch {
+    pub fn matches(&self, id: &StylusId) -> bool {
+        self.name.as_ref().is_none_or(|name| id.name.to_lowercase().contains(&name.to_lowercase()))
+            && self.vendor.is_none_or(|vendor| vendor == id.vendor)
+            && self.product.is_none_or(|product| product == id.product)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TabletProfile {
+    pub name: String,
+    #[serde(rename = "match")]
+    pub device: DeviceMatch,
+    /// Replaces the pressure range the device reports.
+    pub pressure_range: Option<AxisRange>,
+    pub buttons: ButtonMap,
+    /// Replaces the `pressure_curve` of the config for this tablet.
+    pub curve: Option<PressureCurve>,
+}
+
+impl Default for TabletProfile {
+    fn default() -> Self {
+        Self {
+            name: "Generic tablet".to_string(),
+            device: DeviceMatch::default(),
+            pressure_range: None,
+            buttons: ButtonMap::default(),
+            curve: None,
+        }
+    }
+}
+
+/// Profiles for tablets whose defaults need adjusting.
+pub fn builtin_profiles() -> Vec<TabletProfile> {
+    vec![
+        // the firm pressure curve was tuned on this one, with the pressure read against 60000
+        TabletProfile {
+            name: "UGEE S640".to_string(),
+            device: DeviceMatch { name: Some("S640".to_string()), ..Default::default() },
+            pressure_range: Some(AxisRange { min: 0, max: 60000, resolution: 0 }),
+            ..Default::default()
+        },
+    ]
+}
+
+/// First profile matching `id`, the configured ones before the built in ones.
+pub fn find_profile(id: &StylusId, configured: &[TabletProfile]) -> TabletProfile {
+    configured.iter().cloned()
+        .chain(builtin_profiles())
+        .find(|p| p.device.matches(id))
+        .unwrap_or_default()
+}
+
+/// A stylus being read: the ranges of its axes and the profile it matched.
+pub struct Tablet {
+    pub id: StylusId,
+    /// x and y, without them positions come from the mouse cursor
+    pub position: Option<[AxisRange; 2]>,
+    pub pressure: AxisRange,
+    pub tilt: [Option<AxisRange>; 2],
+    /// height of the hovering pen, few tablets report it
+    pub distance: Option<AxisRange>,
+    pub profile: TabletProfile,
+}
+
+impl Tablet {
+    pub fn new(device: &Device, profiles: &[TabletProfile]) -> Self {
+        let id = StylusId::of(device);
+        let profile = find_profile(&id, profiles);
+        let range = |axis| AxisRange::read(device, axis);
+        let position = range(AbsoluteAxisType::ABS_X).zip(range(AbsoluteAxisType::ABS_Y)).map(|(x, y)| [x, y]);
+        let pressure = profile.pressure_range.or_else(|| range(AbsoluteAxisType::ABS_PRESSURE)).unwrap_or(FALLBACK_PRESSURE);
+        let tilt = [range(AbsoluteAxisType::ABS_TILT_X), range(AbsoluteAxisType::ABS_TILT_Y)];
+        let distance = range(AbsoluteAxisType::ABS_DISTANCE);
+        Self { id, position, pressure, tilt, distance, profile }
+    }
+
+    /// Raw pressure as a fraction of the range, 0 to 1.
+    pub fn pressure(&self, raw: i32) -> f32 {
+        self.pressure.normalize(raw).clamp(0.0, 1.0)
+    }
+
+    /// Tilt in degrees. Tilt resolutions are in units per radian, devices
+    /// without one are assumed to report degrees.
+    pub fn tilt_degrees(&self, raw: [i32; 2]) -> Vec2 {
+        let degrees = |range: Option<AxisRange>, value: i32| match range {
+            Some(range) if range.resolution > 0 => (value as f32 / range.resolution as f32).to_degrees(),
+            _ => value as f32,
+        };
+        vec2(degrees(self.tilt[0], raw[0]), degrees(self.tilt[1], raw[1]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(name: &str, vendor: u16, product: u16) -> StylusId {
+        StylusId { name: name.to_string(), vendor, product }
+    }
+
+    #[test]
+    fn device_match() {
+        let s640 = id("UGEE S640 Pen", 0x28bd, 0x0094);
+        assert!(DeviceMatch::default().matches(&s640));
+        assert!(DeviceMatch { name: Some("s640".to_string()), ..Default::default() }.matches(&s640));
+        assert!(DeviceMatch { name: Some("ugee S640 PEN".to_string()), ..Default::default() }.matches(&s640));
+        assert!(!DeviceMatch { name: Some("Wacom".to_string()), ..Default::default() }.matches(&s640));
+        assert!(DeviceMatch { vendor: Some(0x28bd), product: Some(0x0094), ..Default::default() }.matches(&s640));
+        assert!(!DeviceMatch { vendor: Some(0x056a), ..Default::default() }.matches(&s640));
+        assert!(!DeviceMatch { vendor: Some(0x28bd), product: Some(0x0095), ..Default::default() }.matches(&s640));
+        // every given field has to match
+        assert!(!DeviceMatch { name: Some("S640".to_string()), vendor: Some(0x056a), product: None }.matches(&s640));
+    }
+
+    #[test]
+    fn configured_profiles_come_first() {
+        let s640 = id("UGEE S640 Pen", 0x28bd, 0x0094);
+        assert_eq!(find_profile(&s640, &[]).name, "UGEE S640");
+        assert_eq!(find_profile(&id("Wacom Intuos", 0x056a, 0x0374), &[]), TabletProfile::default());
+
+        let mine = TabletProfile {
+            name: "My S640".to_string(),
+            device: DeviceMatch { vendor: Some(0x28bd), ..Default::default() },
+            buttons: ButtonMap { stylus: ButtonAction::Nothing, stylus2: ButtonAction::Pan },
+            ..Default::default()
+        };
+        let other = TabletProfile {
+            name: "Wacom".to_string(),
+            device: DeviceMatch { vendor: Some(0x056a), ..Default::default() },
+            ..Default::default()
+        };
+        let configured = [other, mine.clone()];
+        assert_eq!(find_profile(&s640, &configured), mine);
+        assert_eq!(find_profile(&id("Wacom Intuos", 0x056a, 0x0374), &configured).name, "Wacom");
+    }
+}
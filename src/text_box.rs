@@ -0,0 +1,148 @@
+use fontdue::{Font, FontSettings};
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+// Typed text placed on the canvas. Boxes have a fixed wrapping width and
+// grow downwards with their text. Layout uses the same font macroquad draws
+// with by default, so line breaks are identical on screen and in exports,
+// which draw the glyphs themselves.
+
+pub type TextId = u32;
+
+pub const FONT_BYTES: &[u8] = include_bytes!("../assets/ProggyClean.ttf");
+pub const FONT_NAME: &str = "ProggyClean";
+/// Distance between baselines, relative to the font size.
+pub const LINE_HEIGHT: f32 = 1.2;
+pub const DEFAULT_FONT_SIZE: f32 = 16.0;
+pub const DEFAULT_WRAP_WIDTH: f32 = 240.0;
+pub const MIN_WRAP_WIDTH: f32 = 20.0;
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct TextBox {
+    pub id: TextId,
+    pub pos: [f32;2], // top left corner in world coords
+    pub text: String,
+    pub font_size: f32,
+    pub color: [f32;4],
+    pub wrap_width: f32,
+}
+
+/// One laid out line, `start` is the char index of its first character.
+pub struct Line {
+    pub start: usize,
+    pub text: String,
+    pub baseline: Vec2,
+}
+
+pub fn font() -> &'static Font {
+    static FONT: OnceLock<Font> = OnceLock::new();
+    FONT.get_or_init(|| Font::from_bytes(FONT_BYTES, FontSettings::default()).expect("bundled font is valid"))
+}
+
+/// Horizontal advance of `c` in world units.
+pub fn advance(c: char, size: f32) -> f32 {
+    font().metrics(c, size).advance_width
+}
+
+pub fn text_width(text: &str, size: f32) -> f32 {
+    text.chars().map(|c| advance(c, size)).sum()
+}
+
+/// Height of the font above the baseline.
+pub fn ascent(size: f32) -> f32 {
+    font().horizontal_line_metrics(size).map_or(size * 0.8, |m| m.ascent)
+}
+
+impl TextBox {
+    pub fn new(id: TextId, pos: Vec2, font_size: f32, color: [f32;4]) -> Self {
+        Self { id, pos: [pos.x, pos.y], text: String::new(), font_size, color, wrap_width: DEFAULT_WRAP_WIDTH }
+    }
+
+    pub fn line_height(&self) -> f32 {
+        self.font_size * LINE_HEIGHT
+    }
+
+    /// Splits the text into lines at newlines and, greedily, at spaces so no
+    /// line is wider than `wrap_width`. Words longer than a line are broken
+    /// between characters. Every character ends up in exactly one line, the
+    /// spaces a line was broken at stay at its end.
+    pub fn layout(&self) -> Vec<Line> {
+        let chars: Vec<char> = self.text.chars().collect();
+        let mut lines = Vec::new();
+        let mut start = 0;
+        let push = |lines: &mut Vec<Line>, from: usize, to: usize| {
+            let y = self.pos[1] + ascent(self.font_size) + lines.len() as f32 * self.line_height();
+            let text = chars[from..to].iter().filter(|c| **c != '\n').collect();
+            lines.push(Line { start: from, text, baseline: vec2(self.pos[0], y) });
+        };
+        while start < chars.len() {
+            let mut width = 0.0;
+            let mut end = start;
+            let mut last_space = None;
+            while end < chars.len() && chars[end] != '\n' {
+                let c = chars[end];
+                if c != ' ' && width + advance(c, self.font_size) > self.wrap_width && end > start {
+                    break;
+                }
+                width += advance(c, self.font_size);
+                if c == ' ' {
+                    last_space = Some(end);
+                }
+                end += 1;
+            }
+            if end < chars.len() && chars[end] == '\n' {
+                push(&mut lines, start, end + 1);
+                start = end + 1;
+                if start == chars.len() {
+                    // an empty last line after a trailing newline
+                    push(&mut lines, start, start);
+                }
+            } else if end < chars.len() {
+                let end = last_space.map_or(end, |s| s + 1);
+                push(&mut lines, start, end);
+                start = end;
+            } else {
+                push(&mut lines, start, end);
+                start = end;
+            }
+        }
+        if lines.is_empty() {
+            push(&mut lines, 0, 0);
+        }
+        lines
+    }
+
+    pub fn rect(&self) -> Rect {
+        let lines = self.layout().len() as f32;
+        Rect::new(self.pos[0], self.pos[1], self.wrap_width, lines * self.line_height())
+    }
+
+    /// World position of the caret in front of char `index`, on its baseline.
+    pub fn caret_position(&self, index: usize) -> Vec2 {
+        let lines = self.layout();
+        let line = lines.iter().rposition(|l| l.start <= index).unwrap_or(0);
+        let line = &lines[line];
+        let before: String = line.text.chars().take(index - line.start).collect();
+        line.baseline + vec2(text_width(&before, self.font_size), 0.0)
+    }
+}
+
+/// Topmost text box under a world position.
+pub fn text_at(texts: &[TextBox], pos: Vec2) -> Option<usize> {
+    texts.iter().rposition(|t| t.rect().contains(pos))
+}
+
+/// Inserts `c` at char index `caret`, returns the new caret.
+pub fn insert_char(text: &mut String, caret: usize, c: char) -> usize {
+    let byte = text.char_indices().nth(caret).map_or(text.len(), |(i, _)| i);
+    text.insert(byte, c);
+    caret + 1
+}
+
+/// Removes the char at index `caret`, if there is one.
+pub fn remove_char(text: &mut String, caret: usize) {
+    if let Some((byte, _)) = text.char_indices().nth(caret) {
+        text.remove(byte);
+    }
+}
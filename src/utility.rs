@@ -1,12 +1,13 @@
 use macroquad::{
-    color::{Color, BLACK},
-    math::{vec2, vec3, Vec2, Vec3, Vec3Swizzles},
+    color::Color,
+    math::Vec2,
     models::Mesh,
-    ui::Vertex,
 };
+use std::path::Path;
+
 use crate::Stroke;
 
-pub(crate) fn perpendicular_distance(p: Vec2, a: Vec2, b: Vec2) -> f32 {
+pub fn perpendicular_distance(p: Vec2, a: Vec2, b: Vec2) -> f32 {
     let ap = p - a;
     let ab = b - a;
     let ab_length = ab.length();
@@ -18,7 +19,7 @@ pub(crate) fn perpendicular_distance(p: Vec2, a: Vec2, b: Vec2) -> f32 {
     (p - closest).length()
 }
 
-pub(crate) fn ramer_douglas_peucker(points: &[(Vec2, f32)], epsilon: f32) -> Vec<(Vec2, f32)> {
+pub fn ramer_douglas_peucker(points: &[(Vec2, f32)], epsilon: f32) -> Vec<(Vec2, f32)> {
     if points.len() < 3 {
         return points.to_vec();
     }
@@ -48,13 +49,13 @@ pub(crate) fn ramer_douglas_peucker(points: &[(Vec2, f32)], epsilon: f32) -> Vec
     }
 }
 
-pub(crate) fn interpolate_pressure(r0: f32, r1: f32, r2: f32, r3: f32, t: f32) -> f32 {
+pub fn interpolate_pressure(r0: f32, r1: f32, r2: f32, r3: f32, t: f32) -> f32 {
     let t2 = t*t;
     let t3 = t2*t;
     0.5 * ((2.0*r1) + (-r0 + r2)*t + (2.0*r0 - 5.0*r1 +4.0*r2 - r3)*t2 + (-r0 +3.0*r1 -3.0*r2 + r3)*t3)
 }
 
-pub(crate) fn catmull_rom_spline(points: &[(Vec2, f32)], segments: usize) -> Vec<(Vec2, f32)> {
+pub fn catmull_rom_spline(points: &[(Vec2, f32)], segments: usize) -> Vec<(Vec2, f32)> {
     if points.len() < 4 {
         return points.to_vec();
     }
@@ -96,7 +97,7 @@ pub(crate) fn catmull_rom_spline(points: &[(Vec2, f32)], segments: usize) -> Vec
     result
 }
 
-pub(crate) fn draw_filled_trapezoid(start: Vec2, start_radius: f32, end: Vec2, end_radius: f32) {
+pub fn draw_filled_trapezoid(start: Vec2, start_radius: f32, end: Vec2, end_radius: f32, color: Color) {
     let direction = (end - start).normalize();
     let perpendicular = Vec2::new(-direction.y, direction.x);
 
@@ -105,8 +106,8 @@ pub(crate) fn draw_filled_trapezoid(start: Vec2, start_radius: f32, end: Vec2, e
     let end_left = end + perpendicular * end_radius;
     let end_right = end - perpendicular * end_radius;
 
-    draw_triangle(start_left, end_left, end_right, BLACK);
-    draw_triangle(start_left, end_right, start_right, BLACK);
+    draw_triangle(start_left, end_left, end_right, color);
+    draw_triangle(start_left, end_right, start_right, color);
 }
 
 fn draw_triangle(p1: Vec2, p2: Vec2, p3: Vec2, color: Color) {
@@ -114,14 +115,14 @@ fn draw_triangle(p1: Vec2, p2: Vec2, p3: Vec2, color: Color) {
 }
 
 
-pub(crate) fn color_u8(color:Color)->[u8;4] {
+pub fn color_u8(color:Color)->[u8;4] {
     [(color.r*255.0)as u8,
      (color.g*255.0)as u8,
      (color.b*255.0)as u8,
      (color.a*255.0)as u8]
 }
 
-pub(crate) fn stroke_intersect(stroke: &Stroke, pos: Vec2, radius: f32) -> bool {
+pub fn stroke_intersect(stroke: &Stroke, pos: Vec2, radius: f32) -> bool {
     for &(p,_) in &stroke.points {
         if p.distance(pos) <= radius {
             return true;
@@ -130,11 +131,11 @@ pub(crate) fn stroke_intersect(stroke: &Stroke, pos: Vec2, radius: f32) -> bool
     false
 }
 
-pub(crate) fn stroke_bounding_box(points: &[(Vec2, f32)]) -> (f32, f32, f32, f32) {
-    let mut min_x = std::f32::MAX;
-    let mut max_x = std::f32::MIN;
-    let mut min_y = std::f32::MAX;
-    let mut max_y = std::f32::MIN;
+pub fn stroke_bounding_box(points: &[(Vec2, f32)]) -> (f32, f32, f32, f32) {
+    let mut min_x = f32::MAX;
+    let mut max_x = f32::MIN;
+    let mut min_y = f32::MAX;
+    let mut max_y = f32::MIN;
     for (pos, _) in points {
         if pos.x < min_x { min_x = pos.x; }
         if pos.x > max_x { max_x = pos.x; }
@@ -145,7 +146,7 @@ pub(crate) fn stroke_bounding_box(points: &[(Vec2, f32)]) -> (f32, f32, f32, f32
 }
 
 
-pub(crate) fn is_stroke_visible(stroke: &Stroke, offset: Vec2, zoom: f32, screen_w: f32, screen_h: f32) -> bool {
+pub fn is_stroke_visible(stroke: &Stroke, offset: Vec2, zoom: f32, screen_w: f32, screen_h: f32) -> bool {
     let (min_x, max_x, min_y, max_y) = stroke_bounding_box(&stroke.points);
     let visible_left = offset.x;
     let visible_top = offset.y;
@@ -157,7 +158,7 @@ pub(crate) fn is_stroke_visible(stroke: &Stroke, offset: Vec2, zoom: f32, screen
 }
 
 
-pub(crate) fn transform_mesh_o(
+pub fn transform_mesh_o(
     original_mesh: &Mesh,
     transformable: &mut Mesh,
     offset_delta: Vec2,
@@ -224,7 +225,7 @@ pub fn transform_mesh_absolute(
 
 
 
-pub(crate) fn transform_mesh(
+pub fn transform_mesh(
     mesh: &mut Mesh,
     offset_delta: Vec2,
     zoom_delta: f32,
@@ -240,4 +241,8 @@ pub(crate) fn transform_mesh(
         vertex.position.x = zoom_center.x + (vertex.position.x - zoom_center.x) * zoom_delta;
         vertex.position.y = zoom_center.y + (vertex.position.y - zoom_center.y) * zoom_delta;
     }
-}
\ No newline at end of file
+}
+
+pub fn file_name(path: &Path) -> String {
+    path.file_name().map_or_else(|| path.display().to_string(), |n| n.to_string_lossy().into_owned())
+}
@@ -0,0 +1,438 @@
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use macroquad::prelude::*;
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::canvas_image::{self, CanvasImage};
+use crate::export;
+use crate::file_format::{CanvasData, DocumentMeta, LayerData, StrokeData, FORMAT_VERSION};
+use crate::layer::LayerProps;
+use crate::page::{self, Background, Orientation, Page, PageLayout, Pattern};
+use crate::persistence::{LoadReport, PersistenceError};
+use crate::stroke_style::{CapStyle, StrokeStyle, ToolKind};
+use crate::text_box::{self, TextBox};
+use crate::utility::stroke_bounding_box;
+use crate::{Stroke, ToolMode};
+
+// Xournal++ files are gzipped xml:
+//
+//   <xournal fileversion="4">
+//     <page width=".." height="..">
+//       <background type="solid" color="#rrggbbaa" style="lined"/>
+//       <layer name="..">
+//         <stroke tool="pen" color="#rrggbbaa" width="w p0 p1 ..">x0 y0 x1 y1 ..</stroke>
+//         <image left=".." top=".." right=".." bottom="..">base64 png</image>
+//         <text font=".." size=".." x=".." y=".." color="#rrggbbaa">lines</text>
+//
+// Coordinates are points relative to the page, like the canvas uses. The
+// first width is the nominal one, the following ones are the widths at each
+// point (all but the last) of pressure sensitive strokes. Xournal++ shows
+// pages below each other, so imported pages are stacked the same way.
+
+const RULED_SPACING: f32 = 24.0;
+const GRAPH_SPACING: f32 = 14.17;
+
+pub fn is_xopp(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("xopp") || ext.eq_ignore_ascii_case("xoj"))
+}
+
+fn parse_color(text: &str) -> Option<[f32; 4]> {
+    let named = match text {
+        "black" => Some([0.0, 0.0, 0.0]),
+        "blue" => Some([0.2, 0.2, 0.8]),
+        "red" => Some([1.0, 0.0, 0.0]),
+        "green" => Some([0.0, 0.5, 0.0]),
+        "gray" => Some([0.5, 0.5, 0.5]),
+        "lightblue" => Some([0.0, 0.75, 1.0]),
+        "lightgreen" => Some([0.0, 1.0, 0.0]),
+        "magenta" => Some([1.0, 0.0, 1.0]),
+        "orange" => Some([1.0, 0.5, 0.0]),
+        "yellow" => Some([1.0, 1.0, 0.0]),
+        "white" => Some([1.0, 1.0, 1.0]),
+        _ => None,
+    };
+    if let Some([r, g, b]) = named {
+        return Some([r, g, b, 1.0]);
+    }
+    let hex = text.strip_prefix('#')?;
+    let v = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok().map(|v| v as f32 / 255.0);
+    match hex.len() {
+        6 => Some([v(0)?, v(2)?, v(4)?, 1.0]),
+        8 => Some([v(0)?, v(2)?, v(4)?, v(6)?]),
+        _ => None,
+    }
+}
+
+fn hex_color(color: [f32; 4]) -> String {
+    let c = |v: f32| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+    format!("#{:02x}{:02x}{:02x}{:02x}", c(color[0]), c(color[1]), c(color[2]), c(color[3]))
+}
+
+fn numbers(text: &str) -> Result<Vec<f32>, String> {
+    text.split_whitespace()
+        .map(|v| v.parse::<f32>().ok().filter(|v| v.is_finite()).ok_or_else(|| format!("bad number '{}'", v)))
+        .collect()
+}
+
+fn background_of(node: Option<roxmltree::Node>, report: &mut LoadReport) -> Background {
+    let Some(node) = node else { return Background::default() };
+    match node.attribute("type") {
+        Some("solid") | None => {}
+        Some("pdf") => report.unsupported("pdf background"),
+        Some(_) => report.unsupported("image background"),
+    }
+    let pattern = match node.attribute("style").unwrap_or("plain") {
+        "lined" | "ruled" => Pattern::Ruled { spacing: RULED_SPACING },
+        "graph" | "isograph" => Pattern::Grid { spacing: GRAPH_SPACING },
+        "dotted" | "isodotted" => Pattern::Dots { spacing: GRAPH_SPACING },
+        _ => Pattern::Blank,
+    };
+    let color = node.attribute("color").and_then(parse_color).unwrap_or([1.0, 1.0, 1.0, 1.0]);
+    Background { color, pattern }
+}
+
+fn image_of(node: roxmltree::Node, origin: Vec2, id: u32) -> Result<CanvasImage, String> {
+    let coord = |name: &str| node.attribute(name).and_then(|v| v.parse::<f32>().ok()).filter(|v| v.is_finite())
+        .ok_or_else(|| format!("image without {}", name));
+    let (left, top, right, bottom) = (coord("left")?, coord("top")?, coord("right")?, coord("bottom")?);
+    if right <= left || bottom <= top {
+        return Err("image has no area".to_string());
+    }
+    let png = canvas_image::base64_decode(node.text().unwrap_or(""))?;
+    canvas_image::decode_png(&png)?;
+    Ok(CanvasImage { id, rect: [left + origin.x, top + origin.y, right - left, bottom - top], png })
+}
+
+// xournal++ doesn't wrap text, so the box is made as wide as its longest line
+fn text_of(node: roxmltree::Node, origin: Vec2, id: u32) -> Result<TextBox, String> {
+    let coord = |name: &str| node.attribute(name).and_then(|v| v.parse::<f32>().ok()).filter(|v| v.is_finite())
+        .ok_or_else(|| format!("text without {}", name));
+    let (x, y) = (coord("x")?, coord("y")?);
+    let size = coord("size").ok().filter(|s| *s > 0.0).unwrap_or(text_box::DEFAULT_FONT_SIZE);
+    let color = node.attribute("color").and_then(parse_color).unwrap_or([0.0, 0.0, 0.0, 1.0]);
+    let mut text = TextBox::new(id, origin + vec2(x, y), size, color);
+    text.text = node.text().unwrap_or("").to_string();
+    let widest = text.text.lines().map(|l| text_box::text_width(l, size)).fold(0.0, f32::max);
+    text.wrap_width = (widest + 1.0).max(text_box::MIN_WRAP_WIDTH);
+    Ok(text)
+}
+
+fn stroke_of(node: roxmltree::Node, origin: Vec2) -> Result<StrokeData, String> {
+    let coords = numbers(node.text().unwrap_or(""))?;
+    if coords.len() < 4 || coords.len() % 2 != 0 {
+        return Err("stroke needs at least two points".to_string());
+    }
+    let widths = numbers(node.attribute("width").unwrap_or("1"))?;
+    let nominal = widths.first().copied().unwrap_or(1.0);
+    let pressure = &widths[widths.len().min(1)..];
+
+    let tool = match node.attribute("tool") {
+        Some("highlighter") => ToolKind::Highlighter,
+        _ => ToolKind::Pen,
+    };
+    let mut style = StrokeStyle::for_tool(tool);
+    style.width = 1.0;
+    if let Some(color) = node.attribute("color").and_then(parse_color) {
+        style.color = color;
+    }
+    style.cap = match node.attribute("capStyle") {
+        Some("butt") => CapStyle::Butt,
+        Some("square") => CapStyle::Square,
+        Some("round") => CapStyle::Round,
+        _ => style.cap,
+    };
+
+    let points = coords.chunks_exact(2).enumerate()
+        .map(|(i, xy)| {
+            // the last point has no width of its own
+            let width = pressure.get(i).or(pressure.last()).copied().unwrap_or(nominal);
+            ([xy[0] + origin.x, xy[1] + origin.y], width * 0.5)
+        })
+        .collect();
+    Ok(StrokeData { points, style, samples: Vec::new(), created: None })
+}
+
+pub fn canvas_from_xopp(bytes: &[u8]) -> Result<(CanvasData, LoadReport), PersistenceError> {
+    let mut text = Vec::new();
+    if bytes.starts_with(&[0x1f, 0x8b]) {
+        GzDecoder::new(bytes).read_to_end(&mut text)?;
+    } else {
+        text.extend_from_slice(bytes);
+    }
+    let text = String::from_utf8(text).map_err(|e| PersistenceError::Parse(e.to_string()))?;
+    let doc = roxmltree::Document::parse(&text).map_err(|e| PersistenceError::Parse(e.to_string()))?;
+    let root = doc.root_element();
+    if !root.has_tag_name("xournal") {
+        return Err(PersistenceError::Parse("not a Xournal++ document".to_string()));
+    }
+
+    let mut report = LoadReport::default();
+    let mut pages = Vec::new();
+    let mut layers: Vec<LayerData> = Vec::new();
+    let mut images = Vec::new();
+    let mut texts = Vec::new();
+    let mut index = 0;
+    let mut top = 0.0;
+
+    for page_node in root.children().filter(|n| n.has_tag_name("page")) {
+        let size = |name: &str| page_node.attribute(name).and_then(|v| v.parse::<f32>().ok()).filter(|v| *v > 0.0);
+        let (w, h) = (size("width").unwrap_or(page::A4_SIZE[0]), size("height").unwrap_or(page::A4_SIZE[1]));
+        let background = background_of(page_node.children().find(|n| n.has_tag_name("background")), &mut report);
+        let (size, orientation) = if w > h { ([h, w], Orientation::Landscape) } else { ([w, h], Orientation::Portrait) };
+        let page = Page { position: [0.0, top], size, orientation, background };
+        top += page.extent().y + page::PAGE_GAP;
+        let origin = page.top_left();
+        pages.push(page);
+
+        for (i, layer_node) in page_node.children().filter(|n| n.has_tag_name("layer")).enumerate() {
+            if layers.len() <= i {
+                let name = layer_node.attribute("name").map_or_else(|| format!("Layer {}", i + 1), str::to_string);
+                layers.push(LayerData { id: i as u32, props: LayerProps::named(name), strokes: Vec::new() });
+            }
+            for element in layer_node.children().filter(|n| n.is_element()) {
+                match element.tag_name().name() {
+                    "stroke" => {
+                        match stroke_of(element, origin) {
+                            Ok(sd) => layers[i].strokes.push(sd),
+                            Err(reason) => report.skip(index, reason),
+                        }
+                        index += 1;
+                    }
+                    "image" => {
+                        match image_of(element, origin, images.len() as u32) {
+                            Ok(image) => images.push(image),
+                            Err(reason) => report.skip(index, reason),
+                        }
+                        index += 1;
+                    }
+                    "text" => {
+                        match text_of(element, origin, texts.len() as u32) {
+                            Ok(text) => texts.push(text),
+                            Err(reason) => report.skip(index, reason),
+                        }
+                        index += 1;
+                    }
+                    "teximage" => report.unsupported("teximage"),
+                    other => report.unsupported(other),
+                }
+            }
+        }
+    }
+    if layers.is_empty() {
+        layers.push(LayerData { id: 0, props: LayerProps::named("Layer 1"), strokes: Vec::new() });
+    }
+
+    let data = CanvasData {
+        format_version: FORMAT_VERSION,
+        meta: DocumentMeta::default(),
+        layers,
+        active_layer: 0,
+        pages: if pages.is_empty() { PageLayout::Infinite } else { PageLayout::Pages(pages) },
+        images,
+        texts,
+        pdf: None,
+        tool_mode: ToolMode::Pen,
+        offset: [0.0, 0.0],
+        zoom: 1.0,
+    };
+    Ok((data, report))
+}
+
+fn write_stroke(xml: &mut String, stroke: &Stroke, origin: Vec2) {
+    if stroke.points.len() < 2 {
+        return;
+    }
+    let tool = if stroke.style.tool == ToolKind::Highlighter { "highlighter" } else { "pen" };
+    let cap = match stroke.style.cap {
+        CapStyle::Round => "round",
+        CapStyle::Butt => "butt",
+        CapStyle::Square => "square",
+    };
+    let widths: Vec<f32> = stroke.points.iter().map(|(_, th)| th * 2.0 * stroke.style.width).collect();
+    let nominal = widths.iter().sum::<f32>() / widths.len() as f32;
+
+    let _ = write!(xml, r#"<stroke tool="{}" color="{}" capStyle="{}" width="{:.2}"#, tool, hex_color(stroke.style.color), cap, nominal);
+    for w in &widths[..widths.len() - 1] {
+        let _ = write!(xml, " {:.2}", w);
+    }
+    xml.push_str("\">");
+    for (i, (pos, _)) in stroke.points.iter().enumerate() {
+        let p = *pos - origin;
+        let _ = write!(xml, "{}{:.2} {:.2}", if i == 0 { "" } else { " " }, p.x, p.y);
+    }
+    xml.push_str("</stroke>\n");
+}
+
+// strokes of a layer with the page they go to
+type PlacedStrokes = Vec<(Stroke, Option<usize>)>;
+
+pub fn canvas_to_xopp(data: &CanvasData) -> io::Result<Vec<u8>> {
+    let all = export::visible_strokes(data);
+    let pages = export::export_pages(data, &all);
+    if pages.is_empty() {
+        return Err(io::Error::other("there is nothing to export, the canvas is empty"));
+    }
+
+    // every stroke goes to the page with its center, or the closest one
+    let page_of = |stroke: &Stroke| {
+        page::page_of_stroke(&pages, stroke).or_else(|| {
+            let (min_x, max_x, min_y, max_y) = stroke_bounding_box(&stroke.points);
+            page::page_near(&pages, vec2((min_x + max_x) * 0.5, (min_y + max_y) * 0.5))
+        })
+    };
+    // xournal++ layers have no opacity, it goes into the stroke colors, and
+    // hidden layers are left out
+    let mut layers: Vec<(&str, PlacedStrokes)> = data.layers.iter()
+        .filter(|layer| layer.props.visible)
+        .map(|layer| {
+            let strokes = layer.strokes.iter().cloned().map(Stroke::from).map(|mut s| {
+                s.style.color[3] *= layer.props.opacity;
+                let page = page_of(&s);
+                (s, page)
+            }).collect();
+            (layer.props.name.as_str(), strokes)
+        })
+        .collect();
+    if layers.is_empty() {
+        // images and text still need a layer
+        layers.push(("Layer 1", Vec::new()));
+    }
+
+    // images and text go to the first layer of the page with their center
+    let image_page = |image: &CanvasImage| page::page_near(&pages, image.rect().center());
+    let text_page = |text: &TextBox| page::page_near(&pages, text.rect().center());
+
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" standalone=\"no\"?>\n");
+    xml.push_str("<xournal creator=\"lignum_draw\" fileversion=\"4\">\n<title>Xournal++ document</title>\n");
+    for (i, page) in pages.iter().enumerate() {
+        let extent = page.extent();
+        let _ = writeln!(xml, r#"<page width="{:.2}" height="{:.2}">"#, extent.x, extent.y);
+        let style = match page.background.pattern {
+            Pattern::Blank => "plain",
+            Pattern::Ruled { .. } => "lined",
+            Pattern::Grid { .. } => "graph",
+            Pattern::Dots { .. } => "dotted",
+        };
+        let _ = writeln!(xml, r#"<background type="solid" color="{}" style="{}"/>"#, hex_color(page.background.color), style);
+        for (l, (name, strokes)) in layers.iter().enumerate() {
+            let name = name.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;");
+            let _ = writeln!(xml, r#"<layer name="{}">"#, name);
+            for image in data.images.iter().filter(|image| l == 0 && image_page(image) == Some(i)) {
+                let r = image.rect().offset(-page.top_left());
+                let _ = writeln!(
+                    xml,
+                    r#"<image left="{:.2}" top="{:.2}" right="{:.2}" bottom="{:.2}">{}</image>"#,
+                    r.x, r.y, r.x + r.w, r.y + r.h, canvas_image::base64_encode(&image.png)
+                );
+            }
+            for text in data.texts.iter().filter(|text| l == 0 && text_page(text) == Some(i)) {
+                // our line breaks, since xournal++ only breaks at newlines
+                let lines: Vec<String> = text.layout().into_iter().map(|line| line.text.trim_end().to_string()).collect();
+                let content = lines.join("\n").replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+                let _ = writeln!(
+                    xml,
+                    r#"<text font="{}" size="{:.2}" x="{:.2}" y="{:.2}" color="{}">{}</text>"#,
+                    text_box::FONT_NAME, text.font_size, text.pos[0] - page.top_left().x, text.pos[1] - page.top_left().y,
+                    hex_color(text.color), content
+                );
+            }
+            for (stroke, _) in strokes.iter().filter(|(_, p)| *p == Some(i)) {
+                write_stroke(&mut xml, stroke, page.top_left());
+            }
+            xml.push_str("</layer>\n");
+        }
+        xml.push_str("</page>\n");
+    }
+    xml.push_str("</xournal>\n");
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(xml.as_bytes())?;
+    encoder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stroke(points: &[([f32; 2], f32)], color: [f32; 4]) -> StrokeData {
+        let style = StrokeStyle { color, ..StrokeStyle::for_tool(ToolKind::Pen) };
+        StrokeData { points: points.to_vec(), style, samples: Vec::new(), created: None }
+    }
+
+    fn layer(id: u32, name: &str, strokes: Vec<StrokeData>) -> LayerData {
+        LayerData { id, props: LayerProps::named(name), strokes }
+    }
+
+    fn document() -> CanvasData {
+        let ruled = Background { color: [1.0, 1.0, 0.8, 1.0], pattern: Pattern::Ruled { spacing: RULED_SPACING } };
+        let pages = vec![
+            Page { background: ruled, ..Page::a4(vec2(0.0, 0.0)) },
+            Page::a4(vec2(0.0, 842.0 + page::PAGE_GAP)),
+        ];
+        // the last point has no width of its own in xournal++
+        let pressure = stroke(&[([10.0, 20.0], 0.5), ([30.5, 20.25], 1.25), ([50.0, 40.0], 0.75), ([60.0, 45.0], 0.75)], [1.0, 0.0, 0.0, 1.0]);
+        let second_page = stroke(&[([100.0, 1000.0], 1.0), ([200.0, 1000.0], 1.0)], [0.0, 0.2, 1.0, 1.0]);
+        let notes = stroke(&[([300.0, 300.0], 1.5), ([320.0, 310.0], 1.5)], [0.0, 0.6, 0.0, 1.0]);
+        CanvasData {
+            format_version: FORMAT_VERSION,
+            meta: DocumentMeta::default(),
+            layers: vec![layer(0, "Ink", vec![pressure, second_page]), layer(1, "Notes & more", vec![notes])],
+            active_layer: 0,
+            pages: PageLayout::Pages(pages),
+            images: Vec::new(),
+            texts: Vec::new(),
+            pdf: None,
+            tool_mode: ToolMode::Pen,
+            offset: [0.0, 0.0],
+            zoom: 1.0,
+        }
+    }
+
+    fn assert_strokes_match(loaded: &[StrokeData], expected: &[StrokeData]) {
+        assert_eq!(loaded.len(), expected.len());
+        for (l, e) in loaded.iter().zip(expected) {
+            assert_eq!(l.points.len(), e.points.len());
+            for ((lp, lw), (ep, ew)) in l.points.iter().zip(&e.points) {
+                assert!((lp[0] - ep[0]).abs() < 0.01 && (lp[1] - ep[1]).abs() < 0.01, "{:?} != {:?}", lp, ep);
+                assert!((lw - ew).abs() < 0.01, "width {} != {}", lw, ew);
+            }
+            for (lc, ec) in l.style.color.iter().zip(e.style.color) {
+                assert!((lc - ec).abs() <= 1.0 / 255.0, "{:?} != {:?}", l.style.color, e.style.color);
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = document();
+        let (loaded, report) = canvas_from_xopp(&canvas_to_xopp(&data).unwrap()).unwrap();
+        assert!(report.is_clean(), "{}", report);
+
+        let PageLayout::Pages(pages) = &loaded.pages else { panic!("expected pages") };
+        let PageLayout::Pages(expected) = &data.pages else { unreachable!() };
+        assert_eq!(pages, expected);
+
+        let names: Vec<&str> = loaded.layers.iter().map(|l| l.props.name.as_str()).collect();
+        assert_eq!(names, ["Ink", "Notes & more"]);
+        assert_strokes_match(&loaded.layers[0].strokes, &data.layers[0].strokes);
+        assert_strokes_match(&loaded.layers[1].strokes, &data.layers[1].strokes);
+    }
+
+    #[test]
+    fn hidden_layers_are_left_out_and_opacity_kept() {
+        let mut data = document();
+        data.layers[0].props.visible = false;
+        data.layers[1].props.opacity = 0.5;
+        let (loaded, _) = canvas_from_xopp(&canvas_to_xopp(&data).unwrap()).unwrap();
+
+        assert_eq!(loaded.layers.len(), 1);
+        assert_eq!(loaded.layers[0].props.name, "Notes & more");
+        let mut expected = data.layers[1].strokes.clone();
+        expected[0].style.color[3] = 0.5;
+        assert_strokes_match(&loaded.layers[0].strokes, &expected);
+    }
+}